@@ -0,0 +1,491 @@
+//! This module reads the entries of archive files, so that they can be browsed
+//! without downloading the whole archive.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+use flate2::read::GzDecoder;
+use zip::{result::ZipError, ZipArchive};
+
+use crate::errors;
+
+/// A recognized archive format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl Format {
+    /// Recognize the archive format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// A regular file within an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The path of the entry within the archive.
+    pub name: String,
+    /// The uncompressed size, in bytes.
+    pub size: u64,
+}
+
+/// Open the tar archive at `path`, decompressing it if necessary.
+fn open_tar(
+    path: &Path,
+    format: Format,
+) -> errors::Result<tar::Archive<Box<dyn Read>>> {
+    let file = BufReader::new(File::open(path)?);
+    let reader: Box<dyn Read> = match format {
+        Format::TarGz => Box::new(GzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// List the regular files in the archive at `path`, in archive order.
+pub fn list(path: &Path, format: Format) -> errors::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    if format == Format::Zip {
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+        for i in 0..zip.len() {
+            let file = zip.by_index(i)?;
+            if file.is_file() {
+                entries.push(Entry {
+                    name: file.name().to_owned(),
+                    size: file.size(),
+                });
+            }
+        }
+    } else {
+        for entry in open_tar(path, format)?.entries()? {
+            let entry = entry?;
+            if entry.header().entry_type().is_file() {
+                let name = entry.path()?.to_string_lossy().into_owned();
+                entries.push(Entry { name, size: entry.size() });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Call `f` with a reader of the regular file `name` in the archive at
+/// `path`.  Return [`None`] when there is no such entry.
+pub fn with_entry<T>(
+    path: &Path,
+    format: Format,
+    name: &str,
+    f: impl FnOnce(&mut dyn Read) -> T,
+) -> errors::Result<Option<T>> {
+    if format == Format::Zip {
+        let mut zip = ZipArchive::new(File::open(path)?)?;
+        let result = match zip.by_name(name) {
+            Ok(mut file) if file.is_file() => Some(f(&mut file)),
+            Ok(_) | Err(ZipError::FileNotFound) => None,
+            Err(e) => Err(e)?,
+        };
+        return Ok(result);
+    }
+
+    for entry in open_tar(path, format)?.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file()
+            && entry.path()?.to_string_lossy() == name
+        {
+            return Ok(Some(f(&mut entry)));
+        }
+    }
+    Ok(None)
+}
+
+/// A zip archive writer for outputs that cannot seek, such as response
+/// bodies.  Entries are stored uncompressed, and their checksums and sizes
+/// follow their data in data descriptors, so nothing is buffered.
+///
+/// Zip64 fields are written only where sizes, offsets, or the number of
+/// entries do not fit the original format, so small archives open anywhere.
+pub struct ZipStream<W: Write> {
+    inner: W,
+    /// The number of bytes written so far.
+    offset: u64,
+    /// The central directory, written by [`ZipStream::finish`].
+    central: Vec<u8>,
+    entries: u64,
+    /// The smallest size or offset that needs Zip64 fields.
+    limit: u64,
+}
+
+/// Bit 3 marks the data descriptor, and bit 11 UTF-8 names.
+const ZIP_FLAGS: u16 = 1 << 3 | 1 << 11;
+/// Version 2.0, the minimum for data descriptors.
+const ZIP_VERSION: u16 = 20;
+/// Version 4.5, the minimum for Zip64.
+const ZIP64_VERSION: u16 = 45;
+/// The header ID of the Zip64 extra field.
+const ZIP64_EXTRA: u16 = 1;
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "too large for a zip archive")
+}
+
+/// The MS-DOS date and time of `time`, in UTC.
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let time = time::OffsetDateTime::from(time);
+    if time.year() < 1980 {
+        // the earliest representable time
+        return (0x21, 0);
+    }
+    let date = ((time.year() - 1980).min(127) as u16) << 9
+        | (time.month() as u16) << 5
+        | time.day() as u16;
+    let clock = (time.hour() as u16) << 11
+        | (time.minute() as u16) << 5
+        | (time.second() as u16 / 2);
+    (date, clock)
+}
+
+impl<W: Write> ZipStream<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_limit(inner, u32::MAX.into())
+    }
+
+    /// Use Zip64 fields from `limit` on, instead of where the original
+    /// format overflows, so that they can be tested with small archives.
+    fn with_limit(inner: W, limit: u64) -> Self {
+        Self { inner, offset: 0, central: Vec::new(), entries: 0, limit }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// `value` as a field of the original format, or the placeholder for
+    /// the Zip64 field appended to `extra`.
+    fn field(&self, value: u64, extra: &mut Vec<u8>) -> u32 {
+        if value < self.limit {
+            value as u32
+        } else {
+            extra.extend(value.to_le_bytes());
+            u32::MAX
+        }
+    }
+
+    /// Append a stored entry `name`, modified at `modified`, with the `size`
+    /// bytes of `reader`.  The size decides the layout of the entry before
+    /// it is read, so reading a different size is an error.
+    pub fn append(
+        &mut self,
+        name: &str,
+        modified: SystemTime,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> io::Result<()> {
+        let header_offset = self.offset;
+        let name_len = u16::try_from(name.len()).map_err(|_| too_large())?;
+        let zip64 = size >= self.limit;
+        let (date, clock) = dos_time(modified);
+
+        let mut header = Vec::with_capacity(50 + name.len());
+        header.extend(0x04034b50u32.to_le_bytes());
+        match zip64 {
+            true => header.extend(ZIP64_VERSION.to_le_bytes()),
+            false => header.extend(ZIP_VERSION.to_le_bytes()),
+        }
+        header.extend(ZIP_FLAGS.to_le_bytes());
+        // stored, without compression
+        header.extend(0u16.to_le_bytes());
+        header.extend(clock.to_le_bytes());
+        header.extend(date.to_le_bytes());
+        // the checksum and sizes are in the data descriptor, which has
+        // 64-bit sizes when the Zip64 field is present
+        header.extend([0; 4]);
+        match zip64 {
+            true => header.extend([0xff; 8]),
+            false => header.extend([0; 8]),
+        }
+        header.extend(name_len.to_le_bytes());
+        header.extend((if zip64 { 20u16 } else { 0 }).to_le_bytes());
+        header.extend(name.as_bytes());
+        if zip64 {
+            header.extend(ZIP64_EXTRA.to_le_bytes());
+            header.extend(16u16.to_le_bytes());
+            header.extend([0; 16]);
+        }
+        self.write(&header)?;
+
+        let mut hasher = crc32fast::Hasher::new();
+        let mut read = 0u64;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            hasher.update(&buf[..n]);
+            self.write(&buf[..n])?;
+            read += n as u64;
+        }
+        if read != size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} changed size while being archived", name),
+            ));
+        }
+        let crc = hasher.finalize();
+
+        let mut descriptor = Vec::with_capacity(24);
+        descriptor.extend(0x08074b50u32.to_le_bytes());
+        descriptor.extend(crc.to_le_bytes());
+        for _ in 0..2 {
+            match zip64 {
+                true => descriptor.extend(size.to_le_bytes()),
+                false => descriptor.extend((size as u32).to_le_bytes()),
+            }
+        }
+        self.write(&descriptor)?;
+
+        let mut extra = Vec::new();
+        let stored_size = self.field(size, &mut extra);
+        self.field(size, &mut extra);
+        let stored_offset = self.field(header_offset, &mut extra);
+        let version = match extra.is_empty() {
+            true => ZIP_VERSION,
+            false => ZIP64_VERSION,
+        };
+        let extra_len = match extra.len() {
+            0 => 0,
+            len => 4 + len as u16,
+        };
+
+        let central = &mut self.central;
+        central.extend(0x02014b50u32.to_le_bytes());
+        // made by and needed
+        central.extend(version.to_le_bytes());
+        central.extend(version.to_le_bytes());
+        central.extend(ZIP_FLAGS.to_le_bytes());
+        central.extend(0u16.to_le_bytes());
+        central.extend(clock.to_le_bytes());
+        central.extend(date.to_le_bytes());
+        central.extend(crc.to_le_bytes());
+        central.extend(stored_size.to_le_bytes());
+        central.extend(stored_size.to_le_bytes());
+        central.extend(name_len.to_le_bytes());
+        central.extend(extra_len.to_le_bytes());
+        // comment, disk number, and attributes
+        central.extend([0; 10]);
+        central.extend(stored_offset.to_le_bytes());
+        central.extend(name.as_bytes());
+        if !extra.is_empty() {
+            central.extend(ZIP64_EXTRA.to_le_bytes());
+            central.extend((extra.len() as u16).to_le_bytes());
+            central.extend(extra);
+        }
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Write the central directory, and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let offset = self.offset;
+        let size = self.central.len() as u64;
+        let central = std::mem::take(&mut self.central);
+        self.write(&central)?;
+
+        let zip64 = self.entries >= self.limit.min(u16::MAX.into())
+            || offset >= self.limit
+            || size >= self.limit;
+        if zip64 {
+            let record_offset = self.offset;
+            let mut record = Vec::with_capacity(76);
+            record.extend(0x06064b50u32.to_le_bytes());
+            // the size of the rest of the record
+            record.extend(44u64.to_le_bytes());
+            record.extend(ZIP64_VERSION.to_le_bytes());
+            record.extend(ZIP64_VERSION.to_le_bytes());
+            // disk numbers
+            record.extend([0; 8]);
+            record.extend(self.entries.to_le_bytes());
+            record.extend(self.entries.to_le_bytes());
+            record.extend(size.to_le_bytes());
+            record.extend(offset.to_le_bytes());
+
+            // the locator of the record, on the only disk
+            record.extend(0x07064b50u32.to_le_bytes());
+            record.extend(0u32.to_le_bytes());
+            record.extend(record_offset.to_le_bytes());
+            record.extend(1u32.to_le_bytes());
+            self.write(&record)?;
+        }
+
+        let mut end = Vec::with_capacity(22);
+        end.extend(0x06054b50u32.to_le_bytes());
+        // disk numbers
+        end.extend([0; 4]);
+        // the Zip64 record holds the actual values
+        match zip64 {
+            true => end.extend([0xff; 12]),
+            false => {
+                let entries = self.entries as u16;
+                end.extend(entries.to_le_bytes());
+                end.extend(entries.to_le_bytes());
+                end.extend((size as u32).to_le_bytes());
+                end.extend((offset as u32).to_le_bytes());
+            }
+        }
+        // comment length
+        end.extend([0; 2]);
+        self.write(&end)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        path::Path,
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::{list, with_entry, Entry, Format, ZipStream};
+
+    #[test]
+    fn test_format() {
+        let format = |name: &str| Format::from_path(Path::new(name));
+        assert_eq!(format("a.tar"), Some(Format::Tar));
+        assert_eq!(format("a.TAR.GZ"), Some(Format::TarGz));
+        assert_eq!(format("a.tgz"), Some(Format::TarGz));
+        assert_eq!(format("a.zip"), Some(Format::Zip));
+        assert_eq!(format("a.txt"), None);
+    }
+
+    #[test]
+    fn test_archives() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let tgz = dir.path().join("a.tar.gz");
+        let gz = GzEncoder::new(
+            std::fs::File::create(&tgz).unwrap(),
+            Compression::default(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        tar.append_data(&mut header, "dir/a.txt", &b"hello"[..])
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let zip = dir.path().join("a.zip");
+        let mut writer = ZipWriter::new(std::fs::File::create(&zip).unwrap());
+        writer
+            .add_directory("dir/", FileOptions::default())
+            .unwrap();
+        writer
+            .start_file("dir/a.txt", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.finish().unwrap();
+
+        for (path, format) in [(tgz, Format::TarGz), (zip, Format::Zip)] {
+            let entries = list(&path, format).unwrap();
+            assert_eq!(
+                entries,
+                [Entry { name: "dir/a.txt".to_owned(), size: 5 }]
+            );
+
+            let read = |name| {
+                with_entry(&path, format, name, |r| {
+                    let mut s = String::new();
+                    r.read_to_string(&mut s).map(|_| s).unwrap()
+                })
+                .unwrap()
+            };
+            assert_eq!(read("dir/a.txt").as_deref(), Some("hello"));
+            assert_eq!(read("dir/b.txt"), None);
+        }
+    }
+
+    #[test]
+    fn test_zip_stream() {
+        let mut zip = ZipStream::new(Vec::new());
+        let modified = std::time::UNIX_EPOCH
+            + std::time::Duration::from_secs(1_000_000_000);
+        zip.append("a.txt", modified, 5, &mut &b"hello"[..])
+            .unwrap();
+        zip.append("dir/ä.txt", modified, 0, &mut &b""[..]).unwrap();
+        let bytes = zip.finish().unwrap();
+
+        let mut zip =
+            zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(zip.len(), 2);
+        let mut file = zip.by_name("a.txt").unwrap();
+        let mut s = String::new();
+        file.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello");
+        // 2001-09-09T01:46:40Z
+        let time = file.last_modified();
+        assert_eq!(
+            (time.year(), time.month(), time.day(), time.hour(), time.minute()),
+            (2001, 9, 9, 1, 46)
+        );
+        drop(file);
+        assert_eq!(zip.by_name("dir/ä.txt").unwrap().size(), 0);
+
+        let mut zip = ZipStream::new(Vec::new());
+        let err = zip.append("a.txt", modified, 4, &mut &b"hello"[..]);
+        assert_eq!(err.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    /// Sizes, offsets, and entry counts beyond the limit are in Zip64 fields.
+    #[test]
+    fn test_zip64_stream() {
+        let mut zip = ZipStream::with_limit(Vec::new(), 4);
+        let modified = std::time::UNIX_EPOCH;
+        let names = ["a", "b", "c", "d", "e"];
+        for name in names {
+            let content = name.repeat(3);
+            zip.append(name, modified, 3, &mut content.as_bytes())
+                .unwrap();
+        }
+        zip.append("long", modified, 6, &mut &b"longer"[..])
+            .unwrap();
+        let bytes = zip.finish().unwrap();
+
+        let mut zip =
+            zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(zip.len(), 6);
+        for name in names.into_iter().chain(["long"]) {
+            let mut file = zip.by_name(name).unwrap();
+            let mut s = String::new();
+            file.read_to_string(&mut s).unwrap();
+            match name {
+                "long" => assert_eq!(s, "longer"),
+                name => assert_eq!(s, name.repeat(3)),
+            }
+        }
+    }
+}
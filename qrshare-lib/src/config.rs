@@ -4,52 +4,795 @@
 use std::{
     fmt::{self, Display, Formatter},
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
 use either::Either;
 
 use crate::{
     default,
-    net::{get_first_net, is_global_4},
+    errors::{self, Error},
+    net::{
+        get_first_net, get_global_net, get_interface_nets, is_global_4,
+        is_global_6,
+    },
     unwrap_getter,
 };
 
 /// The configuration structure.  Should be able to be extracted from one or
 /// more configuration files.
-#[derive(Debug, Clone, clap::Args, serde::Deserialize, merge::Merge)]
+#[derive(
+    Debug, Clone, clap::Args, serde::Deserialize, serde::Serialize, merge::Merge,
+)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub struct Config {
     /// Image options.  Use PNG format or SVG format to produce the QR code, or
     /// skip producing the QR code at all.
     #[clap(short = 'I', long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub image: Option<ImageOptions>,
 
     /// Quiet operation.  Do not warn about missing files.
     #[clap(short, long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub quiet: Option<bool>,
 
     /// Strict mode.  When enabled, the server exits on any failure in path
     /// resolution and IO.
     #[clap(short, long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub strict: Option<bool>,
 
+    /// What to do when two different paths have the same content: skip the
+    /// later path, exit with an error, or keep both paths.
+    #[clap(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_duplicate: Option<DuplicateOptions>,
+
+    /// When an already-served path is enqueued again with different content,
+    /// stop serving its old digest, instead of serving both digests.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replace_on_reenqueue: Option<bool>,
+
+    /// Serve every regular file under directories, instead of the directories
+    /// themselves.  Symbolic links are followed.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recursive: Option<bool>,
+
+    /// With `--recursive`, only descend this many levels into directories,
+    /// where files directly in a directory are at depth 1.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+
+    /// With `--recursive`, also serve files and directories whose names
+    /// start with `.`.  Hidden files given directly are always served.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_hidden: Option<bool>,
+
+    /// With `--recursive`, skip files and directories whose names or
+    /// relative paths match this glob pattern, e.g. `'*.log'`.  May be
+    /// repeated.
+    #[clap(long, value_parser)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[merge(strategy = merge::vec::overwrite_empty)]
+    pub exclude: Vec<String>,
+
+    /// Only serve files with these extensions, compared case-insensitively,
+    /// e.g. `pdf,png`.  Directories are not checked.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[merge(strategy = merge::vec::overwrite_empty)]
+    pub allow_ext: Vec<String>,
+
+    /// Never serve files with these extensions, compared case-insensitively,
+    /// e.g. `exe,sh`.  Files failing either list are skipped with a warning,
+    /// or are an error at startup in strict mode.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[merge(strategy = merge::vec::overwrite_empty)]
+    pub deny_ext: Vec<String>,
+
+    /// Expand file arguments as glob patterns, e.g. `'*.pdf'`.  Patterns
+    /// without matches are taken literally.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub glob: Option<bool>,
+
+    /// Serve a small QR code of the file listing as the favicon, instead of
+    /// the static favicon file.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favicon_qr: Option<bool>,
+
+    /// The static favicon file, such as an ICO or PNG image.  Default to the
+    /// bundled icon.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<PathBuf>,
+
+    /// Adds a `Strict-Transport-Security` header with this `max-age`, in
+    /// seconds, to responses served over HTTPS.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hsts: Option<u64>,
+
+    /// Write the encoded URL beneath generated QR codes, so that it can be
+    /// typed manually when scanning fails.
+    #[clap(long, value_parser, alias = "qr-show-url")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_caption_url: Option<bool>,
+
+    /// The size of each QR code module, in pixels, e.g. to print large codes.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_scale: Option<u32>,
+
+    /// Surround QR codes with a quiet zone of four modules, which some
+    /// scanners require.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_quiet_zone: Option<bool>,
+
+    /// The color of dark QR code modules, as `#RRGGBB`, by default black.
+    /// Only SVG QR codes are colored.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_dark: Option<HexColor>,
+
+    /// The color of light QR code modules, as `#RRGGBB`, by default white.
+    /// Only SVG QR codes are colored.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_light: Option<HexColor>,
+
+    /// Embed the QR code of each file in the listing page, instead of linking
+    /// to it.  This makes the page heavier.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline_qr: Option<bool>,
+
+    /// When rendering a PNG QR code fails, log a warning and render SVG
+    /// instead of failing.  Default to true.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_fallback: Option<bool>,
+
+    /// Choose the format of QR codes served over HTTP from the `Accept`
+    /// header, preferring PNG or SVG as requested, and falling back to
+    /// `image`.  An explicit `fmt` query parameter still takes precedence.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_format_from_accept: Option<bool>,
+
+    /// When a single directory is served, serve this file within it, such as
+    /// `index.html`, as the file listing instead of the generated table.  The
+    /// generated table is used when the file does not exist.
+    #[clap(long, value_parser, alias = "serve-index-file")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index_file: Option<PathBuf>,
+
+    /// The theme of the generated file listing: `responsive` for a styled
+    /// table fitting small screens, or `plain` for preformatted text in the
+    /// style of nginx autoindex.  Default to `responsive`.
+    #[clap(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<ListTheme>,
+
+    /// A CSS stylesheet added to the generated file listing, after the style
+    /// of the theme.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub css: Option<PathBuf>,
+
+    /// The title of the generated file listing.  Default to `QR Share:
+    /// Files`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list_title: Option<String>,
+
+    /// Serve an RSS feed of the served files at `/feed.xml`, so that feed
+    /// readers can notice newly enqueued files.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feed: Option<bool>,
+
+    /// Once the server is bound, save a PNG QR code of the file listing to
+    /// this path, overwriting any existing file.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub startup_qr_png: Option<PathBuf>,
+
+    /// On graceful shutdown, write a JSON manifest of the served files to this
+    /// path, with their digests, paths, sizes, and download counts.
+    #[clap(long, value_parser, alias = "persist-digests")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<PathBuf>,
+
+    /// Keep file digests in this JSON file across restarts, so that files
+    /// whose size and modification time are unchanged are not hashed again.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<PathBuf>,
+
+    /// Accept files uploaded to `/upload` into this directory, and serve
+    /// them.  Uploads are subject to the same restrictions as enqueuing.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_dir: Option<PathBuf>,
+
+    /// Only enqueue files under this directory after startup, through the
+    /// API, uploads or watched directories.  Paths are compared after
+    /// resolving symbolic links, so links cannot escape the directory.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serve_root: Option<PathBuf>,
+
+    /// The download name of the file read from standard input, when `-` is
+    /// given as a path.  Default to `stdin.bin`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stdin_name: Option<String>,
+
+    /// Watch these directories, and serve the files created in them once
+    /// writes have settled.  Deleted files stop being served.  With
+    /// `recursive`, subdirectories are watched too.
+    #[clap(long, value_parser)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[merge(strategy = merge::vec::overwrite_empty)]
+    pub watch: Vec<PathBuf>,
+
+    /// The maximum total size of the files in each upload, in bytes.  Default
+    /// to 100 MiB.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_upload_size: Option<u64>,
+
+    /// The maximum number of paths in each request to enqueue files over
+    /// HTTP.  Larger requests are answered with 413 status.  Default to 1000.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_enqueue_batch: Option<usize>,
+
+    /// Stop serving files this long after they are enqueued, e.g. `30m` or
+    /// `1h`.  Expired files are answered with 410 status.  Files enqueued
+    /// over HTTP may set their own `ttl`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<HumanDuration>,
+
+    /// Stop serving each file after this many successful downloads, including
+    /// those in archives.  Exhausted files are answered with 410 status, and
+    /// left out of `all.zip`.  Files enqueued over HTTP may set their own
+    /// `max_downloads`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+
+    /// Require HTTP Basic authentication with this user name to enqueue,
+    /// upload, or remove files, and to show the configuration.  This allows
+    /// them without the `insecure` feature.  Requires `auth-pass`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_user: Option<String>,
+
+    /// The password for `auth-user`.  Prefer setting it in a configuration
+    /// file, as command-line arguments are visible to other local users.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_pass: Option<String>,
+
+    /// The secret key of signed download URLs at `/signed/...`, which stop
+    /// working once they expire.  The listing only shows them when this is
+    /// set.  Prefer setting it in a configuration file.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+
+    /// How long signed URLs minted for the listing stay valid, e.g. `30m`.
+    /// Default to `1h`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signed_ttl: Option<HumanDuration>,
+
+    /// Also require authentication to download, list, or preview files, with
+    /// `auth-user`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_downloads: Option<bool>,
+
+    /// Limit each client to this many renderings of QR codes and thumbnails
+    /// per minute.  Excess requests are rejected with 429 status.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub qr_rate_limit: Option<u32>,
+
+    /// Limit each client to this many concurrent downloads.  Excess requests
+    /// are rejected with 429 status.
+    #[clap(long, value_parser, alias = "max-concurrent-downloads-per-ip")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_per_ip: Option<u32>,
+
+    /// Limit each client to this many requests per second, on every route.
+    /// Excess requests are rejected with 429 status.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<u32>,
+
+    /// With `rate-limit`, allow bursts of up to this many requests.  Default
+    /// to the value of `rate-limit`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_burst: Option<u32>,
+
+    /// With `rate-limit`, do not limit clients on the loopback address.
+    /// Default to false.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit_exempt_loopback: Option<bool>,
+
+    /// Send each download at no more than this many bytes per second.  The
+    /// cap applies to each connection separately.  Default to 0, which is
+    /// unlimited.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_bps: Option<u64>,
+
+    /// Hash each file again before serving it, and respond with 409 status
+    /// when its content no longer matches the digest, e.g. when a network
+    /// mount is remapped.  This reads every downloaded file twice.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_on_download: Option<bool>,
+
+    /// Always download files as `application/octet-stream` attachments,
+    /// instead of with their detected type, which browsers may preview.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force_download: Option<bool>,
+
+    /// The hash algorithm of file digests, which also names the download
+    /// route, e.g. `/sha512/?h=...`.  Default to `sha512`.
+    #[clap(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashMethod>,
+
+    /// The encoding of file digests in download URLs: `base64url` is the
+    /// shortest, and `base58` leaves out ambiguous characters for manual
+    /// entry.  `ETag` headers in `hash-algorithm-for-etag` stay in hex.
+    /// Default to `hex`.
+    #[clap(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_encoding: Option<DigestEncoding>,
+
+    /// The algorithm of the file digest in the `ETag` header of downloads,
+    /// independently of the digest in download URLs.  Any algorithm other
+    /// than `--hash` hashes every file a second time, adding to the time
+    /// needed before files are served.  Default to the digest in download
+    /// URLs.
+    #[clap(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_algorithm_for_etag: Option<EtagAlgorithm>,
+
+    /// The size of each read from files when hashing them or streaming
+    /// archives, in bytes.  Larger buffers need fewer reads.  Default to 64
+    /// KiB.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buf_size: Option<usize>,
+
+    /// The scheme of generated URLs, e.g. `https` behind a reverse proxy that
+    /// terminates TLS.  Default to `https` when TLS is configured, and `http`
+    /// otherwise.
+    #[clap(long, value_enum)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<UrlScheme>,
+
+    /// The host name or address of generated URLs, as reachable by clients,
+    /// e.g. behind a reverse proxy or a NAT with port forwarding.  This does
+    /// not change the bound addresses.  Default to the primary bound host.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_host: Option<String>,
+
+    /// The port of generated URLs, as reachable by clients.  Default to the
+    /// bound port.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_port: Option<u16>,
+
+    /// Advertise the server over mDNS as `<mdns-name>.local`, and use that
+    /// host name in generated URLs unless `external-host` is set.  Only global
+    /// IPv4 addresses are advertised; when none is bound or the advertisement
+    /// fails, generated URLs keep the primary bound host.  Default to false.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mdns: Option<bool>,
+
+    /// The mDNS instance and host name, without the `.local` suffix.  Set a
+    /// distinct name for each server on the same network.  Default to
+    /// `qrshare`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mdns_name: Option<String>,
+
+    /// Serve download, listing, QR code, hashing and response counters in
+    /// the Prometheus text format at `/metrics`.  Default to false.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<bool>,
+
+    /// Allow cross-origin requests from this origin, e.g.
+    /// `https://example.com`, or from any origin with `*`, to the JSON
+    /// listing, enqueuing, upload, removal, configuration and health routes.
+    /// Downloads and HTML pages stay same-origin.
+    #[clap(long, value_parser)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[merge(strategy = merge::vec::overwrite_empty)]
+    pub cors_origin: Vec<String>,
+
+    /// Serve all routes under this path prefix, e.g. `/share` behind a
+    /// reverse proxy, and include it in generated URLs.  Default to serving
+    /// at the root.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_path: Option<String>,
+
+    /// The maximum number of seconds to wait for in-progress downloads when
+    /// shutting down.  Default to 30.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drain_timeout: Option<u64>,
+
     /// Bind options, containing the bound host(s) and port.
     #[clap(flatten)]
     #[serde(default)]
     pub bind: BindOptions,
+
+    /// TLS options, containing the certificate and key paths.
+    #[clap(flatten)]
+    #[serde(default, skip_serializing_if = "TlsOptions::is_empty")]
+    pub tls: TlsOptions,
 }
 default!(
     !Config = Self {
         image: None,
         quiet: None,
         strict: None,
-        bind: BindOptions::default()
+        on_duplicate: None,
+        replace_on_reenqueue: None,
+        recursive: None,
+        max_depth: None,
+        include_hidden: None,
+        exclude: Vec::new(),
+        allow_ext: Vec::new(),
+        deny_ext: Vec::new(),
+        glob: None,
+        favicon_qr: None,
+        favicon: None,
+        hsts: None,
+        qr_caption_url: None,
+        qr_scale: None,
+        qr_quiet_zone: None,
+        qr_dark: None,
+        qr_light: None,
+        inline_qr: None,
+        qr_fallback: None,
+        qr_format_from_accept: None,
+        index_file: None,
+        theme: None,
+        css: None,
+        list_title: None,
+        feed: None,
+        startup_qr_png: None,
+        manifest: None,
+        state_file: None,
+        upload_dir: None,
+        serve_root: None,
+        stdin_name: None,
+        watch: Vec::new(),
+        max_upload_size: None,
+        max_enqueue_batch: None,
+        ttl: None,
+        max_downloads: None,
+        auth_user: None,
+        auth_pass: None,
+        secret: None,
+        signed_ttl: None,
+        auth_downloads: None,
+        qr_rate_limit: None,
+        max_per_ip: None,
+        rate_limit: None,
+        rate_burst: None,
+        rate_limit_exempt_loopback: None,
+        max_bps: None,
+        verify_on_download: None,
+        force_download: None,
+        hash: None,
+        digest_encoding: None,
+        hash_algorithm_for_etag: None,
+        buf_size: None,
+        scheme: None,
+        external_host: None,
+        external_port: None,
+        mdns: None,
+        mdns_name: None,
+        metrics: None,
+        cors_origin: Vec::new(),
+        base_path: None,
+        drain_timeout: None,
+        bind: BindOptions::default(),
+        tls: TlsOptions::default()
     }
 );
 unwrap_getter!(Config::image: ImageOptions);
+unwrap_getter!(Config::on_duplicate: DuplicateOptions);
+unwrap_getter!(Config::hash: HashMethod);
+unwrap_getter!(Config::digest_encoding: DigestEncoding);
+unwrap_getter!(Config::replace_on_reenqueue: bool = false);
+unwrap_getter!(Config::recursive: bool = false);
+unwrap_getter!(Config::include_hidden: bool = false);
+unwrap_getter!(Config::glob: bool = false);
+unwrap_getter!(Config::favicon_qr: bool = false);
+unwrap_getter!(Config::qr_caption_url: bool = false);
+unwrap_getter!(Config::qr_quiet_zone: bool = true);
+unwrap_getter!(Config::inline_qr: bool = false);
+unwrap_getter!(Config::qr_fallback: bool = true);
+unwrap_getter!(Config::qr_format_from_accept: bool = false);
+unwrap_getter!(Config::theme: ListTheme);
+unwrap_getter!(Config::feed: bool = false);
+unwrap_getter!(Config::verify_on_download: bool = false);
+unwrap_getter!(Config::force_download: bool = false);
+unwrap_getter!(Config::drain_timeout: u64 = 30);
+unwrap_getter!(
+    Config::signed_ttl: HumanDuration = HumanDuration(Duration::from_secs(60 * 60))
+);
+unwrap_getter!(Config::max_upload_size: u64 = 100 << 20);
+unwrap_getter!(Config::max_enqueue_batch: usize = 1000);
+unwrap_getter!(Config::auth_downloads: bool = false);
+unwrap_getter!(Config::rate_limit_exempt_loopback: bool = false);
+unwrap_getter!(Config::max_bps: u64 = 0);
+unwrap_getter!(Config::buf_size: usize = 64 << 10);
+unwrap_getter!(Config::mdns: bool = false);
+unwrap_getter!(Config::metrics: bool = false);
+
+impl Config {
+    /// The environment variable holding the TOML configuration.
+    pub const ENV_VAR: &'static str = "QRSHARE_CONFIG";
+
+    /// Fill in the default values of all fields that have one, so that the
+    /// result reflects the effective configuration.
+    pub fn resolve(self) -> Self {
+        Self {
+            image: Some(self.image()),
+            on_duplicate: Some(self.on_duplicate()),
+            replace_on_reenqueue: Some(self.replace_on_reenqueue()),
+            recursive: Some(self.recursive()),
+            include_hidden: Some(self.include_hidden()),
+            glob: Some(self.glob()),
+            hash: Some(self.hash()),
+            digest_encoding: Some(self.digest_encoding()),
+            favicon_qr: Some(self.favicon_qr()),
+            qr_caption_url: Some(self.qr_caption_url()),
+            qr_quiet_zone: Some(self.qr_quiet_zone()),
+            inline_qr: Some(self.inline_qr()),
+            qr_fallback: Some(self.qr_fallback()),
+            qr_format_from_accept: Some(self.qr_format_from_accept()),
+            theme: Some(self.theme()),
+            list_title: Some(self.list_title().to_owned()),
+            feed: Some(self.feed()),
+            verify_on_download: Some(self.verify_on_download()),
+            force_download: Some(self.force_download()),
+            drain_timeout: Some(self.drain_timeout()),
+            signed_ttl: self.secret.as_ref().map(|_| self.signed_ttl()),
+            max_upload_size: Some(self.max_upload_size()),
+            max_enqueue_batch: Some(self.max_enqueue_batch()),
+            auth_downloads: Some(self.auth_downloads()),
+            rate_burst: self.rate_limit.map(|_| self.rate_burst()),
+            rate_limit_exempt_loopback: Some(self.rate_limit_exempt_loopback()),
+            max_bps: Some(self.max_bps()),
+            buf_size: Some(self.buf_size()),
+            mdns: Some(self.mdns()),
+            metrics: Some(self.metrics()),
+            bind: BindOptions {
+                hosts: self.bind.hosts_iter().collect(),
+                port: Some(self.bind.port()),
+                backlog: Some(self.bind.backlog()),
+                advertise_host: self.bind.advertise_host,
+                interface: self.bind.interface.clone(),
+                exclude_interface: self
+                    .bind
+                    .excluded_interfaces()
+                    .map(str::to_owned)
+                    .collect(),
+                unix_socket: self.bind.unix_socket.clone(),
+                local: Some(self.bind.local()),
+            },
+            ..self
+        }
+    }
+
+    /// The title of the generated file listing.
+    pub fn list_title(&self) -> &str {
+        self.list_title.as_deref().unwrap_or("QR Share: Files")
+    }
+
+    /// The burst size of `rate-limit`, which defaults to the rate itself.
+    pub fn rate_burst(&self) -> u32 {
+        self.rate_burst.or(self.rate_limit).unwrap_or_default()
+    }
+
+    /// The download name of the file read from standard input.  Anything
+    /// other than a plain file name is rejected.
+    pub fn stdin_name(&self) -> errors::Result<&str> {
+        let name = self.stdin_name.as_deref().unwrap_or("stdin.bin");
+        match Path::new(name).file_name() {
+            Some(file_name) if file_name == name => Ok(name),
+            _ => Err(Error::InvalidArg(format!(
+                "stdin-name is not a file name: {}",
+                name
+            ))),
+        }
+    }
+
+    /// The mDNS instance and host name.  Anything other than a single host
+    /// name label is rejected.
+    pub fn mdns_name(&self) -> errors::Result<&str> {
+        let name = self.mdns_name.as_deref().unwrap_or("qrshare");
+        if !name.contains('.') && is_host_name(name) {
+            Ok(name)
+        } else {
+            Err(Error::InvalidArg(format!(
+                "mdns-name is not a host name label: {}",
+                name
+            )))
+        }
+    }
+
+    /// The host of generated URLs, as set in `external-host`, with IPv6
+    /// addresses bracketed.  Anything other than an IP address or a host name
+    /// is rejected.
+    pub fn normalized_external_host(&self) -> errors::Result<Option<String>> {
+        let host = match &self.external_host {
+            Some(host) => host,
+            None => return Ok(None),
+        };
+        let bare = host.strip_prefix('[').and_then(|h| h.strip_suffix(']'));
+        if let Ok(ip) = bare.unwrap_or(host).parse::<Ipv6Addr>() {
+            Ok(Some(format!("[{}]", ip)))
+        } else if host.parse::<Ipv4Addr>().is_ok() || is_host_name(host) {
+            Ok(Some(host.clone()))
+        } else {
+            Err(Error::InvalidArg(format!(
+                "external-host is neither an IP address nor a host name: {}",
+                host
+            )))
+        }
+    }
+
+    /// The route prefix, with a leading slash and without a trailing one.
+    /// Empty when serving at the root.
+    pub fn normalized_base_path(&self) -> String {
+        let path = self.base_path.as_deref().unwrap_or_default();
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", path)
+        }
+    }
+
+    /// Replace secrets and deployment details, such as the paths to TLS
+    /// certificates and keys, with a placeholder.
+    pub fn redacted(mut self) -> Self {
+        const REDACTED: &str = "<redacted>";
+        for secret in [&mut self.auth_pass, &mut self.secret] {
+            if secret.is_some() {
+                *secret = Some(REDACTED.to_owned());
+            }
+        }
+        for path in [
+            &mut self.tls.tls_cert,
+            &mut self.tls.tls_key,
+            &mut self.tls.tls_client_ca,
+        ] {
+            if path.is_some() {
+                *path = Some(REDACTED.into());
+            }
+        }
+        self
+    }
+
+    /// Serialize the configuration in TOML format.
+    pub fn to_toml(&self) -> errors::Result<String> {
+        toml::to_string(self)
+            .map_err(|e| Error::Config("TOML output".into(), e.to_string()))
+    }
+
+    /// Expand environment variables and `~` in the path-typed fields.  An unset
+    /// variable is an error when `strict`, and otherwise expands to an empty
+    /// string with a warning.
+    pub fn expand_paths(&mut self, strict: bool) -> errors::Result<()> {
+        let paths = [
+            &mut self.index_file,
+            &mut self.css,
+            &mut self.favicon,
+            &mut self.startup_qr_png,
+            &mut self.manifest,
+            &mut self.state_file,
+            &mut self.upload_dir,
+            &mut self.serve_root,
+            &mut self.bind.unix_socket,
+            &mut self.tls.tls_cert,
+            &mut self.tls.tls_key,
+            &mut self.tls.tls_client_ca,
+        ];
+        for path in paths.into_iter().flatten().chain(&mut self.watch) {
+            *path = expand_path(path, strict)?;
+        }
+        Ok(())
+    }
+
+    /// Parse the TOML configuration held in the environment variable `var`.
+    /// An unset variable is treated as an empty configuration.
+    pub fn from_env(var: &str) -> errors::Result<Self> {
+        let source = || format!("environment variable {}", var);
+        match std::env::var(var) {
+            Ok(toml) => toml::from_str(&toml)
+                .map_err(|e| Error::Config(source(), e.to_string())),
+            Err(std::env::VarError::NotPresent) => Ok(Self::default()),
+            Err(e) => Err(Error::Config(source(), e.to_string())),
+        }
+    }
+}
+
+/// Whether `name` is a host name: labels of ASCII letters, digits and
+/// hyphens separated by dots, with an optional trailing dot.  Each label has
+/// 1 to 63 characters, and does not start or end with a hyphen.
+fn is_host_name(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// Expand environment variables and `~` in a path.  See
+/// [`Config::expand_paths`].
+fn expand_path(path: &Path, strict: bool) -> errors::Result<PathBuf> {
+    // only UTF-8 paths can be expanded
+    let path = match path.to_str() {
+        Some(path) => path,
+        None => return Ok(path.to_owned()),
+    };
+
+    let lookup = |var: &str| match std::env::var(var) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if strict => Err(e),
+        Err(_) => {
+            log::warn!("Expanding unset variable {} in {} to empty", var, path);
+            Ok(Some(String::new()))
+        }
+    };
+    shellexpand::env_with_context(&shellexpand::tilde(path), lookup)
+        .map(|path| PathBuf::from(path.as_ref()))
+        .map_err(|e| Error::Config(format!("path {}", path), e.to_string()))
+}
 
 /// Allowed image formats.
-#[derive(Debug, Clone, Copy, serde::Deserialize, clap::ValueEnum)]
+#[derive(
+    Debug, Clone, Copy, serde::Deserialize, serde::Serialize, clap::ValueEnum,
+)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub enum ImageOptions {
     Png,
@@ -65,8 +808,268 @@ impl Display for ImageOptions {
     }
 }
 
+/// Policies for files whose content duplicates an already-served file.
+#[derive(
+    Debug, Clone, Copy, serde::Deserialize, serde::Serialize, clap::ValueEnum,
+)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub enum DuplicateOptions {
+    /// Serve only the first path, and skip the later ones.
+    Skip,
+    /// Fail on the first duplicate.
+    Error,
+    /// Serve the first path, and remember the later ones as aliases.
+    Keep,
+}
+default!(DuplicateOptions = Self::Keep);
+
+/// Hash algorithms for file digests, as in download URLs.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    serde::Deserialize,
+    serde::Serialize,
+    clap::ValueEnum,
+)]
+pub enum HashMethod {
+    Sha256,
+    Sha512,
+    Blake2b,
+    Blake3,
+}
+default!(HashMethod = Self::Sha512);
+
+impl HashMethod {
+    /// The name in download URLs, e.g. `/sha512/?h=...`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Blake2b => "blake2b",
+            Self::Blake3 => "blake3",
+        }
+    }
+}
+
+impl Display for HashMethod {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Encodings of file digests, as in download URLs.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    serde::Deserialize,
+    serde::Serialize,
+    clap::ValueEnum,
+)]
+pub enum DigestEncoding {
+    /// Lowercase hexadecimal.
+    Hex,
+    /// Lowercase base32 of RFC 4648, without padding.
+    Base32,
+    /// Base58 with the Bitcoin alphabet.
+    Base58,
+    /// The URL-safe base64 of RFC 4648, without padding.
+    Base64url,
+}
+default!(DigestEncoding = Self::Hex);
+
+/// Hash algorithms for `ETag` headers.
+#[derive(
+    Debug, Clone, Copy, serde::Deserialize, serde::Serialize, clap::ValueEnum,
+)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub enum EtagAlgorithm {
+    Sha256,
+    /// With the default `--hash`, the same digest as in download URLs, at no
+    /// extra cost.
+    Sha512,
+}
+
+impl EtagAlgorithm {
+    pub const fn method(self) -> HashMethod {
+        match self {
+            Self::Sha256 => HashMethod::Sha256,
+            Self::Sha512 => HashMethod::Sha512,
+        }
+    }
+}
+
+/// URL schemes of generated URLs.
+#[derive(
+    Debug, Clone, Copy, serde::Deserialize, serde::Serialize, clap::ValueEnum,
+)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub enum UrlScheme {
+    Http,
+    Https,
+}
+
+impl UrlScheme {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Http => "http",
+            Self::Https => "https",
+        }
+    }
+}
+
+/// Themes of the generated file listing.
+#[derive(
+    Debug, Clone, Copy, serde::Deserialize, serde::Serialize, clap::ValueEnum,
+)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub enum ListTheme {
+    /// A styled table, which fits small screens.
+    Responsive,
+    /// Preformatted text, as nginx autoindex displays file listings.
+    Plain,
+}
+default!(ListTheme = Self::Responsive);
+
+/// An RGB color, written as `#RRGGBB`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct HexColor(pub [u8; 3]);
+
+impl HexColor {
+    /// The relative luminance, from 0 for black to 1 for white, as defined
+    /// by WCAG 2.
+    pub fn luminance(self) -> f64 {
+        let linear = |c: u8| {
+            let c = f64::from(c) / 255.0;
+            if c <= 0.03928 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        let [r, g, b] = self.0.map(linear);
+        0.2126 * r + 0.7152 * g + 0.0722 * b
+    }
+
+    /// The contrast ratio of `self` as the foreground on `background`, from
+    /// 1 for the same luminance to 21 for black on white.  It is below 1
+    /// when `self` is the lighter color.
+    pub fn contrast(self, background: Self) -> f64 {
+        (background.luminance() + 0.05) / (self.luminance() + 0.05)
+    }
+}
+
+impl std::str::FromStr for HexColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid color {:?}, expected #RRGGBB", s);
+        let hex = s.strip_prefix('#').filter(|hex| {
+            hex.len() == 6 && hex.bytes().all(|b| b.is_ascii_hexdigit())
+        });
+        let hex = hex.ok_or_else(invalid)?;
+        let mut rgb = [0; 3];
+        for (i, c) in rgb.iter_mut().enumerate() {
+            let byte = hex.get(2 * i..2 * i + 2).ok_or_else(invalid)?;
+            *c = u8::from_str_radix(byte, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(rgb))
+    }
+}
+
+impl TryFrom<String> for HexColor {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for HexColor {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl From<HexColor> for String {
+    fn from(color: HexColor) -> Self {
+        color.to_string()
+    }
+}
+
+/// A duration, written as a whole number of seconds, minutes, hours, or
+/// days, e.g. `90s`, `30m`, `1h`, or `7d`.  A bare number is in seconds.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize,
+)]
+#[serde(try_from = "String", into = "String")]
+pub struct HumanDuration(pub Duration);
+
+impl HumanDuration {
+    /// The units, with their lengths in seconds, from the largest.
+    const UNITS: [(char, u64); 4] =
+        [('d', 24 * 60 * 60), ('h', 60 * 60), ('m', 60), ('s', 1)];
+}
+
+impl std::str::FromStr for HumanDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!("invalid duration {:?}, expected e.g. 90s, 30m, or 1h", s)
+        };
+        let (number, unit) = match s.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c),
+            _ => (s, 's'),
+        };
+        let (_, secs) = Self::UNITS
+            .into_iter()
+            .find(|(u, _)| *u == unit)
+            .ok_or_else(invalid)?;
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+        let secs = number.checked_mul(secs).ok_or_else(invalid)?;
+        Ok(Self(Duration::from_secs(secs)))
+    }
+}
+
+impl TryFrom<String> for HumanDuration {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for HumanDuration {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let secs = self.0.as_secs();
+        let (unit, len) = Self::UNITS
+            .into_iter()
+            .find(|(_, len)| secs > 0 && secs.is_multiple_of(*len))
+            .unwrap_or(('s', 1));
+        write!(f, "{}{}", secs / len, unit)
+    }
+}
+
+impl From<HumanDuration> for String {
+    fn from(duration: HumanDuration) -> Self {
+        duration.to_string()
+    }
+}
+
 /// Options for interface bindings.
-#[derive(Debug, Clone, serde::Deserialize, clap::Args, merge::Merge)]
+#[derive(
+    Debug, Clone, serde::Deserialize, serde::Serialize, clap::Args, merge::Merge,
+)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 pub struct BindOptions {
     /// Sets custom bound host addresses.  When empty, use all available IPv4
@@ -79,43 +1082,283 @@ pub struct BindOptions {
     /// Sets a custom port.  Default to 0, where an arbitrary available port is
     /// used.
     #[clap(short, long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub port: Option<u16>,
+
+    /// Sets the maximum number of pending connections on each listener.
+    /// Default to 2048.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backlog: Option<u32>,
+
+    /// Use this address in generated URLs and QR codes, instead of the first
+    /// bound address or the first global IPv4 address.  Useful on multi-homed
+    /// hosts, where the default may not be reachable by clients.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub advertise_host: Option<IpAddr>,
+
+    /// Only consider the addresses of this network interface, e.g. `wlan0`,
+    /// when choosing the address of generated URLs and QR codes.  Useful on
+    /// multi-homed hosts, where other interfaces may not be reachable by
+    /// clients.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+
+    /// Skip the network interfaces whose names start with any of these
+    /// prefixes when choosing the address of generated URLs and QR codes,
+    /// unless named by `interface`.  Default to common virtual interfaces:
+    /// `docker`, `veth`, `br-`, `virbr`, `tun`, and `tap`.  An empty prefix,
+    /// as in `--exclude-interface=`, excludes nothing.
+    #[clap(long, value_parser)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[merge(strategy = merge::vec::overwrite_empty)]
+    pub exclude_interface: Vec<String>,
+
+    /// Also listen on a Unix domain socket at this path, e.g. behind a local
+    /// reverse proxy.  Requires `external-host`, as the socket has no address
+    /// to advertise.  Combine with `local` to keep the TCP listeners off the
+    /// network.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Serve only to this machine, by binding the IPv4 and IPv6 loopback
+    /// addresses instead of `hosts`.  Useful with a tunnel, or for testing.
+    #[clap(long, value_parser, alias = "listen-localhost")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<bool>,
 }
 
-default!(!BindOptions = Self { hosts: Self::default_hosts(), port: None });
+default!(
+    !BindOptions = Self {
+        hosts: Self::default_hosts(),
+        port: None,
+        backlog: None,
+        advertise_host: None,
+        interface: None,
+        exclude_interface: Vec::new(),
+        unix_socket: None,
+        local: None,
+    }
+);
 unwrap_getter!(BindOptions::port: u16 = 0);
+unwrap_getter!(BindOptions::backlog: u32 = 2048);
+unwrap_getter!(BindOptions::local: bool = false);
 
 impl BindOptions {
     pub const UNSPECIFIED_HOSTS: [IpAddr; 2] =
         [IpAddr::V4(Ipv4Addr::UNSPECIFIED), IpAddr::V6(Ipv6Addr::UNSPECIFIED)];
 
+    pub const LOOPBACK_HOSTS: [IpAddr; 2] =
+        [IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)];
+
+    /// The prefixes of virtual interface names skipped by default.
+    pub const EXCLUDED_INTERFACES: [&'static str; 6] =
+        ["docker", "veth", "br-", "virbr", "tun", "tap"];
+
+    /// The accepted range of listen backlog sizes.
+    pub const BACKLOG_RANGE: RangeInclusive<u32> = 1..=65535;
+
     #[inline]
     pub(crate) fn default_hosts() -> Vec<IpAddr> {
         BindOptions::UNSPECIFIED_HOSTS.into()
     }
 
+    /// The hosts to bind, in order, skipping [`Self::duplicate_hosts`].
     pub fn hosts_iter(&self) -> impl Iterator<Item = IpAddr> {
-        if self.hosts.is_empty() {
+        if self.local() {
+            Either::Right(Self::LOOPBACK_HOSTS.into_iter())
+        } else if self.hosts.is_empty() {
             Either::Right(Self::UNSPECIFIED_HOSTS.into_iter())
         } else {
-            Either::Left(self.hosts.clone().into_iter())
+            Either::Left(self.partition_hosts().0.into_iter())
+        }
+    }
+
+    /// The hosts that would be bound more than once: repeated addresses, and
+    /// addresses already covered by an unspecified address of the same
+    /// family.
+    pub fn duplicate_hosts(&self) -> Vec<IpAddr> {
+        if self.local() {
+            return Vec::new();
+        }
+        self.partition_hosts().1
+    }
+
+    /// Split the hosts into the unique ones and the duplicates.
+    fn partition_hosts(&self) -> (Vec<IpAddr>, Vec<IpAddr>) {
+        let covered = |ip: &IpAddr| {
+            !ip.is_unspecified()
+                && self.hosts.iter().any(|host| {
+                    host.is_unspecified() && host.is_ipv4() == ip.is_ipv4()
+                })
+        };
+
+        let mut unique = Vec::with_capacity(self.hosts.len());
+        let mut duplicates = Vec::new();
+        for &ip in &self.hosts {
+            if unique.contains(&ip) || covered(&ip) {
+                duplicates.push(ip);
+            } else {
+                unique.push(ip);
+            }
+        }
+        (unique, duplicates)
+    }
+
+    /// Whether the advertised host, if any, is served: either it is bound, or
+    /// it is an interface address covered by a bound unspecified address of
+    /// the same family.
+    pub fn advertise_host_served(&self) -> bool {
+        let ip = match self.advertise_host {
+            Some(ip) => ip,
+            None => return true,
+        };
+        let mut available = None;
+        self.hosts_iter().any(|host| {
+            host == ip
+                || host.is_unspecified()
+                    && host.is_ipv4() == ip.is_ipv4()
+                    && *available.get_or_insert_with(|| {
+                        get_first_net(|addr| *addr == ip).is_some()
+                    })
+        })
+    }
+
+    /// The prefixes of skipped interface names, as configured or else
+    /// [`Self::EXCLUDED_INTERFACES`].
+    pub fn excluded_interfaces(&self) -> impl Iterator<Item = &str> {
+        if self.exclude_interface.is_empty() {
+            Either::Right(Self::EXCLUDED_INTERFACES.into_iter())
+        } else {
+            Either::Left(self.exclude_interface.iter().map(String::as_str))
+        }
+    }
+
+    /// Whether the addresses of the network interface `name` are considered
+    /// for generated URLs: either it is the configured interface, or there
+    /// is none and `name` is not excluded.
+    pub fn uses_interface(&self, name: &str) -> bool {
+        match &self.interface {
+            Some(interface) => name == interface,
+            None => !self
+                .excluded_interfaces()
+                .any(|prefix| !prefix.is_empty() && name.starts_with(prefix)),
+        }
+    }
+
+    /// The first global address of the considered interfaces.  See
+    /// [`get_global_net`].
+    pub fn global_host(&self) -> Option<IpAddr> {
+        get_global_net(|name| self.uses_interface(name))
+    }
+
+    /// The global addresses served by the bound hosts, in order: bound
+    /// global addresses, and the global addresses of the considered
+    /// interfaces covered by bound unspecified addresses.
+    pub fn global_hosts(&self) -> Vec<IpAddr> {
+        let is_global = |ip: &IpAddr| is_global_4(ip) || is_global_6(ip);
+        let mut global = Vec::new();
+        for host in self.hosts_iter() {
+            let ips = if host.is_unspecified() {
+                get_interface_nets(
+                    |name| self.uses_interface(name),
+                    |ip| ip.is_ipv4() == host.is_ipv4() && is_global(ip),
+                )
+            } else if is_global(&host) {
+                vec![host]
+            } else {
+                continue;
+            };
+            for ip in ips {
+                if !global.contains(&ip) {
+                    global.push(ip);
+                }
+            }
+        }
+        global
+    }
+
+    /// Ensure that the configured interface, if any, has a global address.
+    pub fn check_interface(&self) -> errors::Result<()> {
+        match &self.interface {
+            Some(name) if self.global_host().is_none() => {
+                Err(Error::NoGlobalIp(Some(name.clone())))
+            }
+            _ => Ok(()),
         }
     }
 
+    /// The host of generated URLs: the advertised host, or else the first
+    /// bound host.  When bound to all addresses, prefer a global IPv4
+    /// address of the considered interfaces, and then a global IPv6 one.  See
+    /// [`Self::uses_interface`].
     pub fn primary_host(&self) -> IpAddr {
-        if self.hosts.is_empty() {
-            get_first_net(is_global_4).unwrap_or(Self::UNSPECIFIED_HOSTS[0])
+        if let Some(ip) = self.advertise_host {
+            ip
+        } else if self.local() {
+            Self::LOOPBACK_HOSTS[0]
+        } else if self.hosts.is_empty() {
+            self.global_host().unwrap_or(Self::UNSPECIFIED_HOSTS[0])
         } else {
             self.hosts[0]
         }
     }
 }
 
+/// Options for serving over TLS.
+#[derive(
+    Debug, Clone, serde::Deserialize, serde::Serialize, clap::Args, merge::Merge,
+)]
+#[cfg_attr(test, derive(PartialEq, Eq))]
+pub struct TlsOptions {
+    /// The PEM file containing the TLS certificate chain.  Requires
+    /// `--tls-key`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// The PEM file containing the TLS private key.  Requires `--tls-cert`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_key: Option<PathBuf>,
+
+    /// The PEM file containing the CA certificates trusted for client
+    /// authentication.  When set, clients without a certificate signed by one
+    /// of these CAs are rejected.  Requires `--tls-cert` and `--tls-key`.
+    #[clap(long, value_parser)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_client_ca: Option<PathBuf>,
+}
+
+default!(
+    TlsOptions = Self { tls_cert: None, tls_key: None, tls_client_ca: None }
+);
+
+impl TlsOptions {
+    /// Whether no TLS option is set.
+    pub const fn is_empty(&self) -> bool {
+        self.tls_cert.is_none()
+            && self.tls_key.is_none()
+            && self.tls_client_ca.is_none()
+    }
+
+    /// Whether TLS is configured.
+    pub const fn enabled(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{fs::read_to_string, net::Ipv6Addr};
+    use std::{
+        fs::read_to_string,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    };
 
-    use super::{BindOptions, Config};
+    use super::{BindOptions, Config, HexColor, HumanDuration};
 
     #[test]
     fn test_config() {
@@ -139,13 +1382,287 @@ mod tests {
                     ]
                     .into_iter()
                     .collect(),
-                    port: None
+                    port: None,
+                    backlog: None,
+                    advertise_host: None,
+                    interface: None,
+                    exclude_interface: Vec::new(),
+                    unix_socket: None,
+                    local: None
                 },
                 ..Config::default()
             }
         );
     }
 
+    #[test]
+    fn test_round_trip() {
+        let config: Config = toml::toml! {
+            image = "Svg"
+            strict = true
+            hsts = 3600
+            [bind]
+                hosts = ["1.2.3.4", "::1"]
+                port = 8080
+            [tls]
+                tls_cert = "cert.pem"
+                tls_key = "key.pem"
+        }
+        .try_into()
+        .unwrap();
+
+        let serialized = config.to_toml().unwrap();
+        assert!(!serialized.contains("quiet"));
+        let config2: Config = toml::from_str(&serialized).unwrap();
+        assert_eq!(config, config2);
+
+        // defaults are preserved as-is
+        let config: Config = toml::from_str("").unwrap();
+        let serialized = config.to_toml().unwrap();
+        assert_eq!(config, toml::from_str(&serialized).unwrap());
+    }
+
+    #[test]
+    fn test_duplicate_hosts() {
+        let bind = BindOptions {
+            hosts: [
+                [1, 2, 3, 4].into(),
+                Ipv6Addr::LOCALHOST.into(),
+                [1, 2, 3, 4].into(),
+                Ipv6Addr::UNSPECIFIED.into(),
+                Ipv6Addr::UNSPECIFIED.into(),
+            ]
+            .into(),
+            ..BindOptions::default()
+        };
+        assert_eq!(
+            bind.hosts_iter().collect::<Vec<_>>(),
+            [IpAddr::from([1, 2, 3, 4]), Ipv6Addr::UNSPECIFIED.into()]
+        );
+        assert_eq!(
+            bind.duplicate_hosts(),
+            [
+                IpAddr::from(Ipv6Addr::LOCALHOST),
+                [1, 2, 3, 4].into(),
+                Ipv6Addr::UNSPECIFIED.into()
+            ]
+        );
+
+        // an unspecified IPv4 address does not cover IPv6 addresses
+        let bind = BindOptions {
+            hosts: [Ipv4Addr::UNSPECIFIED.into(), Ipv6Addr::LOCALHOST.into()]
+                .into(),
+            ..BindOptions::default()
+        };
+        assert!(bind.duplicate_hosts().is_empty());
+    }
+
+    #[test]
+    fn test_advertise_host() {
+        let mut bind = BindOptions {
+            hosts: [[1, 2, 3, 4].into(), Ipv6Addr::UNSPECIFIED.into()].into(),
+            advertise_host: Some([1, 2, 3, 4].into()),
+            ..BindOptions::default()
+        };
+        assert!(bind.advertise_host_served());
+        assert_eq!(bind.primary_host(), IpAddr::from([1, 2, 3, 4]));
+
+        bind.advertise_host = Some([5, 6, 7, 8].into());
+        assert!(!bind.advertise_host_served());
+        assert_eq!(bind.primary_host(), IpAddr::from([5, 6, 7, 8]));
+
+        // the loopback address is always available
+        bind.advertise_host = Some(Ipv6Addr::LOCALHOST.into());
+        assert!(bind.advertise_host_served());
+    }
+
+    #[test]
+    fn test_local() {
+        let bind = BindOptions {
+            hosts: [[1, 2, 3, 4].into(), [1, 2, 3, 4].into()].into(),
+            local: Some(true),
+            ..BindOptions::default()
+        };
+        assert_eq!(
+            bind.hosts_iter().collect::<Vec<_>>(),
+            BindOptions::LOOPBACK_HOSTS
+        );
+        assert!(bind.duplicate_hosts().is_empty());
+        assert_eq!(bind.primary_host(), IpAddr::from(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn test_interface() {
+        let mut bind = BindOptions {
+            hosts: Vec::new(),
+            interface: Some("qrshare-missing0".to_owned()),
+            ..BindOptions::default()
+        };
+        let err = bind.check_interface().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No outside-facing IP address on interface qrshare-missing0"
+        );
+        assert_eq!(bind.primary_host(), BindOptions::UNSPECIFIED_HOSTS[0]);
+
+        // the advertised host takes precedence over the interface
+        bind.advertise_host = Some([1, 2, 3, 4].into());
+        assert_eq!(bind.primary_host(), IpAddr::from([1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_exclude_interface() {
+        let mut bind = BindOptions::default();
+        for (name, used) in [
+            ("eth0", true),
+            ("wlan0", true),
+            ("docker0", false),
+            ("veth1a2b3c", false),
+            ("br-0123abcd", false),
+            ("virbr0", false),
+            ("tun0", false),
+            ("tap0", false),
+        ] {
+            assert_eq!(bind.uses_interface(name), used, "{}", name);
+        }
+
+        // configured prefixes replace the default ones
+        bind.exclude_interface = vec!["wlan".to_owned()];
+        assert!(bind.uses_interface("docker0"));
+        assert!(!bind.uses_interface("wlan0"));
+        bind.exclude_interface = vec![String::new()];
+        assert!(bind.uses_interface("docker0") && bind.uses_interface("eth0"));
+
+        // a named interface is never excluded
+        bind.exclude_interface = Vec::new();
+        bind.interface = Some("docker0".to_owned());
+        assert!(bind.uses_interface("docker0"));
+        assert!(!bind.uses_interface("eth0"));
+    }
+
+    #[test]
+    fn test_base_path() {
+        for (path, expected) in [
+            (None, ""),
+            (Some("/"), ""),
+            (Some("share"), "/share"),
+            (Some("/share/"), "/share"),
+            (Some("/a/b/"), "/a/b"),
+        ] {
+            let config = Config {
+                base_path: path.map(str::to_owned),
+                ..Config::default()
+            };
+            assert_eq!(config.normalized_base_path(), expected);
+        }
+    }
+
+    #[test]
+    fn test_stdin_name() {
+        let mut config = Config::default();
+        assert_eq!(config.stdin_name().unwrap(), "stdin.bin");
+        config.stdin_name = Some("backup.tar.gz".to_owned());
+        assert_eq!(config.stdin_name().unwrap(), "backup.tar.gz");
+        for name in ["", "..", "a/b", "/tmp/a"] {
+            config.stdin_name = Some(name.to_owned());
+            assert!(config.stdin_name().is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_mdns_name() {
+        let mut config = Config::default();
+        assert_eq!(config.mdns_name().unwrap(), "qrshare");
+        config.mdns_name = Some("office-printer".to_owned());
+        assert_eq!(config.mdns_name().unwrap(), "office-printer");
+        for name in ["", "a.b", "-a", "a_b", "a.local"] {
+            config.mdns_name = Some(name.to_owned());
+            assert!(config.mdns_name().is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_external_host() {
+        for (host, expected) in [
+            (None, Ok(None)),
+            (Some("example.com"), Ok(Some("example.com"))),
+            (Some("my-box.local."), Ok(Some("my-box.local."))),
+            (Some("203.0.113.7"), Ok(Some("203.0.113.7"))),
+            (Some("2001:db8::1"), Ok(Some("[2001:db8::1]"))),
+            (Some("[2001:db8::1]"), Ok(Some("[2001:db8::1]"))),
+            (Some(""), Err(())),
+            (Some("-bad.example"), Err(())),
+            (Some("example.com:8080"), Err(())),
+            (Some("http://example.com"), Err(())),
+        ] {
+            let config = Config {
+                external_host: host.map(str::to_owned),
+                ..Config::default()
+            };
+            let host = config.normalized_external_host();
+            let host = host.as_ref().map(Option::as_deref).map_err(|_| ());
+            assert_eq!(host, expected);
+        }
+    }
+
+    #[test]
+    fn test_expand_paths() {
+        std::env::set_var("QRSHARE_TEST_EXPAND", "/srv");
+        std::env::remove_var("QRSHARE_TEST_UNSET");
+
+        let mut config: Config = toml::toml! {
+            index_file = "index.html"
+            [tls]
+                tls_cert = "$QRSHARE_TEST_EXPAND/cert.pem"
+                tls_key = "${QRSHARE_TEST_UNSET}/key.pem"
+        }
+        .try_into()
+        .unwrap();
+        assert!(config.clone().expand_paths(true).is_err());
+
+        config.expand_paths(false).unwrap();
+        assert_eq!(config.index_file, Some("index.html".into()));
+        assert_eq!(config.tls.tls_cert, Some("/srv/cert.pem".into()));
+        assert_eq!(config.tls.tls_key, Some("/key.pem".into()));
+    }
+
+    #[test]
+    fn test_hex_color() {
+        let color: HexColor = "#1A2b3C".parse().unwrap();
+        assert_eq!(color, HexColor([0x1a, 0x2b, 0x3c]));
+        assert_eq!(color.to_string(), "#1a2b3c");
+
+        for invalid in ["1a2b3c", "#1a2b3", "#1a2b3c4", "#1a2b3g", "#+1+2+3"] {
+            assert!(invalid.parse::<HexColor>().is_err(), "{}", invalid);
+        }
+
+        let black = HexColor([0x00; 3]);
+        let white = HexColor([0xff; 3]);
+        assert!((black.contrast(white) - 21.0).abs() < 1e-9);
+        assert!(white.contrast(black) < 1.0);
+    }
+
+    #[test]
+    fn test_human_duration() {
+        for (s, secs, display) in [
+            ("90", 90, "90s"),
+            ("90s", 90, "90s"),
+            ("120s", 120, "2m"),
+            ("30m", 30 * 60, "30m"),
+            ("1h", 60 * 60, "1h"),
+            ("7d", 7 * 24 * 60 * 60, "7d"),
+            ("0s", 0, "0s"),
+        ] {
+            let duration: HumanDuration = s.parse().unwrap();
+            assert_eq!(duration.0.as_secs(), secs, "{}", s);
+            assert_eq!(duration.to_string(), display, "{}", s);
+        }
+
+        for invalid in ["", "h", "1w", "-1s", "1.5h", "99999999999999999999d"] {
+            assert!(invalid.parse::<HumanDuration>().is_err(), "{}", invalid);
+        }
+    }
+
     #[test]
     fn test_examples() {
         let config = read_to_string("../assets/empty.toml").unwrap();
@@ -1,7 +1,10 @@
 use std::{fmt, io, path::PathBuf};
 
-use actix_web::{body::BoxBody, error::ResponseError, HttpResponse};
+use actix_web::{
+    body::BoxBody, error::ResponseError, HttpResponse, HttpResponseBuilder,
+};
 use http::status::StatusCode;
+use serde::Serialize;
 
 #[non_exhaustive]
 #[derive(Debug)]
@@ -11,13 +14,25 @@ pub enum Error {
     NoFiles, // "Supply at least one file"
     /// When options are in conflict
     ArgConflict,
+    /// When an option has an invalid value
+    InvalidArg(String),
+    /// When a configuration source (left) cannot be parsed, with the reason
+    /// (right)
+    Config(String, String),
     /// When a file is invalid (not an existing and readable FIFO or regular
     /// file)
     InvalidFile(PathBuf),
+    /// When a file (left) has the same content as an already-served file
+    /// (right)
+    Duplicate(PathBuf, PathBuf),
     /// FIFO is currently not supported
     NoFifo(PathBuf),
+    /// When the extension of a file is not allowed
+    DeniedExtension(PathBuf),
     /// An io error
     IO(io::ErrorKind),
+    /// Cannot create a temporary directory for generated files
+    TempDir(io::ErrorKind),
     /// A file-serving thread has panicked
     JoinPanic,
     /// A file-serving thread has been cancelled
@@ -33,14 +48,25 @@ pub enum Error {
     Http(http::Error),
     /// An HTTP status code with a body
     HttpResponse(http::StatusCode, String),
-    /// Unable to retrieve an outside-facing IPv4 address.
-    NoGlobalIpv4,
+    /// Too many requests, retry after the given number of seconds
+    RateLimited(u64),
+    /// Unable to retrieve an outside-facing IPv4 or IPv6 address, on the
+    /// named interface if any.
+    NoGlobalIp(Option<String>),
     /// Cannot parse string into URI
     Uri(String),
     /// An error from [`qrcode`]
     Qr(qrcode::types::QrError),
     /// An error from [`image`]
     Img(image::ImageError),
+    /// An error from [`rustls`]
+    Tls(rustls::Error),
+    /// An error from [`zip`]
+    Zip(zip::result::ZipError),
+    /// An error from [`mdns_sd`]
+    Mdns(mdns_sd::Error),
+    /// An error from [`notify`]
+    Notify(notify::Error),
 }
 
 impl From<http::StatusCode> for Error {
@@ -51,7 +77,7 @@ impl From<http::StatusCode> for Error {
 
 impl<'b, S: AsRef<str> + ?Sized> From<(http::StatusCode, &'b S)> for Error {
     fn from((code, body): (http::StatusCode, &'b S)) -> Self {
-        (code, body.to_owned()).into()
+        (code, body.as_ref().to_owned()).into()
     }
 }
 
@@ -73,6 +99,30 @@ impl From<image::ImageError> for Error {
     }
 }
 
+impl From<rustls::Error> for Error {
+    fn from(v: rustls::Error) -> Self {
+        Self::Tls(v)
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(v: zip::result::ZipError) -> Self {
+        Self::Zip(v)
+    }
+}
+
+impl From<mdns_sd::Error> for Error {
+    fn from(v: mdns_sd::Error) -> Self {
+        Self::Mdns(v)
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(v: notify::Error) -> Self {
+        Self::Notify(v)
+    }
+}
+
 impl From<qrcode::types::QrError> for Error {
     fn from(v: qrcode::types::QrError) -> Self {
         Self::Qr(v)
@@ -109,6 +159,75 @@ impl From<tokio::task::JoinError> for Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// The body of an error response in JSON.  See [`Error::json_response`].
+#[derive(Debug, Serialize)]
+struct JsonError<'a> {
+    /// The variant name, see [`Error::kind`].
+    error: &'static str,
+    message: &'a str,
+    status: u16,
+}
+
+impl Error {
+    /// The name of this variant, as a stable tag for API clients.
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::NoFiles => "NoFiles",
+            Self::ArgConflict => "ArgConflict",
+            Self::InvalidArg(_) => "InvalidArg",
+            Self::Config(..) => "Config",
+            Self::InvalidFile(_) => "InvalidFile",
+            Self::Duplicate(..) => "Duplicate",
+            Self::NoFifo(_) => "NoFifo",
+            Self::DeniedExtension(_) => "DeniedExtension",
+            Self::IO(_) => "IO",
+            Self::TempDir(_) => "TempDir",
+            Self::JoinPanic => "JoinPanic",
+            Self::JoinCancel => "JoinCancel",
+            Self::PoisonSync => "PoisonSync",
+            Self::Hyper(_) => "Hyper",
+            Self::Http(_) => "Http",
+            Self::HttpResponse(..) => "HttpResponse",
+            Self::RateLimited(_) => "RateLimited",
+            Self::NoGlobalIp(_) => "NoGlobalIp",
+            Self::Uri(_) => "Uri",
+            Self::Qr(_) => "Qr",
+            Self::Img(_) => "Img",
+            Self::Tls(_) => "Tls",
+            Self::Zip(_) => "Zip",
+            Self::Mdns(_) => "Mdns",
+            Self::Notify(_) => "Notify",
+        }
+    }
+
+    /// The error response, with a JSON body of the form `{ "error": kind,
+    /// "message": ..., "status": code }` instead of plain text.
+    pub fn json_response(&self) -> HttpResponse<BoxBody> {
+        self.response_builder().json(JsonError {
+            error: self.kind(),
+            message: &self.message(),
+            status: self.status_code().as_u16(),
+        })
+    }
+
+    /// The message shown to clients, without a trailing newline.
+    pub fn message(&self) -> String {
+        match self {
+            Self::HttpResponse(_, body) => body.trim_end().to_owned(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// The response builder with the status code and headers, but no body.
+    pub fn response_builder(&self) -> HttpResponseBuilder {
+        let mut builder = HttpResponse::build(self.status_code());
+        if let Self::RateLimited(secs) = self {
+            builder.insert_header((http::header::RETRY_AFTER, *secs));
+        }
+        builder
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -118,19 +237,48 @@ impl fmt::Display for Error {
                 write!(f, "Invalid file at {}", p.display())
             }
             Self::IO(e) => write!(f, "Error from std::io: {}", e),
+            Self::TempDir(e) => write!(
+                f,
+                "Cannot create a temporary directory ({}); \
+                set TMPDIR to a writable directory",
+                e
+            ),
             Self::JoinPanic => write!(f, "Cannot join task"),
             Self::JoinCancel => write!(f, "Task canceled"),
             Self::PoisonSync => write!(f, "Lock poisoned"),
+            Self::Duplicate(p, q) => write!(
+                f,
+                "Duplicate file at {} (same content as {})",
+                p.display(),
+                q.display()
+            ),
             Self::NoFifo(p) => write!(f, "FIFO file at {}", p.display()),
-            Self::NoGlobalIpv4 => write!(f, "No outside-facing IPv4 address"),
+            Self::DeniedExtension(p) => {
+                write!(f, "File extension not allowed at {}", p.display())
+            }
+            Self::NoGlobalIp(None) => write!(f, "No outside-facing IP address"),
+            Self::NoGlobalIp(Some(name)) => {
+                write!(f, "No outside-facing IP address on interface {}", name)
+            }
             Self::Uri(s) => write!(f, "Cannot parse as URI: {}", s),
             Self::ArgConflict => write!(f, "Conflicting arguments found"),
+            Self::InvalidArg(s) => write!(f, "Invalid argument: {}", s),
+            Self::Config(src, e) => {
+                write!(f, "Invalid configuration from {}: {}", src, e)
+            }
             // error objects from external crates
             Self::Hyper(e) => write!(f, "[hyper]: {}", e),
             Self::Http(e) => write!(f, "[http]: {}", e),
             Self::Qr(e) => write!(f, "[qrcode]: {}", e),
             Self::Img(e) => write!(f, "[image]: {}", e),
+            Self::Tls(e) => write!(f, "[rustls]: {}", e),
+            Self::Zip(e) => write!(f, "[zip]: {}", e),
+            Self::Mdns(e) => write!(f, "[mdns-sd]: {}", e),
+            Self::Notify(e) => write!(f, "[notify]: {}", e),
             Self::HttpResponse(code, body) => write!(f, "({}) {}", code, body),
+            Self::RateLimited(secs) => {
+                write!(f, "Too many requests, retry after {}s", secs)
+            }
         }
     }
 }
@@ -139,12 +287,14 @@ impl ResponseError for Error {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::HttpResponse(code, _) => *code,
+            Self::Duplicate(..) => StatusCode::CONFLICT,
+            Self::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 
     fn error_response(&self) -> HttpResponse<BoxBody> {
-        let mut builder = HttpResponse::build(self.status_code());
+        let mut builder = self.response_builder();
         match self {
             Self::HttpResponse(_, body) => builder.body(body.to_owned()),
             _ => builder.body(self.to_string()),
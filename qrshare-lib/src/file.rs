@@ -1,6 +1,41 @@
 /// Shared API
 pub mod shared {
-    use std::fs::FileType;
+    use std::{fs::FileType, path::Path};
+
+    /// Which file extensions may be served, compared case-insensitively.
+    #[derive(Debug, Clone, Default)]
+    pub struct ExtPolicy {
+        /// If non-empty, only these extensions are allowed.
+        allow: Vec<String>,
+
+        /// These extensions are denied.
+        deny: Vec<String>,
+    }
+
+    impl ExtPolicy {
+        /// Create a policy from extensions, with or without a leading `.`.
+        pub fn new(allow: &[String], deny: &[String]) -> Self {
+            let normalize = |exts: &[String]| {
+                exts.iter()
+                    .map(|e| e.trim_start_matches('.').to_ascii_lowercase())
+                    .collect()
+            };
+            Self { allow: normalize(allow), deny: normalize(deny) }
+        }
+
+        /// Check whether the file at `path` passes the policy, based on its
+        /// final extension.  Files without extensions only fail an allowlist.
+        pub fn permits(&self, path: &Path) -> bool {
+            let ext = path
+                .extension()
+                .map(|e| e.to_string_lossy().to_ascii_lowercase());
+            let listed = |exts: &[String]| {
+                ext.as_ref().is_some_and(|ext| exts.contains(ext))
+            };
+            (self.allow.is_empty() || listed(&self.allow))
+                && !listed(&self.deny)
+        }
+    }
 
     /// Check whether a file type does not represent a single-read file.
     #[cfg(target_family = "unix")]
@@ -21,33 +56,251 @@ pub mod shared {
     pub fn is_multiread_md(_: FileType) -> bool {
         true
     }
+
+    /// Check whether a file type represents a FIFO.
+    #[cfg(target_family = "unix")]
+    pub fn is_fifo_md(ft: FileType) -> bool {
+        use std::os::unix::fs::FileTypeExt;
+        ft.is_fifo()
+    }
+
+    /// Check whether a file type represents a FIFO.
+    #[cfg(not(target_family = "unix"))]
+    pub fn is_fifo_md(_: FileType) -> bool {
+        false
+    }
 }
 
 /// Synchronous API
 pub mod sync {
+    use std::path::{Path, PathBuf};
+
     pub use std::fs::{canonicalize, File};
 
     use super::shared::is_multiread_md;
 
+    /// Expand `pattern` as a glob pattern into the matching paths, in
+    /// alphabetical order.  As in shells, a pattern that is invalid or has
+    /// no matches is taken literally.
+    pub fn expand_glob(pattern: &Path) -> Vec<PathBuf> {
+        let matches: Vec<_> = match pattern.to_str().map(glob::glob) {
+            Some(Ok(paths)) => paths.filter_map(Result::ok).collect(),
+            _ => Vec::new(),
+        };
+        if matches.is_empty() {
+            vec![pattern.to_owned()]
+        } else {
+            matches
+        }
+    }
+
     /// Check whether a file is a multi-read file.
     pub fn is_multiread_file(file: &File) -> bool {
         file.metadata()
             .map(|md| md.file_type())
-            .map_or(false, is_multiread_md)
+            .is_ok_and(is_multiread_md)
     }
 }
 
 /// Asynchronous API
 pub mod asy {
+    use std::{
+        collections::HashSet,
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+
+    use notify::{
+        event::{AccessKind, AccessMode},
+        Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+    };
+    use tempfile::TempDir;
     pub use tokio::fs::{canonicalize, File};
+    use tokio::{
+        io::{AsyncRead, AsyncWriteExt},
+        sync::mpsc,
+        time,
+    };
+    use walkdir::WalkDir;
 
-    use super::shared::is_multiread_md;
+    use super::shared::{is_fifo_md, is_multiread_md};
+    use crate::errors::{self, Error};
+
+    /// The entries to skip when walking directories.
+    #[derive(Debug, Clone, Default)]
+    pub struct WalkFilter {
+        /// Whether to walk entries whose names start with `.`.
+        pub include_hidden: bool,
+
+        /// Skip entries whose names, or paths relative to the walked
+        /// directory, match any of these patterns.
+        pub exclude: Vec<glob::Pattern>,
+    }
+
+    impl WalkFilter {
+        /// Create a filter from the glob patterns in `exclude`.
+        pub fn new(
+            include_hidden: bool,
+            exclude: &[String],
+        ) -> errors::Result<Self> {
+            let exclude = exclude
+                .iter()
+                .map(|p| {
+                    glob::Pattern::new(p).map_err(|e| {
+                        Error::InvalidArg(format!("exclude {}: {}", p, e))
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(Self { include_hidden, exclude })
+        }
+
+        /// Check whether to skip the entry at `relative`, the path relative
+        /// to the walked directory.
+        pub fn skips(&self, relative: &Path) -> bool {
+            let name =
+                relative.file_name().unwrap_or_default().to_string_lossy();
+            (!self.include_hidden && name.starts_with('.'))
+                || self
+                    .exclude
+                    .iter()
+                    .any(|p| p.matches(&name) || p.matches_path(relative))
+        }
+    }
+
+    /// Find the regular files under the directory `dir`, following symbolic
+    /// links, and descending at most `max_depth` levels where files directly
+    /// in `dir` are at depth 1.  Return their canonicalized paths.  Entries
+    /// that cannot be read, including symbolic link loops, are skipped with a
+    /// warning.  Entries skipped by `filter` are not descended into, but
+    /// `dir` itself is always walked.
+    pub async fn walk_files(
+        dir: PathBuf,
+        max_depth: Option<usize>,
+        filter: WalkFilter,
+    ) -> Vec<PathBuf> {
+        tokio::task::spawn_blocking(move || {
+            let mut walk = WalkDir::new(&dir).follow_links(true);
+            if let Some(depth) = max_depth {
+                walk = walk.max_depth(depth);
+            }
+            let walk = walk.into_iter().filter_entry(|e| {
+                let relative = e.path().strip_prefix(&dir).unwrap_or(e.path());
+                e.depth() == 0 || !filter.skips(relative)
+            });
+            let mut files = Vec::new();
+            for entry in walk {
+                match entry.map(|e| (e.file_type().is_file(), e.into_path())) {
+                    Ok((true, path)) => match path.canonicalize() {
+                        Ok(path) => files.push(path),
+                        Err(e) => {
+                            log::warn!("Skipping {}: {}", path.display(), e)
+                        }
+                    },
+                    Ok((false, _)) => (),
+                    Err(e) => log::warn!("Skipping {}", e),
+                }
+            }
+            files
+        })
+        .await
+        .unwrap_or_default()
+    }
 
     /// Check whether a file is a multi-read file.
     pub async fn is_multiread_file(file: &File) -> bool {
         file.metadata()
             .await
             .map(|md| md.file_type())
-            .map_or(false, is_multiread_md)
+            .is_ok_and(is_multiread_md)
+    }
+
+    /// Check whether the file at `path`, following symbolic links, is a FIFO.
+    pub async fn is_fifo(path: &Path) -> bool {
+        tokio::fs::metadata(path)
+            .await
+            .map(|md| md.file_type())
+            .is_ok_and(is_fifo_md)
+    }
+
+    /// Copy the single-read `reader`, such as the standard input or a FIFO,
+    /// into a file named `name`, in a new temporary directory which is
+    /// deleted when dropped.  Return the directory and the canonicalized path
+    /// of the file.
+    pub async fn buffer<R: AsyncRead + Unpin>(
+        name: impl AsRef<Path>,
+        reader: &mut R,
+    ) -> errors::Result<(TempDir, PathBuf)> {
+        let dir = tempfile::tempdir().map_err(|e| Error::TempDir(e.kind()))?;
+        let path = dir.path().join(name);
+        let mut file = File::create(&path).await?;
+        let size = tokio::io::copy(reader, &mut file).await?;
+        file.flush().await?;
+        log::info!("Buffered {} bytes into {}", size, path.display());
+        let path = canonicalize(&path).await?;
+        Ok((dir, path))
+    }
+
+    /// A watcher of directories, which reports the paths created, modified,
+    /// or removed under them.  Watching stops when dropped.
+    pub struct DirWatcher {
+        _watcher: RecommendedWatcher,
+        rx: mpsc::UnboundedReceiver<PathBuf>,
+    }
+
+    impl DirWatcher {
+        /// Watch the directories `dirs`, and also their subdirectories when
+        /// `recursive` is set.
+        pub fn new(dirs: &[PathBuf], recursive: bool) -> errors::Result<Self> {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let handler = move |event: notify::Result<Event>| match event {
+                Ok(Event { kind, paths, .. }) if is_change(&kind) => {
+                    for path in paths {
+                        // the receiver is only dropped with the watcher
+                        let _ = tx.send(path);
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => log::warn!("Cannot watch directory: {}", e),
+            };
+            let mut watcher = notify::recommended_watcher(handler)?;
+            let mode = match recursive {
+                true => RecursiveMode::Recursive,
+                false => RecursiveMode::NonRecursive,
+            };
+            for dir in dirs {
+                watcher.watch(dir, mode)?;
+                log::info!("Watching directory: {}", dir.display());
+            }
+            Ok(Self { _watcher: watcher, rx })
+        }
+
+        /// Wait for the next changed paths, once no change has been reported
+        /// for `quiet`, so that a file being written is reported only once.
+        /// Return `None` when the watcher has stopped.
+        pub async fn next_batch(
+            &mut self,
+            quiet: Duration,
+        ) -> Option<HashSet<PathBuf>> {
+            let mut batch = HashSet::from([self.rx.recv().await?]);
+            while let Ok(Some(path)) =
+                time::timeout(quiet, self.rx.recv()).await
+            {
+                batch.insert(path);
+            }
+            Some(batch)
+        }
+    }
+
+    /// Whether an event of `kind` may change the files served from a watched
+    /// directory.  Writes are included to postpone new files until they are
+    /// complete.
+    fn is_change(kind: &EventKind) -> bool {
+        matches!(
+            kind,
+            EventKind::Create(_)
+                | EventKind::Modify(_)
+                | EventKind::Remove(_)
+                | EventKind::Access(AccessKind::Close(AccessMode::Write))
+        )
     }
 }
@@ -0,0 +1,213 @@
+//! This module computes file digests in the configured hash algorithm.
+
+use blake2::Blake2b512;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::config::{DigestEncoding, HashMethod};
+
+/// An incremental hasher in any [`HashMethod`].
+#[derive(Debug, Clone)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake2b(Blake2b512),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new(method: HashMethod) -> Self {
+        match method {
+            HashMethod::Sha256 => Self::Sha256(Sha256::new()),
+            HashMethod::Sha512 => Self::Sha512(Sha512::new()),
+            HashMethod::Blake2b => Self::Blake2b(Blake2b512::new()),
+            HashMethod::Blake3 => Self::Blake3(Box::default()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+            Self::Blake2b(h) => h.update(data),
+            Self::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    /// The digest as bytes.
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Sha256(h) => h.finalize().to_vec(),
+            Self::Sha512(h) => h.finalize().to_vec(),
+            Self::Blake2b(h) => h.finalize().to_vec(),
+            Self::Blake3(h) => h.finalize().as_bytes().to_vec(),
+        }
+    }
+
+    /// The digest as a lowercase hex string.
+    pub fn finalize_hex(self) -> String {
+        hex::encode(self.finalize())
+    }
+}
+
+/// The HMAC-SHA256 of `message` keyed by `key`, as in RFC 2104.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    // longer keys are hashed first, and shorter ones padded with zeros
+    let mut block = [0; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.map(|b| b ^ byte);
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// Encode the digest `bytes` as a string in `encoding`.
+pub fn encode(encoding: DigestEncoding, bytes: &[u8]) -> String {
+    match encoding {
+        DigestEncoding::Hex => hex::encode(bytes),
+        DigestEncoding::Base32 => base32(bytes),
+        DigestEncoding::Base58 => base58(bytes),
+        DigestEncoding::Base64url => {
+            base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+        }
+    }
+}
+
+/// Encode `bytes` in lowercase base32 of RFC 4648, without padding.
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    // at most 12 pending bits, the lowest `bits` of `buffer`
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = ((buffer << 8) | u32::from(byte)) & 0xfff;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(char::from(ALPHABET[(buffer >> bits) as usize & 31]));
+        }
+    }
+    if bits > 0 {
+        encoded
+            .push(char::from(ALPHABET[(buffer << (5 - bits)) as usize & 31]));
+    }
+    encoded
+}
+
+/// Encode `bytes` in base58 with the Bitcoin alphabet, which leaves out the
+/// ambiguous `0`, `O`, `I` and `l`.
+fn base58(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 58] =
+        b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    // base-58 digits of the big-endian number, least significant first
+    let mut digits: Vec<u8> = Vec::with_capacity(bytes.len() * 138 / 100 + 1);
+    for &byte in bytes {
+        let mut carry = u32::from(byte);
+        for digit in &mut digits {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    // each leading zero byte is kept as a leading `1`
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    std::iter::repeat_n('1', zeros)
+        .chain(
+            digits
+                .iter()
+                .rev()
+                .map(|&d| char::from(ALPHABET[d as usize])),
+        )
+        .collect()
+}
+
+/// The digest of `data` in `method`, as a lowercase hex string.
+pub fn digest_hex(method: HashMethod, data: impl AsRef<[u8]>) -> String {
+    let mut hasher = Hasher::new(method);
+    hasher.update(data.as_ref());
+    hasher.finalize_hex()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{digest_hex, encode, hmac_sha256};
+    use crate::config::{DigestEncoding, HashMethod};
+
+    /// Test cases 2 and 6 of RFC 4231, with a short and a long key.
+    #[test]
+    fn test_hmac_sha256() {
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            hex::encode(mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        let mac = hmac_sha256(
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First",
+        );
+        assert_eq!(
+            hex::encode(mac),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        for (encoding, bytes, expected) in [
+            (DigestEncoding::Hex, b"foobar".as_ref(), "666f6f626172"),
+            (DigestEncoding::Base32, b"", ""),
+            (DigestEncoding::Base32, b"f", "my"),
+            (DigestEncoding::Base32, b"fooba", "mzxw6ytb"),
+            (DigestEncoding::Base32, b"foobar", "mzxw6ytboi"),
+            (DigestEncoding::Base58, b"", ""),
+            (DigestEncoding::Base58, b"\0\0\x01", "112"),
+            (DigestEncoding::Base58, b"hello world", "StV1DL6CwTryKyV"),
+            (DigestEncoding::Base64url, b"\xfb\xff", "-_8"),
+            (DigestEncoding::Base64url, b"foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encode(encoding, bytes), expected, "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn test_digest_hex() {
+        for (method, expected) in [
+            (
+                HashMethod::Sha256,
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                HashMethod::Sha512,
+                "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+                 2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+            ),
+            (
+                HashMethod::Blake2b,
+                "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+                 7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923",
+            ),
+            (
+                HashMethod::Blake3,
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+        ] {
+            assert_eq!(digest_hex(method, "abc"), expected, "{}", method);
+        }
+    }
+}
@@ -1,7 +1,13 @@
+pub mod archive;
 pub mod config;
 pub mod errors;
 pub mod file;
+pub mod hash;
 pub mod macros;
+pub mod metrics;
 pub mod net;
 pub mod qr;
+pub mod ratelimit;
+pub mod thumb;
+pub mod tls;
 pub mod utils;
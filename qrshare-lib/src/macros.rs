@@ -53,11 +53,13 @@ macro_rules! default_internal {
     };
     ($(#[$m:meta])*, const $s:ident, $default:expr) => {
         impl $s {
+            #[allow(clippy::should_implement_trait)]
             $(#[$m])* pub const fn default() -> Self { $default }
         }
     };
     ($(#[$m:meta])*, $s:ident, $default:expr) => {
         impl $s {
+            #[allow(clippy::should_implement_trait)]
             $(#[$m])* pub fn default() -> Self { $default }
         }
     };
@@ -67,9 +69,9 @@ macro_rules! default_internal {
 /// requirements:
 ///
 /// 1. (Skipped, waiting for `Option::unwrap_or` to become const.) The field has
-/// type `F`, with a const method `F::unwrap_or(&self) -> T`.
+///    type `F`, with a const method `F::unwrap_or(&self) -> T`.
 /// 2. (In effect until `Option::unwrap_or` becomes const.) The field must have
-/// `Option<T>` type.
+///    `Option<T>` type.
 /// 3. The aforementioned type `T` must implement `Copy`.
 ///
 /// ```rust
@@ -0,0 +1,163 @@
+//! This module implements the counters exported in the Prometheus text
+//! format.  See <https://prometheus.io/docs/instrumenting/exposition_formats/>.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// The counters of a running server.  Gauges, such as the number of served
+/// files, are instead passed to [`Metrics::render`] when scraped.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Successful file downloads.
+    downloads: AtomicU64,
+    /// The bytes in successful file download responses.
+    bytes_served: AtomicU64,
+    /// Views of the file listing.
+    listings: AtomicU64,
+    /// Served QR code images.
+    qr_codes: AtomicU64,
+    /// The time spent hashing files, in microseconds.
+    hashing_micros: AtomicU64,
+    /// Responses, keyed by status code.  This uses a synchronous lock, as
+    /// the count is incremented from middlewares.
+    statuses: Mutex<BTreeMap<u16, u64>>,
+}
+
+impl Metrics {
+    /// Count a successful download of `bytes` bytes.
+    pub fn count_download(&self, bytes: u64) {
+        self.downloads.fetch_add(1, Ordering::Relaxed);
+        self.bytes_served.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Count a view of the file listing.
+    pub fn count_listing(&self) {
+        self.listings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a served QR code image.
+    pub fn count_qr_code(&self) {
+        self.qr_codes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add the time spent hashing a file.
+    pub fn add_hashing(&self, duration: Duration) {
+        let micros = duration.as_micros().try_into().unwrap_or(u64::MAX);
+        self.hashing_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Count a response with `status`.
+    pub fn count_status(&self, status: u16) {
+        let mut statuses =
+            self.statuses.lock().unwrap_or_else(|e| e.into_inner());
+        *statuses.entry(status).or_default() += 1;
+    }
+
+    /// Render the counters, and the number of served `files`, in the
+    /// Prometheus text format.
+    pub fn render(&self, files: usize) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &str| {
+            // writing to a string never fails
+            let _ = write!(
+                out,
+                "# HELP {0} {1}\n# TYPE {0} {2}\n{0} {3}\n",
+                name, help, kind, value
+            );
+        };
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        metric(
+            "qrshare_downloads_total",
+            "counter",
+            "Successful file downloads.",
+            &load(&self.downloads).to_string(),
+        );
+        metric(
+            "qrshare_served_bytes_total",
+            "counter",
+            "Bytes in successful file download responses.",
+            &load(&self.bytes_served).to_string(),
+        );
+        metric(
+            "qrshare_listings_total",
+            "counter",
+            "Views of the file listing.",
+            &load(&self.listings).to_string(),
+        );
+        metric(
+            "qrshare_qr_codes_total",
+            "counter",
+            "Served QR code images.",
+            &load(&self.qr_codes).to_string(),
+        );
+        metric(
+            "qrshare_hashing_seconds_total",
+            "counter",
+            "Time spent hashing files.",
+            &(load(&self.hashing_micros) as f64 / 1e6).to_string(),
+        );
+        metric(
+            "qrshare_files",
+            "gauge",
+            "Currently served files.",
+            &files.to_string(),
+        );
+
+        out.push_str("# HELP qrshare_responses_total Responses by status.\n");
+        out.push_str("# TYPE qrshare_responses_total counter\n");
+        let statuses = self.statuses.lock().unwrap_or_else(|e| e.into_inner());
+        for (status, count) in statuses.iter() {
+            let _ = writeln!(
+                out,
+                "qrshare_responses_total{{status=\"{}\"}} {}",
+                status, count
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Metrics;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.count_download(5);
+        metrics.count_download(7);
+        metrics.count_listing();
+        metrics.add_hashing(Duration::from_millis(1500));
+        for status in [404, 200, 200] {
+            metrics.count_status(status);
+        }
+
+        let text = metrics.render(3);
+        for line in [
+            "# TYPE qrshare_downloads_total counter",
+            "qrshare_downloads_total 2",
+            "qrshare_served_bytes_total 12",
+            "qrshare_listings_total 1",
+            "qrshare_qr_codes_total 0",
+            "qrshare_hashing_seconds_total 1.5",
+            "# TYPE qrshare_files gauge",
+            "qrshare_files 3",
+            "qrshare_responses_total{status=\"200\"} 2",
+            "qrshare_responses_total{status=\"404\"} 1",
+        ] {
+            assert!(text.lines().any(|l| l == line), "{}\n{}", line, text);
+        }
+        // status codes are sorted
+        assert!(text.find("\"200\"") < text.find("\"404\""));
+    }
+}
@@ -1,16 +1,53 @@
-use std::net::IpAddr;
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    time::Duration,
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixListener, path::Path};
 
 use get_if_addrs::get_if_addrs;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use socket2::{Domain, Socket, Type};
+
+use crate::errors;
 
 pub fn get_first_net<F>(f: F) -> Option<IpAddr>
 where
     F: FnMut(&IpAddr) -> bool,
+{
+    get_interface_net(|_| true, f)
+}
+
+/// The first address satisfying `f` among those of the network interfaces
+/// whose names satisfy `interface`.
+pub fn get_interface_net<P, F>(mut interface: P, mut f: F) -> Option<IpAddr>
+where
+    P: FnMut(&str) -> bool,
+    F: FnMut(&IpAddr) -> bool,
+{
+    get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|i| interface(&i.name))
+        .map(|i| i.ip())
+        .find(|ip| f(ip))
+}
+
+/// All addresses satisfying `f` among those of the network interfaces whose
+/// names satisfy `interface`, in order.
+pub fn get_interface_nets<P, F>(mut interface: P, mut f: F) -> Vec<IpAddr>
+where
+    P: FnMut(&str) -> bool,
+    F: FnMut(&IpAddr) -> bool,
 {
     get_if_addrs()
         .unwrap_or_default()
         .into_iter()
+        .filter(|i| interface(&i.name))
         .map(|i| i.ip())
-        .find(f)
+        .filter(|ip| f(ip))
+        .collect()
 }
 
 /// While [`std::net::IpAddr::is_global`] is still unstable after 7 years, here
@@ -26,3 +63,141 @@ pub const fn is_global_4(addr: &IpAddr) -> bool {
         false
     }
 }
+
+/// The same predicate as [`is_global_4`] for [`std::net::Ipv6Addr`],
+/// excluding the unspecified, loopback, unique local (`fc00::/7`), link-local
+/// (`fe80::/10`), and multicast addresses.
+pub const fn is_global_6(addr: &IpAddr) -> bool {
+    if let IpAddr::V6(addr) = addr {
+        let first = addr.segments()[0];
+        !(addr.is_unspecified()
+            || addr.is_loopback()
+            || first & 0xfe00 == 0xfc00
+            || first & 0xffc0 == 0xfe80
+            || addr.is_multicast())
+    } else {
+        false
+    }
+}
+
+/// The first global IPv4 address of the network interfaces whose names
+/// satisfy `interface`, or else the first global IPv6 one.
+pub fn get_global_net<P>(mut interface: P) -> Option<IpAddr>
+where
+    P: FnMut(&str) -> bool,
+{
+    get_interface_net(&mut interface, is_global_4)
+        .or_else(|| get_interface_net(interface, is_global_6))
+}
+
+/// Create a TCP listener bound to `addr`, with a custom listen backlog.  Unlike
+/// [`TcpListener::bind`], which always uses the default backlog of the
+/// standard library, this honors the `backlog` argument.
+pub fn tcp_listener(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    // allow IPv4 and IPv6 listeners to share the same port
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(backlog.try_into().unwrap_or(i32::MAX))?;
+    Ok(socket.into())
+}
+
+/// An HTTP service advertised over mDNS, until unregistered.
+pub struct MdnsService {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl MdnsService {
+    /// The mDNS service type of HTTP servers.
+    pub const SERVICE_TYPE: &'static str = "_http._tcp.local.";
+
+    /// Advertise an HTTP service named `name`, at the host name `name.local`
+    /// resolving to `ips`, on `port`.
+    pub fn register(
+        name: &str,
+        ips: &[Ipv4Addr],
+        port: u16,
+    ) -> errors::Result<Self> {
+        let daemon = ServiceDaemon::new()?;
+        let host_name = format!("{}.local.", name);
+        let info = ServiceInfo::new(
+            Self::SERVICE_TYPE,
+            name,
+            &host_name,
+            ips,
+            port,
+            None,
+        )?;
+        let fullname = info.get_fullname().to_owned();
+        daemon.register(info)?;
+        Ok(Self { daemon, fullname })
+    }
+
+    /// Stop advertising the service, waiting up to one second for the
+    /// goodbye announcement, and stop the daemon.
+    pub fn unregister(self) -> errors::Result<()> {
+        let status = self.daemon.unregister(&self.fullname)?;
+        if status.recv_timeout(Duration::from_secs(1)).is_err() {
+            log::warn!("No response unregistering mDNS service");
+        }
+        Ok(self.daemon.shutdown()?)
+    }
+}
+
+impl fmt::Debug for MdnsService {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MdnsService")
+            .field("fullname", &self.fullname)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Create a Unix domain socket listener at `path`.  A stale socket at `path`
+/// is replaced, but any other file is kept and reported as in use.
+#[cfg(unix)]
+pub fn unix_listener(path: &Path) -> io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            std::fs::remove_file(path)?
+        }
+        Ok(_) => return Err(io::ErrorKind::AddrInUse.into()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    UnixListener::bind(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::{is_global_4, is_global_6};
+
+    #[test]
+    fn test_is_global_6() {
+        for (addr, global) in [
+            ("2001:db8::1", true),
+            ("2606:4700::1111", true),
+            ("::", false),
+            ("::1", false),
+            ("fc00::1", false),
+            ("fd12:3456::1", false),
+            ("fe80::1", false),
+            ("febf::1", false),
+            ("ff02::1", false),
+        ] {
+            let addr: IpAddr = addr.parse().unwrap();
+            assert_eq!(is_global_6(&addr), global, "{}", addr);
+            assert!(!is_global_4(&addr), "{}", addr);
+        }
+        // IPv4 addresses are never global IPv6 addresses
+        assert!(!is_global_6(&Ipv4Addr::new(1, 2, 3, 4).into()));
+        assert!(!is_global_6(&Ipv6Addr::LOCALHOST.into()));
+    }
+}
@@ -1,26 +1,52 @@
 /// Generate the QR code from a file
 pub mod gen {
     use std::{
-        fmt::{self, Display, Formatter},
+        fmt::{self, Debug, Display, Formatter},
         io::ErrorKind,
         net::SocketAddr,
-        path::PathBuf,
+        path::{Path, PathBuf},
+        sync::Arc,
     };
 
     use http::Uri;
-    use image::Luma;
+    use image::{DynamicImage, ImageOutputFormat, Luma};
     use qrcode::{render::svg, QrCode};
     use tempfile::TempDir;
     use tokio::{fs::File, io::AsyncWriteExt};
 
     use crate::{
-        config::ImageOptions,
-        errors,
-        net::{get_first_net, is_global_4},
+        config::{BindOptions, HexColor, ImageOptions},
+        default, errors,
+        net::{is_global_4, is_global_6},
     };
 
     pub use self::svg::Color as SvgColor;
 
+    /// A transformation applied to a URL right before it is encoded into a QR
+    /// code, e.g. to sign or shorten it, or to wrap it in an app scheme.  The
+    /// default transformation returns the URL unchanged.
+    #[derive(Clone)]
+    pub struct PayloadTransform(Arc<dyn Fn(&str) -> String + Send + Sync>);
+
+    default!(!PayloadTransform = Self::new(|url| url.to_owned()));
+
+    impl PayloadTransform {
+        pub fn new(f: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+            Self(Arc::new(f))
+        }
+
+        /// Transform `url` into the QR code payload.
+        pub fn apply(&self, url: &str) -> String {
+            (self.0)(url)
+        }
+    }
+
+    impl Debug for PayloadTransform {
+        fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+            f.debug_tuple("PayloadTransform").finish_non_exhaustive()
+        }
+    }
+
     /// Which file type to render.
     #[derive(Debug, Clone, Copy)]
     pub enum QrFileType {
@@ -37,49 +63,509 @@ pub mod gen {
         }
     }
 
-    /// Generate a QR code file from a digest.  The lifetime is used for working
-    /// with [`tempfile`] crate whose security promise states that the temporary
-    /// directory is removed when the [`tempfile::TempDir`] object goes
-    /// out-of-scope.
-    pub async fn gen_qr<'dir>(
+    /// How QR codes are rendered.
+    #[derive(Debug, Clone)]
+    pub struct QrStyle {
+        /// Whether to write the encoded URL beneath the code, see
+        /// [`super::caption`].
+        pub caption: bool,
+        /// The size of each module, in pixels, instead of the renderer
+        /// default.
+        pub scale: Option<u32>,
+        /// Whether to surround the code with a quiet zone, which some
+        /// scanners require.
+        pub quiet_zone: bool,
+        /// The color of dark modules in SVG codes, instead of black.
+        pub dark: Option<HexColor>,
+        /// The color of light modules in SVG codes, instead of white.
+        pub light: Option<HexColor>,
+    }
+    default!(
+        QrStyle = Self {
+            caption: false,
+            scale: None,
+            quiet_zone: true,
+            dark: None,
+            light: None,
+        }
+    );
+
+    impl QrStyle {
+        /// The smallest contrast ratio of dark on light modules for reliable
+        /// scanning.
+        pub const MIN_CONTRAST: f64 = 4.0;
+
+        /// The contrast ratio of dark on light modules, see
+        /// [`HexColor::contrast`].
+        pub fn contrast(&self) -> f64 {
+            let dark = self.dark.unwrap_or(HexColor([0x00; 3]));
+            let light = self.light.unwrap_or(HexColor([0xff; 3]));
+            dark.contrast(light)
+        }
+    }
+
+    /// How [`gen_qr`] renders QR code files.
+    #[derive(Debug, Clone, Default)]
+    pub struct QrFileOptions {
+        /// The file type to render.
+        pub ft: ImageOptions,
+        /// How to render the code.
+        pub style: QrStyle,
+        /// The transformation applied to the URL before it is encoded.
+        pub transform: PayloadTransform,
+        /// Whether to render SVG when PNG rendering fails.
+        pub fallback: bool,
+    }
+
+    /// Render a QR code into PNG-encoded bytes.  When `max_size` is set, the
+    /// image is scaled down to at most `max_size` pixels on each side, subject
+    /// to the minimum of one pixel per module.
+    pub fn render_png(
+        qr: &QrCode,
+        max_size: Option<u32>,
+    ) -> errors::Result<Vec<u8>> {
+        let mut renderer = qr.render::<Luma<u8>>();
+        if let Some(size) = max_size {
+            renderer.max_dimensions(size, size);
+        }
+
+        let mut bytes = Vec::new();
+        DynamicImage::ImageLuma8(renderer.build())
+            .write_to(&mut bytes, ImageOutputFormat::Png)?;
+        Ok(bytes)
+    }
+
+    /// Create a temporary directory for QR code files.  When the directory
+    /// cannot be created, return an error in `strict` mode, and otherwise
+    /// warn and return [`None`], so that callers skip generating files but
+    /// can still show the URLs.
+    pub fn temp_dir(strict: bool) -> errors::Result<Option<TempDir>> {
+        match tempfile::tempdir() {
+            Ok(dir) => Ok(Some(dir)),
+            Err(e) if strict => Err(errors::Error::TempDir(e.kind())),
+            Err(e) => {
+                log::warn!(
+                    "{}; skipping QR code files",
+                    errors::Error::TempDir(e.kind())
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Generate a QR code file from a digest, rendered with `options`.  A
+    /// non-global `addr` is replaced by a global address of the interfaces
+    /// considered by `bind`.  The file is created in `dir`, and so is removed
+    /// along with it when the [`TempDir`] is dropped.
+    pub async fn gen_qr(
         addr: SocketAddr,
         digest: &str,
         method: &str, // sha512
         scheme: &str, // http
-        ft: ImageOptions,
-        dir: &'dir TempDir,
+        options: &QrFileOptions,
+        bind: &BindOptions,
+        dir: &TempDir,
     ) -> errors::Result<PathBuf> {
         let host = addr.ip();
-        let host = if is_global_4(&host) {
+        let host = if is_global_4(&host) || is_global_6(&host) {
             host
         } else {
-            get_first_net(is_global_4).ok_or(errors::Error::NoGlobalIpv4)?
+            bind.global_host().ok_or_else(|| {
+                errors::Error::NoGlobalIp(bind.interface.clone())
+            })?
         };
-        let port = addr.port();
+        // IPv6 hosts are bracketed
+        let addr = SocketAddr::new(host, addr.port());
 
         // construct and validate URL
-        let url =
-            format!("{}://{}:{}/{}/?h={}", scheme, host, port, method, digest);
+        let url = format!("{}://{}/{}/?h={}", scheme, addr, method, digest);
         let _: Uri =
             url.parse().map_err(|_| errors::Error::Uri(url.clone()))?;
 
+        let (ft, bytes) = render_qr_with_fallback(
+            &options.transform.apply(&url),
+            options.ft,
+            &options.style,
+            options.fallback,
+        )?;
         let path = dir.path().join(format!("{}_{}.{}", method, "qrshare", ft));
+        let mut file = File::create(&path).await?;
+        file.write_all(&bytes).await?;
+        file.flush().await?;
+
+        Ok(path)
+    }
+
+    /// Save a QR code of `url` to `path` in the file type `ft` and `style`,
+    /// overwriting any existing file.
+    pub async fn save_qr(
+        url: &str,
+        ft: ImageOptions,
+        style: &QrStyle,
+        path: &Path,
+    ) -> errors::Result<()> {
+        let bytes = render_qr(url, ft, style)?;
+        let mut file = File::create(path).await?;
+        file.write_all(&bytes).await?;
+        file.flush().await?;
 
+        Ok(())
+    }
+
+    /// Like [`render_qr`], but when PNG rendering fails and `fallback` is
+    /// set, log a warning and render SVG instead.  Return the file type
+    /// actually rendered along with the bytes.
+    pub fn render_qr_with_fallback(
+        url: &str,
+        ft: ImageOptions,
+        style: &QrStyle,
+        fallback: bool,
+    ) -> errors::Result<(ImageOptions, Vec<u8>)> {
+        fallback_to_svg(ft, fallback, |ft| render_qr(url, ft, style))
+    }
+
+    /// Render in the file type `ft` with `render`, falling back to SVG when
+    /// PNG rendering fails and `fallback` is set.
+    fn fallback_to_svg(
+        ft: ImageOptions,
+        fallback: bool,
+        render: impl Fn(ImageOptions) -> errors::Result<Vec<u8>>,
+    ) -> errors::Result<(ImageOptions, Vec<u8>)> {
+        match (ft, render(ft)) {
+            (_, Ok(bytes)) => Ok((ft, bytes)),
+            (ImageOptions::Png, Err(e)) if fallback => {
+                log::warn!("Cannot render PNG QR code, using SVG: {}", e);
+                Ok((ImageOptions::Svg, render(ImageOptions::Svg)?))
+            }
+            (_, Err(e)) => Err(e),
+        }
+    }
+
+    /// Render a QR code of `url` into bytes in the file type `ft` and
+    /// `style`.
+    pub fn render_qr(
+        url: &str,
+        ft: ImageOptions,
+        style: &QrStyle,
+    ) -> errors::Result<Vec<u8>> {
         let qr = QrCode::new(url)?;
         match ft {
-            ImageOptions::None => {
-                return Err(errors::Error::IO(ErrorKind::Other))
+            ImageOptions::None => Err(errors::Error::IO(ErrorKind::Other)),
+            ImageOptions::Png => {
+                let mut renderer = qr.render::<Luma<u8>>();
+                renderer.quiet_zone(style.quiet_zone);
+                if let Some(scale) = style.scale {
+                    renderer.module_dimensions(scale, scale);
+                }
+                let mut image = renderer.build();
+                if style.caption {
+                    image = super::caption::png(&image, url)?;
+                }
+                let mut bytes = Vec::new();
+                DynamicImage::ImageLuma8(image)
+                    .write_to(&mut bytes, ImageOutputFormat::Png)?;
+                Ok(bytes)
             }
-            ImageOptions::Png => qr.render::<Luma<u8>>().build().save(&path)?,
             ImageOptions::Svg => {
-                let mut file = File::create(&path).await?;
-                file.write_all(qr.render::<svg::Color>().build().as_bytes())
-                    .await?;
-                file.flush().await?;
+                let dark = style.dark.map(|color| color.to_string());
+                let light = style.light.map(|color| color.to_string());
+                let mut renderer = qr.render::<svg::Color>();
+                if let Some(dark) = &dark {
+                    renderer.dark_color(svg::Color(dark));
+                }
+                if let Some(light) = &light {
+                    renderer.light_color(svg::Color(light));
+                }
+                renderer.quiet_zone(style.quiet_zone);
+                if let Some(scale) = style.scale {
+                    renderer.module_dimensions(scale, scale);
+                }
+                let mut svg = renderer.build();
+                if style.caption {
+                    svg = super::caption::svg(&svg, url);
+                }
+                Ok(svg.into_bytes())
             }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{fallback_to_svg, render_qr, QrStyle};
+        use crate::{
+            config::{HexColor, ImageOptions},
+            errors::Error,
         };
 
-        Ok(path)
+        #[test]
+        fn test_render_qr_style() {
+            // a version 1 code has 21 modules, and the quiet zone adds 8
+            for (quiet_zone, size) in [(true, 290), (false, 210)] {
+                let style = QrStyle {
+                    scale: Some(10),
+                    quiet_zone,
+                    ..QrStyle::default()
+                };
+                let svg = render_qr("a", ImageOptions::Svg, &style).unwrap();
+                let svg = String::from_utf8(svg).unwrap();
+                let dimensions = format!(r#"width="{0}" height="{0}""#, size);
+                assert!(svg.contains(&dimensions), "{}", svg);
+            }
+        }
+
+        #[test]
+        fn test_render_qr_colors() {
+            let style = QrStyle {
+                dark: Some(HexColor([0x11, 0x22, 0x33])),
+                light: Some(HexColor([0xee, 0xdd, 0xcc])),
+                ..QrStyle::default()
+            };
+            assert!(style.contrast() >= QrStyle::MIN_CONTRAST);
+            let svg = render_qr("a", ImageOptions::Svg, &style).unwrap();
+            let svg = String::from_utf8(svg).unwrap();
+            assert!(svg.contains("#112233"), "{}", svg);
+            assert!(svg.contains("#eeddcc"), "{}", svg);
+
+            let style = QrStyle { dark: style.light, ..QrStyle::default() };
+            assert!(style.contrast() < QrStyle::MIN_CONTRAST);
+        }
+
+        /// Simulate a PNG encoding failure.
+        fn render(ft: ImageOptions) -> crate::errors::Result<Vec<u8>> {
+            match ft {
+                ImageOptions::Png => Err(Error::InvalidArg("png".to_owned())),
+                _ => Ok(b"<svg/>".to_vec()),
+            }
+        }
+
+        #[test]
+        fn test_fallback_to_svg() {
+            let (ft, bytes) =
+                fallback_to_svg(ImageOptions::Png, true, render).unwrap();
+            assert!(matches!(ft, ImageOptions::Svg));
+            assert_eq!(bytes, b"<svg/>");
+
+            assert!(fallback_to_svg(ImageOptions::Png, false, render).is_err());
+        }
+    }
+}
+
+/// Render the encoded URL as human-readable text beneath the QR code
+pub mod caption {
+    use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+    use image::{GenericImage, GrayImage, Luma};
+
+    use crate::{errors, utils::xml_escape};
+
+    /// The bundled monospace font.  See `assets/DejaVuSansMono.LICENSE`.
+    const FONT: &[u8] = include_bytes!("../assets/DejaVuSansMono.ttf");
+
+    /// Long captions are shrunk to fit within this many lines, and wrapped
+    /// into more lines when already at [`MIN_FONT_SIZE`].
+    const MAX_LINES: usize = 3;
+
+    /// The smallest font size, in pixels.
+    const MIN_FONT_SIZE: f32 = 8.0;
+
+    /// The caption layout: the font size, the line height and the ascent in
+    /// pixels, and the wrapped lines.
+    struct Layout {
+        size: f32,
+        line_height: f32,
+        ascent: f32,
+        lines: Vec<String>,
+    }
+
+    impl Layout {
+        /// Lay out `text` beneath a QR code `width` pixels wide.  The text is
+        /// hard-wrapped since URLs rarely contain spaces.
+        fn new(font: &FontRef, width: u32, text: &str) -> Self {
+            // monospace: every character has the same advance
+            let advance = font
+                .as_scaled(PxScale::from(1.0))
+                .h_advance(font.glyph_id('0'));
+            let usable = width as f32 * 0.9;
+            let chars: Vec<char> = text.chars().collect();
+
+            // use the fewest lines that keep the font at least half of the
+            // largest size, then the most lines allowed
+            let max_size = width as f32 / 16.0;
+            let size_for = |lines: usize| {
+                let per_line = chars.len().max(1).div_ceil(lines);
+                (usable / (per_line as f32 * advance)).min(max_size)
+            };
+            let size = (1..MAX_LINES)
+                .map(size_for)
+                .find(|&size| size >= max_size / 2.0)
+                .unwrap_or_else(|| size_for(MAX_LINES))
+                .max(MIN_FONT_SIZE);
+            let per_line = ((usable / (size * advance)) as usize).max(1);
+
+            let scaled = font.as_scaled(PxScale::from(size));
+            Self {
+                size,
+                line_height: scaled.height() + scaled.line_gap(),
+                ascent: scaled.ascent(),
+                lines: chars
+                    .chunks(per_line)
+                    .map(|line| line.iter().collect())
+                    .collect(),
+            }
+        }
+
+        /// The total height of the caption, including a bottom margin of
+        /// half a line.
+        fn height(&self) -> u32 {
+            (self.line_height * (self.lines.len() as f32 + 0.5)).ceil() as u32
+        }
+    }
+
+    fn font() -> FontRef<'static> {
+        // the bundled font is always valid
+        FontRef::try_from_slice(FONT).unwrap()
+    }
+
+    /// Append `text` beneath a QR code image.  The image, including its
+    /// quiet zone, is left untouched.
+    pub fn png(qr: &GrayImage, text: &str) -> errors::Result<GrayImage> {
+        let font = font();
+        let layout = Layout::new(&font, qr.width(), text);
+        let scaled = font.as_scaled(PxScale::from(layout.size));
+
+        let mut image = GrayImage::from_pixel(
+            qr.width(),
+            qr.height() + layout.height(),
+            Luma([u8::MAX]),
+        );
+        image.copy_from(qr, 0, 0)?;
+
+        for (i, line) in layout.lines.iter().enumerate() {
+            let line_width: f32 = line
+                .chars()
+                .map(|c| scaled.h_advance(font.glyph_id(c)))
+                .sum();
+            let mut x = (qr.width() as f32 - line_width) / 2.0;
+            let y = qr.height() as f32
+                + i as f32 * layout.line_height
+                + layout.ascent;
+
+            for c in line.chars() {
+                let mut glyph = scaled.scaled_glyph(c);
+                glyph.position = point(x, y);
+                x += scaled.h_advance(glyph.id);
+
+                let outline = match font.outline_glyph(glyph) {
+                    Some(outline) => outline,
+                    None => continue,
+                };
+                let bounds = outline.px_bounds();
+                outline.draw(|gx, gy, coverage| {
+                    let px = bounds.min.x as i64 + gx as i64;
+                    let py = bounds.min.y as i64 + gy as i64;
+                    if (0..image.width() as i64).contains(&px)
+                        && (0..image.height() as i64).contains(&py)
+                    {
+                        let pixel = image.get_pixel_mut(px as u32, py as u32);
+                        let ink = (f32::from(u8::MAX) * (1.0 - coverage)) as u8;
+                        pixel.0[0] = pixel.0[0].min(ink);
+                    }
+                });
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Append `text` beneath an SVG QR code rendered by [`qrcode`], as
+    /// centered `<text>` elements.  An unrecognized SVG is returned as-is.
+    pub fn svg(qr: &str, text: &str) -> String {
+        let dimensions = qr
+            .split_once(r#"viewBox="0 0 "#)
+            .and_then(|(_, rest)| rest.split_once('"'))
+            .and_then(|(view_box, _)| view_box.split_once(' '))
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+        let (width, height): (u32, u32) = match dimensions {
+            Some(dimensions) => dimensions,
+            None => return qr.to_owned(),
+        };
+
+        let layout = Layout::new(&font(), width, text);
+        let new_height = height + layout.height();
+
+        // extend the canvas and the background, then append the text
+        let mut svg = qr
+            .replace(
+                &format!(r#"viewBox="0 0 {} {}""#, width, height),
+                &format!(r#"viewBox="0 0 {} {}""#, width, new_height),
+            )
+            .replace(
+                &format!(r#"height="{}""#, height),
+                &format!(r#"height="{}""#, new_height),
+            );
+        let end = svg.rfind("</svg>").unwrap_or(svg.len());
+        let text: String = layout
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                format!(
+                    concat!(
+                        r#"<text x="{x}" y="{y}" font-family="monospace""#,
+                        r#" font-size="{size}" text-anchor="middle">"#,
+                        r#"{line}</text>"#,
+                    ),
+                    x = width as f32 / 2.0,
+                    y = height as f32
+                        + i as f32 * layout.line_height
+                        + layout.ascent,
+                    size = layout.size,
+                    line = xml_escape(line),
+                )
+            })
+            .collect();
+        svg.insert_str(end, &text);
+        svg
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use image::{GenericImageView, Luma};
+        use qrcode::{render::svg, QrCode};
+
+        const URL: &str = "http://192.0.2.1:8080/sha512/?h=0123456789abcdef\
+            0123456789abcdef0123456789abcdef0123456789abcdef\
+            0123456789abcdef0123456789abcdef0123456789abcdef&x=<y>";
+
+        #[test]
+        fn test_png() {
+            let qr = QrCode::new(URL).unwrap().render::<Luma<u8>>().build();
+            let image = super::png(&qr, URL).unwrap();
+
+            assert_eq!(image.width(), qr.width());
+            assert!(image.height() > qr.height());
+            // the code is unobstructed
+            assert!(image
+                .view(0, 0, qr.width(), qr.height())
+                .pixels()
+                .zip(qr.pixels())
+                .all(|((_, _, a), b)| a == *b));
+            // the caption is drawn
+            assert!(image
+                .view(0, qr.height(), qr.width(), image.height() - qr.height())
+                .pixels()
+                .any(|(_, _, p)| p.0[0] < u8::MAX / 2));
+        }
+
+        #[test]
+        fn test_svg() {
+            let qr = QrCode::new(URL).unwrap().render::<svg::Color>().build();
+            let image = super::svg(&qr, URL);
+
+            assert!(image.len() > qr.len());
+            assert!(image.ends_with("</text></svg>"));
+            assert!(image.contains("&amp;x=&lt;y&gt;"));
+            assert!(!image.contains("<y>"));
+        }
     }
 }
 
@@ -87,10 +573,40 @@ pub mod gen {
 pub mod show {
     use std::path::Path;
 
+    use qrcode::{render::unicode::Dense1x2, QrCode};
+
     use crate::errors;
 
     /// Show a QR code for the path.  See [`open`] crate for further details.
     pub async fn qr_show(qr_path: impl AsRef<Path>) -> errors::Result<()> {
         Ok(open::that(qr_path.as_ref().as_os_str())?)
     }
+
+    /// Render a QR code of `url` as text for a terminal, with two modules
+    /// per character using Unicode half blocks.  The colors are inverted for
+    /// the usual light-on-dark terminal, so that dark modules are blanks.
+    pub fn qr_to_terminal(url: &str) -> errors::Result<String> {
+        Ok(QrCode::new(url)?
+            .render::<Dense1x2>()
+            .dark_color(Dense1x2::Light)
+            .light_color(Dense1x2::Dark)
+            .build())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::qr_to_terminal;
+
+        #[test]
+        fn test_qr_to_terminal() {
+            let url = "http://192.168.100.100:8080/base/list.html";
+            let qr = qr_to_terminal(url).unwrap();
+            let lines: Vec<_> = qr.lines().collect();
+            let width = lines[0].chars().count();
+            assert!(width <= 80, "{}", qr);
+            // half blocks make the code roughly square
+            assert_eq!(lines.len(), width.div_ceil(2), "{}", qr);
+            assert!(lines.iter().all(|line| line.chars().count() == width));
+        }
+    }
 }
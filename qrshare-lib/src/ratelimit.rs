@@ -0,0 +1,213 @@
+//! This module implements a token bucket for rate limiting, a counter of
+//! concurrent requests per client, and bandwidth throttling of response
+//! bodies.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use actix_web::{body::MessageBody, web::Bytes};
+use tokio::time::{self, Sleep};
+
+/// A token bucket, which holds up to `capacity` tokens and is refilled at a
+/// constant rate.  Each permitted request takes one token.
+#[derive(Debug, Clone, Copy)]
+pub struct Bucket {
+    /// The currently available tokens.
+    tokens: f64,
+    /// The last time the bucket was refilled.
+    last: Instant,
+}
+
+impl Bucket {
+    /// Create a full bucket.
+    pub fn new(capacity: f64) -> Self {
+        Self { tokens: capacity, last: Instant::now() }
+    }
+
+    /// Try to take one token, after refilling at `rate` tokens per second up
+    /// to `capacity` tokens.  When the bucket is empty, return how long until
+    /// the next token is available.
+    pub fn take(&mut self, rate: f64, capacity: f64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    /// Whether the bucket would be full at `now`, in which case it holds no
+    /// information and can be dropped.
+    pub fn is_full(&self, rate: f64, capacity: f64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens + elapsed * rate >= capacity
+    }
+}
+
+/// The number of active requests, keyed by client address.  This uses a
+/// synchronous lock, as the count is decremented on drop.
+#[derive(Debug, Clone, Default)]
+pub struct Active(Arc<Mutex<HashMap<IpAddr, u32>>>);
+
+impl Active {
+    /// Count one more active request from `ip`, unless there are already
+    /// `limit` of them.  The request stays active until the returned guard
+    /// is dropped.
+    pub fn acquire(&self, ip: IpAddr, limit: u32) -> Option<ActiveGuard> {
+        let mut active = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let count = active.entry(ip).or_default();
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(ActiveGuard { active: self.clone(), ip })
+    }
+
+    /// The number of active requests from `ip`.
+    pub fn count(&self, ip: IpAddr) -> u32 {
+        let active = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        active.get(&ip).copied().unwrap_or_default()
+    }
+}
+
+/// An active request counted in [`Active`], until dropped.
+#[derive(Debug)]
+pub struct ActiveGuard {
+    active: Active,
+    ip: IpAddr,
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        let mut active =
+            self.active.0.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = active.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// A response body holding an [`ActiveGuard`], so that the request stays
+/// active until the body is fully sent or the client disconnects, either of
+/// which drops the body.
+pub struct GuardedBody<B> {
+    body: B,
+    _guard: ActiveGuard,
+}
+
+impl<B> GuardedBody<B> {
+    pub const fn new(body: B, guard: ActiveGuard) -> Self {
+        Self { body, _guard: guard }
+    }
+}
+
+impl<B: MessageBody + Unpin> MessageBody for GuardedBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> actix_web::body::BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Pin::new(&mut self.get_mut().body).poll_next(cx)
+    }
+}
+
+/// A response body sent at no more than `bps` bytes per second.  Each chunk
+/// is held back until the previous chunks would have been sent at that rate,
+/// without saving up the time while the client is not reading.
+pub struct ThrottledBody<B> {
+    body: B,
+    /// The maximum rate, in bytes per second.
+    bps: u64,
+    /// The chunk held back until `sleep` completes.
+    pending: Option<Bytes>,
+    /// When the chunks so far are sent at the maximum rate.
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl<B> ThrottledBody<B> {
+    /// Throttle `body` to `bps` bytes per second, which must not be 0.
+    pub fn new(body: B, bps: u64) -> Self {
+        let sleep = Box::pin(time::sleep(Duration::ZERO));
+        Self { body, bps, pending: None, sleep }
+    }
+}
+
+impl<B: MessageBody + Unpin> MessageBody for ThrottledBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> actix_web::body::BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        if this.pending.is_none() {
+            match ready!(Pin::new(&mut this.body).poll_next(cx)) {
+                Some(Ok(chunk)) => {
+                    let delay = chunk.len() as f64 / this.bps as f64;
+                    let start = this.sleep.deadline().max(time::Instant::now());
+                    this.sleep
+                        .as_mut()
+                        .reset(start + Duration::from_secs_f64(delay));
+                    this.pending = Some(chunk);
+                }
+                other => return Poll::Ready(other),
+            }
+        }
+        ready!(this.sleep.as_mut().poll(cx));
+        Poll::Ready(this.pending.take().map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Active, Bucket};
+
+    #[test]
+    fn test_bucket() {
+        let mut bucket = Bucket::new(3.0);
+        for _ in 0..3 {
+            assert_eq!(bucket.take(1.0, 3.0), Ok(()));
+        }
+
+        // the bucket is empty, and refills one token per second
+        let wait = bucket.take(1.0, 3.0).unwrap_err();
+        assert!(wait.as_secs_f64() > 0.9 && wait.as_secs_f64() <= 1.0);
+    }
+
+    #[test]
+    fn test_active() {
+        let active = Active::default();
+        let ip = [127, 0, 0, 1].into();
+        let guard = active.acquire(ip, 1).unwrap();
+        assert!(active.acquire(ip, 1).is_none());
+        assert!(active.acquire([127, 0, 0, 2].into(), 1).is_some());
+
+        drop(guard);
+        assert_eq!(active.count(ip), 0);
+        assert!(active.acquire(ip, 1).is_some());
+    }
+}
@@ -0,0 +1,35 @@
+//! This module generates thumbnails for image files.
+
+use std::path::Path;
+
+use image::{
+    imageops::FilterType, DynamicImage, GenericImageView, ImageFormat,
+    ImageOutputFormat,
+};
+
+use crate::errors;
+
+/// The JPEG quality of generated thumbnails.
+const JPEG_QUALITY: u8 = 80;
+
+/// Generate a JPEG thumbnail at most `width` pixels wide for the image at
+/// `path`, preserving its aspect ratio.  Images narrower than `width` are not
+/// enlarged.  Return [`None`] when the file is not a recognized image format.
+pub fn thumbnail(path: &Path, width: u32) -> errors::Result<Option<Vec<u8>>> {
+    if ImageFormat::from_path(path).is_err() {
+        return Ok(None);
+    }
+
+    let img = image::open(path)?;
+    let img = if img.width() > width {
+        img.resize(width, u32::MAX, FilterType::Triangle)
+    } else {
+        img
+    };
+
+    // JPEG does not support transparency
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgb8(img.to_rgb8())
+        .write_to(&mut bytes, ImageOutputFormat::Jpeg(JPEG_QUALITY))?;
+    Ok(Some(bytes))
+}
@@ -0,0 +1,82 @@
+//! This module builds the [`rustls`] server configuration from the TLS
+//! options.
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use rustls::{
+    server::AllowAnyAuthenticatedClient, Certificate, PrivateKey,
+    RootCertStore, ServerConfig,
+};
+use rustls_pemfile::Item;
+
+use crate::{
+    config::TlsOptions,
+    errors::{self, Error},
+};
+
+/// Read all PEM items from a file.
+fn read_pem(path: &Path) -> errors::Result<Vec<Item>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(rustls_pemfile::read_all(&mut reader)?)
+}
+
+/// Read all certificates from a PEM file.
+fn read_certs(path: &Path) -> errors::Result<Vec<Certificate>> {
+    let certs: Vec<_> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(cert) => Some(Certificate(cert)),
+            _ => None,
+        })
+        .collect();
+
+    if certs.is_empty() {
+        Err(Error::InvalidFile(path.to_owned()))
+    } else {
+        Ok(certs)
+    }
+}
+
+/// Read the first private key from a PEM file.
+fn read_key(path: &Path) -> errors::Result<PrivateKey> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => {
+                Some(PrivateKey(key))
+            }
+            _ => None,
+        })
+        .ok_or_else(|| Error::InvalidFile(path.to_owned()))
+}
+
+/// Build the server configuration, or [`None`] when TLS is not configured.
+/// When a client CA is configured, clients must present a certificate signed
+/// by that CA, or the connection is rejected during the TLS handshake.
+pub fn server_config(
+    opts: &TlsOptions,
+) -> errors::Result<Option<ServerConfig>> {
+    let (cert, key) = match (&opts.tls_cert, &opts.tls_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if opts.tls_client_ca.is_none() => return Ok(None),
+        _ => return Err(Error::ArgConflict),
+    };
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &opts.tls_client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca)? {
+                roots
+                    .add(&cert)
+                    .map_err(|_| Error::InvalidFile(ca.to_owned()))?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(
+                roots,
+            ))
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(Some(builder.with_single_cert(read_certs(cert)?, read_key(key)?)?))
+}
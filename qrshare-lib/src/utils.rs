@@ -1,12 +1,98 @@
-use std::mem::replace;
+use std::{
+    mem::replace,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use hyper::{Response, StatusCode};
+use percent_encoding::{
+    percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
-pub fn query_split(s: &str) -> Vec<(&str, &str)> {
-    s.split('&').filter_map(|p| p.split_once('=')).collect()
+/// The characters to percent-encode in a URL path segment.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Escape `text` for XML or HTML character data and attribute values.
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Format a size in bytes for humans, in binary units, e.g. `1.5 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+/// Format a time in ISO 8601 in UTC, to the second, e.g.
+/// `2022-08-01T12:00:00Z`.
+pub fn format_time(time: SystemTime) -> String {
+    let time = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+        Err(_) => time,
+    };
+    OffsetDateTime::from(time)
+        .format(&Rfc3339)
+        .unwrap_or_else(|_| "-".to_owned())
+}
+
+/// Encode `data` of the MIME type `mime` into a `data:` URI.
+pub fn data_uri(mime: &str, data: &[u8]) -> String {
+    format!("data:{};base64,{}", mime, base64::encode(data))
+}
+
+/// Percent-encode each segment of a `/`-separated path, for use in a URL.
+pub fn path_encode(path: &str) -> String {
+    path.split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Decode a query string component, where `+` stands for a space.  Invalid
+/// UTF-8 sequences are replaced.
+fn query_decode(s: &str) -> String {
+    percent_decode_str(&s.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Split a query string into decoded key-value pairs.  Pairs without `=` are
+/// skipped.
+pub fn query_split(s: &str) -> Vec<(String, String)> {
+    s.split('&')
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (query_decode(k), query_decode(v)))
+        .collect()
 }
 
-pub fn query_split_opt(s: Option<&str>) -> Vec<(&str, &str)> {
+pub fn query_split_opt(s: Option<&str>) -> Vec<(String, String)> {
     match s {
         Some(s) => query_split(s),
         None => vec![],
@@ -30,3 +116,61 @@ where
 {
     swap_status(Response::new("".into()), s).0
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{
+        data_uri, format_size, format_time, query_split, query_split_opt,
+        xml_escape,
+    };
+
+    #[test]
+    fn test_data_uri() {
+        assert_eq!(
+            data_uri("text/plain", b"hi"),
+            "data:text/plain;base64,aGk="
+        );
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 << 30), "5.0 GiB");
+    }
+
+    #[test]
+    fn test_format_time() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_659_355_200_500);
+        assert_eq!(format_time(time), "2022-08-01T12:00:00Z");
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(
+            xml_escape(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_query_split() {
+        assert_eq!(
+            query_split("h=abc&q=a%20b+c&p=100%25&k%3D=v%26&flag"),
+            [
+                ("h".into(), "abc".into()),
+                ("q".into(), "a b c".into()),
+                ("p".into(), "100%".into()),
+                ("k=".into(), "v&".into()),
+            ]
+        );
+        // malformed escapes are kept as-is
+        assert_eq!(
+            query_split("q=%zz%2"),
+            [("q".to_owned(), "%zz%2".to_owned())]
+        );
+        assert!(query_split_opt(None).is_empty());
+    }
+}
@@ -1,7 +1,11 @@
-use std::path::PathBuf;
+use std::{
+    io::{stdout, IsTerminal},
+    path::PathBuf,
+};
 
-use lib::config::Config;
+use lib::{config::Config, errors};
 use log::Level;
+use merge::Merge;
 
 /// A [`Cli`] is the collection of all options configurable from the
 /// command-line arguments.
@@ -16,21 +20,81 @@ pub struct Cli {
     #[clap(long, value_parser)]
     debug_print: bool,
 
+    /// Debug use only: delay each download response by this many milliseconds,
+    /// to simulate a slow network.
+    #[cfg(debug_assertions)]
+    #[clap(long, value_parser)]
+    pub debug_delay: Option<u64>,
+
+    /// Debug use only: delay hashing each queued file by this many
+    /// milliseconds, to simulate large files.
+    #[cfg(debug_assertions)]
+    #[clap(long, value_parser)]
+    pub debug_hash_delay: Option<u64>,
+
     /// General configurations, which may come from a configuration file.
     #[clap(flatten)]
     pub config: Config,
 
-    /// The paths of files to serve.  There should be at least one file to
-    /// serve.
+    /// The paths of files to serve, where `-` serves the standard input as a
+    /// file named `stdin-name`.  FIFOs, such as those from process
+    /// substitution, are read once into a temporary file.  There should be at
+    /// least one file to serve, unless `watch` is set.
     #[clap(value_parser)]
     pub files: Vec<PathBuf>,
 
     /// The log level to use.
     #[clap(short = 'L', long, value_parser, default_value_t = Level::Warn)]
     pub log_level: Level,
+
+    /// Read additional configurations in TOML format from the environment
+    /// variable `QRSHARE_CONFIG`.  Command-line options take precedence.
+    #[clap(long, value_parser)]
+    pub config_env: bool,
+
+    /// Print the effective configuration in TOML format, with secrets and
+    /// deployment details redacted as in `/config`, and terminate.
+    #[clap(long, value_parser)]
+    pub print_config: bool,
+
+    /// Do not colorize log messages.  Colors are also disabled when the
+    /// `NO_COLOR` environment variable is set, or when the standard output is
+    /// not a terminal.
+    #[clap(long, value_parser)]
+    pub no_color: bool,
+
+    /// Do not print the QR codes of the file listing URLs to the standard
+    /// output on startup.
+    #[clap(long, value_parser)]
+    pub no_qr_terminal: bool,
+
+    /// Do not compress responses, even when clients accept `gzip` or `br`
+    /// encoding.  Downloaded files are never compressed.
+    #[clap(long, value_parser)]
+    pub no_compress: bool,
 }
 
 impl Cli {
+    /// Merge configurations from other sources into the command-line
+    /// configurations, where the command-line values take precedence.
+    pub fn merge_config(&mut self) -> errors::Result<()> {
+        if self.config_env {
+            let mut config = Config::from_env(Config::ENV_VAR)?;
+            config.expand_paths(
+                self.config.strict.or(config.strict).unwrap_or(false),
+            )?;
+            self.config.merge(config);
+        }
+        Ok(())
+    }
+
+    /// Whether log messages should be colorized.
+    pub fn use_colors(&self) -> bool {
+        !self.no_color
+            && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            && stdout().is_terminal()
+    }
+
     #[cfg(debug_assertions)]
     #[inline]
     pub fn parse() -> Self {
@@ -1,4 +1,5 @@
 mod cli;
+mod middleware;
 mod server;
 mod services;
 
@@ -8,20 +9,30 @@ use log::LevelFilter;
 use simple_logger::SimpleLogger;
 
 fn main() -> errors::Result<()> {
+    let cli = Cli::parse();
+
     SimpleLogger::new()
-        .with_colors(true)
+        .with_colors(cli.use_colors())
         .with_level(LevelFilter::Debug)
         .with_module_level("qrshare", LevelFilter::Trace)
+        // every mDNS packet is logged at the debug level
+        .with_module_level("mdns_sd", LevelFilter::Info)
         .env()
         .init()
         .unwrap();
 
-    main_actix()
+    main_actix(cli)
 }
 
 #[tokio::main]
-async fn main_actix() -> errors::Result<()> {
-    let server = Server::new(Cli::parse()).await?;
+async fn main_actix(mut cli: Cli) -> errors::Result<()> {
+    cli.merge_config()?;
+    if cli.print_config {
+        print!("{}", cli.config.resolve().redacted().to_toml()?);
+        return Ok(());
+    }
+
+    let server = Server::new(cli).await?;
     Server::start_actix(server).await?;
 
     Ok(())
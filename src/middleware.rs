@@ -0,0 +1,588 @@
+//! This module defines middlewares for actix-web.  See
+//! [`actix_web::dev::Transform`] for further information.
+
+use std::{
+    future::{ready, Ready},
+    io,
+    rc::Rc,
+    sync::Arc,
+};
+
+use actix_http::{
+    header::{
+        HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS,
+        ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+        ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+        ACCESS_CONTROL_REQUEST_METHOD, AUTHORIZATION, ORIGIN,
+        STRICT_TRANSPORT_SECURITY, VARY, WWW_AUTHENTICATE,
+    },
+    Method, StatusCode,
+};
+use actix_service::{forward_ready, Service, Transform};
+use actix_web::{
+    body::EitherBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{Accept, ContentType},
+    web::Data,
+    Error, HttpMessage, HttpResponse, ResponseError,
+};
+use build_html::{Html, HtmlContainer, HtmlPage};
+use futures::future::LocalBoxFuture;
+use lib::{
+    config::HashMethod, errors, hash::digest_hex, metrics::Metrics,
+    utils::xml_escape,
+};
+use subtle::ConstantTimeEq;
+
+use crate::Server;
+
+/// Add a `Strict-Transport-Security` header to every response served over
+/// HTTPS, either directly or as reported by a reverse proxy.
+#[derive(Debug, Clone, Copy)]
+pub struct Hsts {
+    /// The `max-age` directive, in seconds.
+    max_age: u64,
+}
+
+impl Hsts {
+    pub const fn new(max_age: u64) -> Self {
+        Self { max_age }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Hsts
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = HstsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let value = format!("max-age={}", self.max_age);
+        // a formatted integer is always a valid header value
+        let value = HeaderValue::from_str(&value).unwrap();
+        ready(Ok(HstsMiddleware { service, value }))
+    }
+}
+
+pub struct HstsMiddleware<S> {
+    service: S,
+    value: HeaderValue,
+}
+
+impl<S, B> Service<ServiceRequest> for HstsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let https = req.connection_info().scheme() == "https";
+        let value = self.value.clone();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut resp = fut.await?;
+            if https {
+                resp.headers_mut().insert(STRICT_TRANSPORT_SECURITY, value);
+            }
+            Ok(resp)
+        })
+    }
+}
+
+/// Count every response by its status code.
+#[derive(Debug, Clone)]
+pub struct ResponseMetrics {
+    metrics: Arc<Metrics>,
+}
+
+impl ResponseMetrics {
+    pub const fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ResponseMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ResponseMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ResponseMetricsMiddleware {
+            service,
+            metrics: Arc::clone(&self.metrics),
+        }))
+    }
+}
+
+pub struct ResponseMetricsMiddleware<S> {
+    service: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, B> Service<ServiceRequest> for ResponseMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let metrics = Arc::clone(&self.metrics);
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let resp = fut.await?;
+            metrics.count_status(resp.status().as_u16());
+            Ok(resp)
+        })
+    }
+}
+
+/// Serialize error responses as JSON when the request prefers
+/// `application/json`, see [`errors::Error::json_response`], and render those
+/// with 404 or 5xx status as a small HTML page when it prefers `text/html`.
+/// Otherwise they are left in plain text.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorPages;
+
+/// The format of an error response preferred by a request.
+enum ErrorFormat {
+    Text,
+    Json,
+    /// An HTML page linking to the file list under the base path.
+    Html(String),
+}
+
+/// The style of the HTML error pages.
+const ERROR_PAGE_STYLE: &str = "body { font-family: sans-serif; \
+    margin: 4em auto; max-width: 40em; color: #333; } \
+    h1 { font-weight: normal; }";
+
+/// The error response as an HTML page, linking to the file list under
+/// `base_path`.
+fn html_response(e: &errors::Error, base_path: &str) -> HttpResponse {
+    let status = e.status_code().to_string();
+    let mut page = HtmlPage::new()
+        .with_title(format!("QR Share: {}", status))
+        .with_style(ERROR_PAGE_STYLE)
+        .with_header(1, &status);
+    let message = e.message();
+    // plain status errors would repeat the header
+    if message != status {
+        page.add_paragraph(xml_escape(&message));
+    }
+    page.add_link(
+        xml_escape(&format!("{}/list.html", base_path)),
+        "Back to the file list",
+    );
+    e.response_builder()
+        .content_type(ContentType::html())
+        .body(page.to_html_string())
+}
+
+impl<S, B: 'static> Transform<S, ServiceRequest> for ErrorPages
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ErrorPagesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ErrorPagesMiddleware { service }))
+    }
+}
+
+pub struct ErrorPagesMiddleware<S> {
+    service: S,
+}
+
+impl<S, B: 'static> Service<ServiceRequest> for ErrorPagesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let preferred = req
+            .get_header::<Accept>()
+            .and_then(|accept| accept.ranked().into_iter().next());
+        let format = match preferred.as_ref().map(|mime| mime.essence_str()) {
+            Some("application/json") => ErrorFormat::Json,
+            Some("text/html") => ErrorFormat::Html(
+                req.app_data::<Data<Server>>()
+                    .map(|server| server.base_path.clone())
+                    .unwrap_or_default(),
+            ),
+            _ => ErrorFormat::Text,
+        };
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let resp = fut.await?;
+            let status = resp.status();
+            let page =
+                status == StatusCode::NOT_FOUND || status.is_server_error();
+            let e = resp
+                .response()
+                .error()
+                .and_then(|e| e.as_error::<errors::Error>());
+            let body = match (e, format) {
+                (Some(e), ErrorFormat::Json) => Some(e.json_response()),
+                (Some(e), ErrorFormat::Html(base_path)) if page => {
+                    Some(html_response(e, &base_path))
+                }
+                _ => None,
+            };
+            Ok(match body {
+                Some(body) => resp.into_response(body).map_into_right_body(),
+                None => resp.map_into_left_body(),
+            })
+        })
+    }
+}
+
+/// The routes, relative to the base path, that answer cross-origin requests
+/// from [`Server::cors_origins`].  Removing files with `DELETE` is allowed at
+/// any path.
+const CORS_ROUTES: [&str; 6] =
+    ["/list.json", "/serve", "/upload", "/config", "/health", "/health/ready"];
+
+/// How long browsers may cache the answer to a preflight request, in seconds.
+const CORS_MAX_AGE: u64 = 24 * 60 * 60;
+
+/// Allow cross-origin requests to the API routes in [`CORS_ROUTES`] from the
+/// origins in [`Server::cors_origins`], and answer their preflight `OPTIONS`
+/// requests.  Other requests are left untouched, so that browsers keep
+/// downloads and HTML pages same-origin.
+///
+/// This is not `actix-cors`, which applies one policy to everything it wraps
+/// and answers requests from other origins with 400 status.  Here the policy
+/// depends on the route below the base path and the method, and requests
+/// from other origins are served as usual, leaving the browser to withhold
+/// the response.  `Access-Control-Allow-Credentials` is never sent: scripts
+/// authenticate by setting `Authorization`, whose preflight is answered
+/// before [`BasicAuth`], and cookies are not used.
+#[derive(Debug, Clone, Copy)]
+pub struct Cors;
+
+impl<S, B: 'static> Transform<S, ServiceRequest> for Cors
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = CorsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CorsMiddleware { service }))
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+}
+
+/// The `Access-Control-Allow-Origin` value for a cross-origin request to an
+/// API route, or for its preflight request, from an allowed origin.
+fn allowed_origin(req: &ServiceRequest) -> Option<HeaderValue> {
+    let server = req.app_data::<Data<Server>>()?;
+    let origin = req.headers().get(ORIGIN)?;
+    // preflight requests name the method of the actual request
+    let method = if req.method() == Method::OPTIONS {
+        req.headers().get(ACCESS_CONTROL_REQUEST_METHOD)?.as_bytes()
+    } else {
+        req.method().as_str().as_bytes()
+    };
+    let path = req.path().strip_prefix(server.base_path.as_str())?;
+    if method != b"DELETE" && !CORS_ROUTES.contains(&path) {
+        return None;
+    }
+    let origins = &server.cors_origins;
+    if origins.iter().any(|allowed| allowed == "*") {
+        Some(HeaderValue::from_static("*"))
+    } else if origins
+        .iter()
+        .any(|allowed| origin.as_bytes() == allowed.as_bytes())
+    {
+        Some(origin.clone())
+    } else {
+        None
+    }
+}
+
+impl<S, B: 'static> Service<ServiceRequest> for CorsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let origin = match allowed_origin(&req) {
+            Some(origin) => origin,
+            None => {
+                let fut = self.service.call(req);
+                return Box::pin(
+                    async move { Ok(fut.await?.map_into_left_body()) },
+                );
+            }
+        };
+
+        if req.method() == Method::OPTIONS {
+            let mut resp = HttpResponse::NoContent();
+            resp.insert_header((ACCESS_CONTROL_ALLOW_ORIGIN, origin))
+                .insert_header((
+                    ACCESS_CONTROL_ALLOW_METHODS,
+                    "GET, POST, DELETE",
+                ))
+                .insert_header((ACCESS_CONTROL_MAX_AGE, CORS_MAX_AGE))
+                .insert_header((VARY, "Origin"));
+            if let Some(headers) =
+                req.headers().get(ACCESS_CONTROL_REQUEST_HEADERS)
+            {
+                resp.insert_header((
+                    ACCESS_CONTROL_ALLOW_HEADERS,
+                    headers.clone(),
+                ));
+            }
+            let resp = req.into_response(resp.finish()).map_into_right_body();
+            return Box::pin(ready(Ok(resp)));
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut resp = fut.await?;
+            let headers = resp.headers_mut();
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            headers.append(VARY, HeaderValue::from_static("Origin"));
+            Ok(resp.map_into_left_body())
+        })
+    }
+}
+
+/// The credentials for HTTP Basic authentication.  Only their salted digests
+/// are kept, and compared in constant time.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    /// Random for each process, so that the digests cannot be looked up in
+    /// precomputed tables.
+    salt: [u8; 16],
+    user: String,
+    pass: String,
+    /// Whether downloads require authentication too.
+    pub downloads: bool,
+}
+
+impl Credentials {
+    pub fn new(
+        user: &str,
+        pass: &str,
+        downloads: bool,
+    ) -> errors::Result<Self> {
+        let mut salt = [0; 16];
+        getrandom::getrandom(&mut salt).map_err(io::Error::from)?;
+        Ok(Self {
+            user: Self::digest(&salt, user.as_bytes()),
+            pass: Self::digest(&salt, pass.as_bytes()),
+            salt,
+            downloads,
+        })
+    }
+
+    fn digest(salt: &[u8], secret: &[u8]) -> String {
+        digest_hex(HashMethod::Sha512, [salt, secret].concat())
+    }
+
+    /// Whether the value of an `Authorization` header carries these
+    /// credentials.
+    pub fn verify(&self, authorization: &[u8]) -> bool {
+        let decoded = authorization
+            .strip_prefix(b"Basic ")
+            .and_then(|encoded| base64::decode(encoded).ok());
+        let (user, pass) = match decoded.as_deref().and_then(|decoded| {
+            let colon = decoded.iter().position(|&b| b == b':')?;
+            Some((&decoded[..colon], &decoded[colon + 1..]))
+        }) {
+            Some(credentials) => credentials,
+            None => return false,
+        };
+        let user = Self::digest(&self.salt, user);
+        let pass = Self::digest(&self.salt, pass);
+        // check both, so that the time does not reveal a valid user name
+        (user.as_bytes().ct_eq(self.user.as_bytes())
+            & pass.as_bytes().ct_eq(self.pass.as_bytes()))
+        .into()
+    }
+}
+
+/// Require HTTP Basic authentication with the credentials in
+/// [`Server::auth`], if configured, and respond with 401 status otherwise.
+/// Download routes only require it when configured for downloads.
+#[derive(Debug, Clone, Copy)]
+pub struct BasicAuth {
+    download: bool,
+}
+
+impl BasicAuth {
+    /// Protect a route that changes or reveals the server state.
+    pub const fn new() -> Self {
+        Self { download: false }
+    }
+
+    /// Protect a route that reveals served files or their metadata.
+    pub const fn download() -> Self {
+        Self { download: true }
+    }
+}
+
+impl<S, B: 'static> Transform<S, ServiceRequest> for BasicAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = BasicAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(BasicAuthMiddleware { service, download: self.download }))
+    }
+}
+
+pub struct BasicAuthMiddleware<S> {
+    service: S,
+    download: bool,
+}
+
+impl<S, B: 'static> Service<ServiceRequest> for BasicAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let authorized = match req
+            .app_data::<Data<Server>>()
+            .and_then(|server| server.auth.as_ref())
+        {
+            Some(auth) if !self.download || auth.downloads => req
+                .headers()
+                .get(AUTHORIZATION)
+                .is_some_and(|value| auth.verify(value.as_bytes())),
+            _ => true,
+        };
+
+        if !authorized {
+            log::info!("Unauthorized request: {}", req.path());
+            let resp = HttpResponse::Unauthorized()
+                .insert_header((
+                    WWW_AUTHENTICATE,
+                    r#"Basic realm="qrshare", charset="UTF-8""#,
+                ))
+                .finish();
+            let resp = req.into_response(resp).map_into_right_body();
+            return Box::pin(ready(Ok(resp)));
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move { Ok(fut.await?.map_into_left_body()) })
+    }
+}
+
+/// Limit the requests of each client as configured in [`Server::rate_limit`],
+/// and respond with 429 status otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit;
+
+impl<S, B: 'static> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    /// Shared with the response future, which first takes a token.
+    service: Rc<S>,
+}
+
+impl<S, B: 'static> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+        + 'static,
+    S::Future: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            if let Some(server) = req.app_data::<Data<Server>>().cloned() {
+                let ip = req.peer_addr().map(|addr| addr.ip());
+                if let Err(e) = server.limit_request(ip).await {
+                    return Ok(req.error_response(e).map_into_right_body());
+                }
+            }
+            Ok(service.call(req).await?.map_into_left_body())
+        })
+    }
+}
@@ -1,51 +1,536 @@
 use std::{
     collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
     fmt::Debug,
-    net::{SocketAddr, TcpListener},
-    path::PathBuf,
-    sync::Arc,
+    fs::Metadata,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
+use actix_http::StatusCode;
 use actix_web::{
-    middleware::{Compress, Logger},
-    web::{to, Data},
+    middleware::{Compress, Condition, Logger},
+    web::{scope, to, Data},
     App, HttpServer,
 };
 use either::Either;
 use futures::stream::FuturesUnordered;
-use sha2::{Digest, Sha512};
-use tokio::{io::AsyncReadExt, sync::RwLock, task::spawn};
+use subtle::ConstantTimeEq;
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::{watch, RwLock},
+    task::spawn,
+};
 
 use crate::{
     cli::Cli,
-    services::{default_service, enqueue_file, favicon, show_qr},
+    middleware::{
+        Cors, Credentials, ErrorPages, Hsts, RateLimit, ResponseMetrics,
+    },
+    services::{
+        browse_archive, browse_entry, default_service, download_tar,
+        download_zip, enqueue_file, favicon, get_signed, health, health_ready,
+        list_json, remove_file, show_config, show_feed, show_metrics, show_qr,
+        thumbnail, upload_file,
+    },
 };
 use lib::{
-    config::{BindOptions, ImageOptions},
+    config::{
+        BindOptions, Config, DigestEncoding, DuplicateOptions, EtagAlgorithm,
+        HashMethod, ImageOptions, ListTheme, TlsOptions, UrlScheme,
+    },
     errors::{self, Error},
-    file::asy,
+    file::{
+        asy::{self, DirWatcher, WalkFilter},
+        shared::ExtPolicy,
+        sync::expand_glob,
+    },
+    hash::{self, Hasher},
+    metrics::Metrics,
+    net::{tcp_listener, MdnsService},
+    qr::{
+        gen::{save_qr, PayloadTransform, QrStyle},
+        show::qr_to_terminal,
+    },
+    ratelimit::{Active, ActiveGuard, Bucket},
+    tls::server_config,
 };
 
+#[cfg(unix)]
+use lib::net::unix_listener;
+
 use super::services::{get_sha512, list_files};
 
-/// The default buffer size, in bytes
-const DEFAULT_BUFSIZE: usize = 1024;
+/// How often expired files are purged.
+const PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a watched directory must be unchanged before its changed files
+/// are served, so that a large copy is hashed once.
+const WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Generated thumbnails, keyed by digest and one of a few fixed widths.
+type Thumbnails = HashMap<(String, u32), Vec<u8>>;
+
+/// A served file, as recorded in the manifest.  See
+/// [`Server::write_manifest`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ManifestEntry {
+    pub digest: String,
+    pub filename: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub downloads: u64,
+}
+
+/// Per-file limits of enqueued files, overriding the configured ones when
+/// set.  See [`Server::enqueue_with`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileLimits {
+    /// How long the files are served.
+    pub ttl: Option<Duration>,
+    /// How many times each file is downloaded before it stops being served.
+    pub max_downloads: Option<u64>,
+}
+
+/// A hashed file, as recorded in the state file.  See
+/// [`Server::write_state`].
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct StateEntry {
+    path: PathBuf,
+    digest: String,
+    etag: Option<String>,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// The content of the state file.  Cached digests are only valid for the
+/// same hash algorithms and digest encoding.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct State {
+    hash: HashMethod,
+    /// Missing in state files written before digests could be encoded.
+    #[serde(default)]
+    encoding: DigestEncoding,
+    etag: Option<HashMethod>,
+    files: Vec<StateEntry>,
+}
+
+/// Read the cached digests in the state file at `path`, keyed by path.  A
+/// missing or unreadable state file, or one written with other hash
+/// algorithms or digest encoding, is treated as empty.
+async fn load_state(
+    path: &Path,
+    hash: HashMethod,
+    encoding: DigestEncoding,
+    etag: Option<HashMethod>,
+) -> HashMap<PathBuf, StateEntry> {
+    let state = match tokio::fs::read(path).await {
+        Ok(json) => {
+            serde_json::from_slice::<State>(&json).map_err(|e| e.to_string())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return HashMap::new()
+        }
+        Err(e) => Err(e.to_string()),
+    };
+    match state {
+        Ok(state)
+            if state.hash == hash
+                && state.encoding == encoding
+                && state.etag == etag =>
+        {
+            state
+                .files
+                .into_iter()
+                .map(|entry| (entry.path.clone(), entry))
+                .collect()
+        }
+        Ok(_) => {
+            log::info!("Ignoring state file hashed with other algorithms");
+            HashMap::new()
+        }
+        Err(e) => {
+            log::warn!("Ignoring state file {}: {}", path.display(), e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Write `data` to `path`, through a temporary file in the same directory
+/// that is then renamed, so that `path` never holds partial data.
+async fn write_atomic(path: &Path, data: Vec<u8>) -> errors::Result<()> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        std::io::Write::write_all(&mut file, &data)?;
+        file.as_file().sync_all()?;
+        file.persist(&path).map_err(|e| e.error)?;
+        errors::Result::Ok(())
+    })
+    .await?
+}
+
+/// Wait for a shutdown signal: Ctrl-C, or also SIGTERM on Unix.  Return the
+/// name of the received signal.
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res.map(|()| "SIGINT"),
+            _ = term.recv() => Ok("SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.map(|()| "Ctrl-C")
+    }
+}
+
+/// Hash a file from its current position, reading up to `buf_size` bytes at
+/// a time.  Return the digest in `method` and `encoding`, the hex `ETag`
+/// value in the `etag` algorithm if any, and the number of bytes read.
+/// Reading stops at the first error.
+async fn hash_file(
+    mut file: impl AsyncRead + Unpin,
+    method: HashMethod,
+    encoding: DigestEncoding,
+    etag: Option<EtagAlgorithm>,
+    buf_size: usize,
+) -> (String, Option<String>, u64) {
+    let mut d = Hasher::new(method);
+    let mut size = 0;
+    // a second hash only when the ETag needs one
+    let etag = etag.map(EtagAlgorithm::method);
+    let mut e = etag.filter(|&etag| etag != method).map(Hasher::new);
+    let mut buf = vec![0; buf_size];
+    loop {
+        // update digest for the newly read data
+        match file.read(&mut buf).await {
+            // EOF or error
+            Ok(0) | Err(_) => break,
+            Ok(sz) => {
+                size += sz as u64;
+                d.update(&buf[0..sz]);
+                if let Some(e) = &mut e {
+                    e.update(&buf[0..sz]);
+                }
+            }
+        }
+    }
+
+    let d = d.finalize();
+    let etag = match e {
+        Some(e) => Some(e.finalize_hex()),
+        None => etag.map(|_| hash::encode(DigestEncoding::Hex, &d)),
+    };
+    (hash::encode(encoding, &d), etag, size)
+}
 
 /// A [`Server`] is the server object.
 #[derive(Debug, Clone)]
 pub struct Server {
+    /// The effective configuration with secrets redacted, for introspection
+    /// only.
+    pub config: Config,
+
     /// The bind options
     pub bind: BindOptions,
 
-    /// The QR code format.
+    /// The TLS options
+    pub tls: TlsOptions,
+
+    /// The default format of served QR codes: the configured QR code format
+    /// if set, and otherwise SVG.
     pub qr: ImageOptions,
 
-    /// The collection of file paths queued for serving.  This assumes that the
-    /// underlying files are unmodified.
+    /// The policy for files with duplicate content.
+    pub on_duplicate: DuplicateOptions,
+
+    /// Whether re-enqueuing a changed path replaces its old digest.
+    pub replace_on_reenqueue: bool,
+
+    /// Whether to serve the files under enqueued directories.
+    pub recursive: bool,
+
+    /// How many levels to descend into enqueued directories, if limited.
+    pub max_depth: Option<usize>,
+
+    /// Which entries to skip when walking enqueued directories.
+    pub walk_filter: WalkFilter,
+
+    /// Which file extensions may be enqueued.
+    pub ext_policy: ExtPolicy,
+
+    /// Whether to serve a QR code of the listing page as the favicon.
+    pub favicon_qr: bool,
+
+    /// The canonical path of the static favicon file, if not the bundled
+    /// icon.
+    pub favicon: Option<PathBuf>,
+
+    /// The `max-age` of the `Strict-Transport-Security` header, if enabled.
+    pub hsts: Option<u64>,
+
+    /// How QR codes are rendered.
+    pub qr_style: QrStyle,
+
+    /// Whether to embed QR codes in the listing page.
+    pub inline_qr: bool,
+
+    /// Whether to render SVG when rendering a PNG QR code fails.
+    pub qr_fallback: bool,
+
+    /// Whether to choose the format of served QR codes from the `Accept`
+    /// header.
+    pub qr_format_from_accept: bool,
+
+    /// The transformation from URLs into QR code payloads.
+    pub qr_transform: PayloadTransform,
+
+    /// The user-provided index file served as the file listing, within the
+    /// only served directory.
+    pub index_file: Option<PathBuf>,
+
+    /// The theme of the generated file listing.
+    pub theme: ListTheme,
+
+    /// The user-provided CSS added to the generated file listing.
+    pub list_css: Option<String>,
+
+    /// The title of the generated file listing.
+    pub list_title: String,
+
+    /// Whether to serve an RSS feed of the served files.
+    pub feed: bool,
+
+    /// Where to save the QR code of the file listing once bound.
+    pub startup_qr_png: Option<PathBuf>,
+
+    /// Whether to print the QR code of the file listing once bound.
+    pub qr_terminal: bool,
+
+    /// Whether to compress responses as accepted by clients, except
+    /// downloaded files.
+    pub compress: bool,
+
+    /// Where to write the manifest of served files on graceful shutdown.
+    pub manifest: Option<PathBuf>,
+
+    /// Where to keep file digests across restarts.
+    pub state_file: Option<PathBuf>,
+
+    /// The canonical directory receiving uploaded files, if uploads are
+    /// accepted.
+    pub upload_dir: Option<PathBuf>,
+
+    /// The canonical directory containing all files enqueued after startup,
+    /// if restricted.
+    pub serve_root: Option<PathBuf>,
+
+    /// The temporary directories holding the files buffered from standard
+    /// input and FIFOs, which are deleted with the last clone of the server.
+    _buffer_dirs: Arc<Vec<TempDir>>,
+
+    /// The canonical directories whose new files are served.  See
+    /// [`Server::run_watcher`].
+    pub watch_dirs: Vec<PathBuf>,
+
+    /// The maximum total size of the files in each upload, in bytes.
+    pub max_upload_size: u64,
+
+    /// The maximum number of paths in each request to enqueue files.
+    pub max_enqueue_batch: usize,
+
+    /// The size of each read from files when hashing them or streaming
+    /// archives, in bytes.
+    pub buf_size: usize,
+
+    /// The credentials required by [`BasicAuth`], if any.
+    ///
+    /// [`BasicAuth`]: crate::middleware::BasicAuth
+    pub auth: Option<Credentials>,
+
+    /// The secret key of signed URLs, if enabled.  See
+    /// [`Server::signed_url`].
+    secret: Option<String>,
+
+    /// How long signed URLs minted for the listing stay valid.
+    pub signed_ttl: Duration,
+
+    /// The digest of each hashed path with its metadata at the time, read
+    /// from and written to `state_file`.  Only populated when `state_file` is
+    /// set.
+    hashed: Arc<RwLock<HashMap<PathBuf, StateEntry>>>,
+
+    /// The scheme of generated URLs, overriding the TLS-dependent default.
+    pub url_scheme: Option<UrlScheme>,
+
+    /// The host of generated URLs, overriding the primary bound host.
+    pub external_host: Option<String>,
+
+    /// The port of generated URLs, overriding the bound port.
+    pub external_port: Option<u16>,
+
+    /// Whether to advertise the server over mDNS.
+    pub mdns: bool,
+
+    /// The mDNS instance and host name, without the `.local` suffix.
+    pub mdns_name: String,
+
+    /// The mDNS advertisement while the server runs.  See
+    /// [`Server::http_builder`].
+    mdns_service: Arc<std::sync::Mutex<Option<MdnsService>>>,
+
+    /// Whether to serve the counters in `metrics` at `/metrics`.
+    pub serve_metrics: bool,
+
+    /// The counters exported at `/metrics`.  They are only updated when
+    /// `serve_metrics` is set.
+    pub metrics: Arc<Metrics>,
+
+    /// The origins allowed to make cross-origin requests to the API routes,
+    /// where `*` allows any origin.  See [`Cors`].
+    pub cors_origins: Vec<String>,
+
+    /// The route prefix, with a leading slash and without a trailing one.
+    /// Empty when serving at the root.
+    pub base_path: String,
+
+    /// How long to wait for in-progress downloads when shutting down.
+    pub drain_timeout: Duration,
+
+    /// The maximum number of QR code and thumbnail renderings per client per
+    /// minute.
+    pub qr_rate_limit: Option<u32>,
+
+    /// The token buckets of QR code and thumbnail renderings, keyed by client
+    /// address.
+    pub qr_buckets: Arc<RwLock<HashMap<IpAddr, Bucket>>>,
+
+    /// The maximum number of concurrent downloads per client.
+    pub max_per_ip: Option<u32>,
+
+    /// The active downloads, keyed by client address.
+    pub downloads: Active,
+
+    /// The maximum number of requests per client per second.
+    pub rate_limit: Option<u32>,
+
+    /// The maximum burst of requests per client, with `rate_limit`.
+    pub rate_burst: u32,
+
+    /// Whether clients on the loopback address are not rate-limited.
+    pub rate_limit_exempt_loopback: bool,
+
+    /// The maximum rate of each download in bytes per second, or 0 when
+    /// unlimited.
+    pub max_bps: u64,
+
+    /// The token buckets of all requests, keyed by client address.  Full
+    /// buckets are dropped periodically, see [`Server::purge_rate_buckets`].
+    pub rate_buckets: Arc<RwLock<HashMap<IpAddr, Bucket>>>,
+
+    /// Debug use only: the artificial delay before each download response.
+    #[cfg(debug_assertions)]
+    pub debug_delay: Option<Duration>,
+
+    /// Debug use only: the artificial delay before hashing each queued file.
+    #[cfg(debug_assertions)]
+    pub debug_hash_delay: Option<Duration>,
+
+    /// The collection of file paths queued for serving.  Files modified after
+    /// they are hashed are detected on download, see
+    /// [`Server::is_modified`].
     pub files: Arc<RwLock<VecDeque<PathBuf>>>,
 
     /// The hash digest of all currently-hashed files.
     pub digest: Arc<RwLock<HashMap<String, PathBuf>>>,
+
+    /// The digest of each path in `digest` and `duplicates`, kept in sync with
+    /// both.
+    paths: Arc<RwLock<HashMap<PathBuf, String>>>,
+
+    /// The modification time of each path in `paths` when it was hashed, if
+    /// known.
+    modified: Arc<RwLock<HashMap<PathBuf, SystemTime>>>,
+
+    /// The JPEG thumbnails generated so far, keyed by digest and width.
+    pub thumbnails: Arc<RwLock<Thumbnails>>,
+
+    /// How long files are served after they are enqueued, unless enqueued
+    /// with their own time-to-live.
+    pub ttl: Option<Duration>,
+
+    /// When each enqueued path expires, if it does.  See
+    /// [`Server::purge_expired`].
+    expiry: Arc<RwLock<HashMap<PathBuf, Instant>>>,
+
+    /// How many times each file is downloaded before it stops being served,
+    /// unless enqueued with its own limit.
+    pub max_downloads: Option<u64>,
+
+    /// The download limit of each path enqueued with its own limit.
+    download_limits: Arc<RwLock<HashMap<PathBuf, u64>>>,
+
+    /// The digests of expired or exhausted files, answered with 410 status.
+    expired: Arc<RwLock<HashSet<String>>>,
+
+    /// Whether to hash files again before serving them.
+    pub verify_on_download: bool,
+
+    /// Whether to always download files as attachments of unknown type.
+    pub force_download: bool,
+
+    /// The hash algorithm of file digests.
+    pub hash: HashMethod,
+
+    /// The encoding of file digests.
+    pub digest_encoding: DigestEncoding,
+
+    /// The hash algorithm of `ETag` headers, if not the digest itself.
+    pub hash_algorithm_for_etag: Option<EtagAlgorithm>,
+
+    /// The `ETag` values of served files, keyed by digest.  Only populated
+    /// when `hash_algorithm_for_etag` is set, and otherwise the digest is the
+    /// `ETag`.
+    pub etags: Arc<RwLock<HashMap<String, String>>>,
+
+    /// When each served file was first enqueued, keyed by digest.
+    pub enqueued: Arc<RwLock<HashMap<String, SystemTime>>>,
+
+    /// The number of successful downloads of each served file, keyed by
+    /// digest.
+    pub download_counts: Arc<RwLock<HashMap<String, u64>>>,
+
+    /// The size in bytes of each served file, keyed by digest.
+    sizes: Arc<RwLock<HashMap<String, u64>>>,
+
+    /// The total size in bytes of all served files, kept in sync with
+    /// `sizes`.
+    total_bytes: Arc<AtomicU64>,
+
+    /// When the server was created, for reporting its uptime.
+    pub started: Instant,
+
+    /// Whether the initially queued files are processed.  See
+    /// [`Server::wait_ready`].
+    ready: Arc<watch::Sender<bool>>,
+
+    /// Additional paths whose content duplicates a file in `digest`, only
+    /// populated under [`DuplicateOptions::Keep`].
+    pub duplicates: Arc<RwLock<HashMap<String, Vec<PathBuf>>>>,
 }
 
 impl Server {
@@ -53,14 +538,173 @@ impl Server {
     /// In particular, the collection of files is canonicalized, deduplicated,
     /// and ensured to reference valid files.
     pub async fn new(cli: Cli) -> errors::Result<Self> {
-        let qr = cli.config.image();
+        // keep no plaintext secrets besides those in use
+        let config = cli.config.clone().resolve().redacted();
+        let qr = match cli.config.image {
+            Some(ft @ (ImageOptions::Png | ImageOptions::Svg)) => ft,
+            _ => ImageOptions::Svg,
+        };
+        let on_duplicate = cli.config.on_duplicate();
+        let replace_on_reenqueue = cli.config.replace_on_reenqueue();
+        let recursive = cli.config.recursive();
+        let max_depth = cli.config.max_depth;
+        let walk_filter =
+            WalkFilter::new(cli.config.include_hidden(), &cli.config.exclude)?;
+        let ext_policy =
+            ExtPolicy::new(&cli.config.allow_ext, &cli.config.deny_ext);
+        let glob = cli.config.glob();
+        let favicon_qr = cli.config.favicon_qr();
+        let favicon_path = match &cli.config.favicon {
+            Some(path) => Some(
+                asy::canonicalize(path)
+                    .await
+                    .map_err(|_| Error::InvalidFile(path.clone()))?,
+            ),
+            None => None,
+        };
+        let qr_style = QrStyle {
+            caption: cli.config.qr_caption_url(),
+            scale: cli.config.qr_scale,
+            quiet_zone: cli.config.qr_quiet_zone(),
+            dark: cli.config.qr_dark,
+            light: cli.config.qr_light,
+        };
+        if qr_style.contrast() < QrStyle::MIN_CONTRAST {
+            log::warn!(
+                "QR code colors have a low contrast ratio of {:.1}, and may \
+                 not scan reliably",
+                qr_style.contrast()
+            );
+        }
+        let inline_qr = cli.config.inline_qr();
+        let qr_fallback = cli.config.qr_fallback();
+        let qr_format_from_accept = cli.config.qr_format_from_accept();
+        let theme = cli.config.theme();
+        let list_css = match &cli.config.css {
+            Some(path) => Some(tokio::fs::read_to_string(path).await?),
+            None => None,
+        };
+        let list_title = cli.config.list_title().to_owned();
+        let feed = cli.config.feed();
+        let verify_on_download = cli.config.verify_on_download();
+        let force_download = cli.config.force_download();
+        let drain_timeout = Duration::from_secs(cli.config.drain_timeout());
+        let max_upload_size = cli.config.max_upload_size();
+        let max_enqueue_batch = cli.config.max_enqueue_batch();
+        let buf_size = cli.config.buf_size();
+        if buf_size == 0 {
+            Err(Error::InvalidArg("buf-size must be positive".to_owned()))?
+        }
+        let auth_downloads = cli.config.auth_downloads();
+        let ttl = cli.config.ttl.map(|ttl| ttl.0);
+        let signed_ttl = cli.config.signed_ttl().0;
+        let max_downloads = cli.config.max_downloads;
+        let hash = cli.config.hash();
+        let digest_encoding = cli.config.digest_encoding();
+        let url_scheme = cli.config.scheme;
+        let mdns = cli.config.mdns();
+        let mdns_name = cli.config.mdns_name()?.to_owned();
+        let external_host = cli.config.normalized_external_host()?;
+        let external_port = cli.config.external_port;
+        let serve_metrics = cli.config.metrics();
+        let base_path = cli.config.normalized_base_path();
+        let stdin_name = cli.config.stdin_name()?.to_owned();
+        let hsts = cli.config.hsts;
+        let qr_rate_limit = cli.config.qr_rate_limit;
+        let max_per_ip = cli.config.max_per_ip;
+        let rate_limit = cli.config.rate_limit;
+        let rate_burst = cli.config.rate_burst();
+        let rate_limit_exempt_loopback =
+            cli.config.rate_limit_exempt_loopback();
+        let max_bps = cli.config.max_bps();
+        let startup_qr_png = cli.config.startup_qr_png;
+        let manifest = cli.config.manifest;
+        let cors_origins = cli.config.cors_origin;
+        let state_file = cli.config.state_file;
+        let auth = match (cli.config.auth_user, cli.config.auth_pass) {
+            (Some(user), Some(pass)) => {
+                Some(Credentials::new(&user, &pass, auth_downloads)?)
+            }
+            (None, None) => None,
+            _ => Err(Error::InvalidArg(
+                "auth-user and auth-pass must be set together".to_owned(),
+            ))?,
+        };
+        let upload_dir = match cli.config.upload_dir {
+            Some(dir) => {
+                tokio::fs::create_dir_all(&dir).await?;
+                Some(asy::canonicalize(&dir).await?)
+            }
+            None => None,
+        };
+        let serve_root = match cli.config.serve_root {
+            Some(dir) => Some(asy::canonicalize(&dir).await?),
+            None => None,
+        };
+        let mut watch_dirs = Vec::with_capacity(cli.config.watch.len());
+        for dir in &cli.config.watch {
+            watch_dirs.push(asy::canonicalize(dir).await?);
+        }
+        let hash_algorithm_for_etag = cli.config.hash_algorithm_for_etag;
         let bind = cli.config.bind;
+        bind.check_interface()?;
+        if bind.unix_socket.is_some() {
+            if cfg!(not(unix)) {
+                Err(Error::InvalidArg(
+                    "unix-socket is only supported on Unix".to_owned(),
+                ))?
+            }
+            // generated URLs cannot point at the socket
+            if external_host.is_none() && !mdns {
+                Err(Error::InvalidArg(
+                    "unix-socket requires external-host".to_owned(),
+                ))?
+            }
+        }
+        let tls = cli.config.tls;
+
+        let hashed = match &state_file {
+            Some(path) => {
+                let etag = hash_algorithm_for_etag.map(EtagAlgorithm::method);
+                load_state(path, hash, digest_encoding, etag).await
+            }
+            None => HashMap::new(),
+        };
 
         // Canonicalize paths, and deduplicate the collection -- raise a warning
         // and continue when not in strict mode, and exit when in strict mode.
+        // Glob patterns are expanded before canonicalization.
+        let args: Vec<_> = if glob {
+            cli.files.iter().flat_map(|p| expand_glob(p)).collect()
+        } else {
+            cli.files
+        };
+        let mut buffer_dirs = Vec::new();
         let files = {
-            let mut files = HashSet::with_capacity(cli.files.len());
-            for p in cli.files {
+            let mut files = HashSet::with_capacity(args.len());
+            let mut read_stdin = false;
+            for p in args {
+                // standard input and FIFOs, such as those from process
+                // substitution, can only be read once, so they are buffered
+                if p.as_os_str() == "-" {
+                    if !read_stdin {
+                        let mut stdin = tokio::io::stdin();
+                        let (dir, path) =
+                            asy::buffer(&stdin_name, &mut stdin).await?;
+                        buffer_dirs.push(dir);
+                        files.insert(path);
+                        read_stdin = true;
+                    }
+                    continue;
+                }
+                if asy::is_fifo(&p).await {
+                    let name = p.file_name().unwrap_or(OsStr::new("fifo"));
+                    let mut fifo = asy::File::open(&p).await?;
+                    let (dir, path) = asy::buffer(name, &mut fifo).await?;
+                    buffer_dirs.push(dir);
+                    files.insert(path);
+                    continue;
+                }
                 let path = asy::canonicalize(&p).await;
                 match (cli.config.strict, cli.config.quiet, path) {
                     // when got a canonicalized path, insert
@@ -78,27 +722,238 @@ impl Server {
             files
         };
 
-        // There should be at least one file to serve
-        if files.is_empty() {
+        // The index file is only used for serving a single directory
+        let index_file = match (cli.config.index_file, files.iter().next()) {
+            (Some(name), Some(dir)) if files.len() == 1 && dir.is_dir() => {
+                Some(dir.join(name))
+            }
+            (Some(name), _) => {
+                log::warn!(
+                    "Ignoring index file {}: not serving a single directory",
+                    name.display()
+                );
+                None
+            }
+            (None, _) => None,
+        };
+
+        // Serve the files under directories instead of the directories
+        let files = if recursive {
+            let mut expanded = HashSet::with_capacity(files.len());
+            for path in files {
+                if path.is_dir() {
+                    let walked =
+                        asy::walk_files(path, max_depth, walk_filter.clone());
+                    expanded.extend(walked.await);
+                } else {
+                    expanded.insert(path);
+                }
+            }
+            expanded
+        } else {
+            files
+        };
+
+        // Skip the files whose extensions are not allowed
+        let files = {
+            let mut allowed = HashSet::with_capacity(files.len());
+            for path in files {
+                if path.is_dir() || ext_policy.permits(&path) {
+                    allowed.insert(path);
+                } else if cli.config.strict == Some(true) {
+                    Err(Error::DeniedExtension(path))?
+                } else {
+                    log::warn!("Skipping {}", Error::DeniedExtension(path));
+                }
+            }
+            allowed
+        };
+
+        // Initial files expire relative to the start
+        let expiry = match ttl {
+            Some(ttl) => {
+                let deadline = Instant::now() + ttl;
+                files.iter().map(|path| (path.clone(), deadline)).collect()
+            }
+            None => HashMap::new(),
+        };
+
+        // There should be at least one file to serve, unless files are
+        // expected in watched directories
+        if files.is_empty() && watch_dirs.is_empty() {
             Err(Error::NoFiles)
         } else {
             let files = Arc::new(RwLock::new(files.into_iter().collect()));
-            Ok(Self { bind, files, digest: Arc::default(), qr })
+            Ok(Self {
+                config,
+                bind,
+                tls,
+                files,
+                digest: Arc::default(),
+                paths: Arc::default(),
+                modified: Arc::default(),
+                duplicates: Arc::default(),
+                verify_on_download,
+                force_download,
+                hash,
+                digest_encoding,
+                hash_algorithm_for_etag,
+                etags: Arc::default(),
+                enqueued: Arc::default(),
+                download_counts: Arc::default(),
+                sizes: Arc::default(),
+                total_bytes: Arc::default(),
+                started: Instant::now(),
+                ready: Arc::new(watch::channel(false).0),
+                thumbnails: Arc::default(),
+                ttl,
+                expiry: Arc::new(RwLock::new(expiry)),
+                max_downloads,
+                download_limits: Arc::default(),
+                expired: Arc::default(),
+                qr,
+                on_duplicate,
+                replace_on_reenqueue,
+                recursive,
+                max_depth,
+                walk_filter,
+                ext_policy,
+                favicon_qr,
+                favicon: favicon_path,
+                hsts,
+                qr_style,
+                inline_qr,
+                qr_fallback,
+                qr_format_from_accept,
+                qr_transform: PayloadTransform::default(),
+                index_file,
+                theme,
+                list_css,
+                list_title,
+                feed,
+                startup_qr_png,
+                qr_terminal: !cli.no_qr_terminal,
+                compress: !cli.no_compress,
+                manifest,
+                state_file,
+                upload_dir,
+                serve_root,
+                _buffer_dirs: Arc::new(buffer_dirs),
+                watch_dirs,
+                max_upload_size,
+                max_enqueue_batch,
+                buf_size,
+                auth,
+                secret: cli.config.secret,
+                signed_ttl,
+                hashed: Arc::new(RwLock::new(hashed)),
+                url_scheme,
+                external_host,
+                external_port,
+                mdns,
+                mdns_name,
+                mdns_service: Arc::default(),
+                serve_metrics,
+                cors_origins,
+                metrics: Arc::default(),
+                base_path,
+                drain_timeout,
+                qr_rate_limit,
+                max_per_ip,
+                downloads: Active::default(),
+                qr_buckets: Arc::default(),
+                rate_limit,
+                rate_burst,
+                rate_limit_exempt_loopback,
+                rate_buckets: Arc::default(),
+                max_bps,
+                #[cfg(debug_assertions)]
+                debug_delay: cli.debug_delay.map(Duration::from_millis),
+                #[cfg(debug_assertions)]
+                debug_hash_delay: cli
+                    .debug_hash_delay
+                    .map(Duration::from_millis),
+            })
         }
     }
 
-    /// Queue additional files for serving.  This method will acquire a write
-    /// lock on `files`.  Files that cannot be canonicalized are skipped.
+    /// Queue additional files for serving, with the configured limits.  See
+    /// [`Server::enqueue_with`].
     pub async fn enqueue(&self, files: impl IntoIterator<Item = PathBuf>) {
+        self.enqueue_with(files, FileLimits::default()).await
+    }
+
+    /// Queue additional files for serving, with `limits` overriding the
+    /// configured `ttl` and `max_downloads`.  This method will acquire a
+    /// write lock on `files`, and also on `download_limits` and possibly on
+    /// `expiry`.  Files that cannot be canonicalized, are outside of
+    /// `serve_root` or fail `ext_policy` are skipped, and directories are
+    /// replaced by their files when `recursive` is set.
+    pub async fn enqueue_with(
+        &self,
+        files: impl IntoIterator<Item = PathBuf>,
+        limits: FileLimits,
+    ) {
+        let deadline = limits.ttl.or(self.ttl).map(|ttl| Instant::now() + ttl);
         let mut lock = self.files.write().await;
         for path in files.into_iter() {
             if let Ok(canon_path) = asy::canonicalize(&path).await {
+                if !self.within_root(&canon_path) {
+                    log::error!(
+                        "Path is outside of the serve root, skipping: {}",
+                        path.display()
+                    );
+                    continue;
+                }
                 log::info!(
                     "Enqueuing path: {} ({})",
                     path.display(),
                     canon_path.display()
                 );
-                lock.push_back(canon_path)
+                let paths = if self.recursive && canon_path.is_dir() {
+                    // symbolic links within the directory may escape the root
+                    let mut paths = asy::walk_files(
+                        canon_path,
+                        self.max_depth,
+                        self.walk_filter.clone(),
+                    )
+                    .await;
+                    paths.retain(|p| self.within_root(p));
+                    paths
+                } else {
+                    vec![canon_path]
+                };
+                let paths: Vec<_> = paths
+                    .into_iter()
+                    .filter(|p| {
+                        let permitted =
+                            p.is_dir() || self.ext_policy.permits(p);
+                        if !permitted {
+                            let e = Error::DeniedExtension(p.clone());
+                            log::warn!("Skipping {}", e);
+                        }
+                        permitted
+                    })
+                    .collect();
+                if let Some(deadline) = deadline {
+                    let mut expiry = self.expiry.write().await;
+                    for path in &paths {
+                        expiry.insert(path.clone(), deadline);
+                    }
+                }
+                {
+                    let mut download_limits =
+                        self.download_limits.write().await;
+                    for path in &paths {
+                        match limits.max_downloads {
+                            Some(max) => {
+                                download_limits.insert(path.clone(), max)
+                            }
+                            None => download_limits.remove(path),
+                        };
+                    }
+                }
+                lock.extend(paths)
             } else {
                 log::error!(
                     "Failed to canonicalize path, skipping: {}",
@@ -108,139 +963,1868 @@ impl Server {
         }
     }
 
-    /// Process all queued files.  This method will acquire a write lock on
-    /// `files`, and also a write lock on `digest`.  When this function returns,
-    /// the queue will become emtpy.
-    pub async fn process_digest(self: Arc<Self>) -> errors::Result<()> {
+    /// Whether the canonical `path` is within `serve_root`, if set.
+    pub fn within_root(&self, path: &Path) -> bool {
+        self.serve_root
+            .as_ref()
+            .is_none_or(|root| path.starts_with(root))
+    }
+
+    /// Process all queued files.  When `skip_existing` is set, queued paths
+    /// that already have a digest are not hashed again, even if their content
+    /// has changed.  This method will acquire a write lock on `files`, a read
+    /// lock on `paths`, and also a write lock on `digest`.  When this function
+    /// returns, the queue will become emtpy.
+    pub async fn process_digest(
+        self: Arc<Self>,
+        skip_existing: bool,
+    ) -> errors::Result<()> {
         let futs = FuturesUnordered::new();
         while let Some(path) = self.files.write().await.pop_front() {
+            if skip_existing && self.paths.read().await.contains_key(&path) {
+                log::trace!("Skipping hashed path {}", path.display());
+                continue;
+            }
             let this = self.clone();
             futs.push(spawn(async move {
                 log::trace!("Beginning processing {}", path.display());
 
-                if let Ok(mut file) = asy::File::open(&path).await {
+                #[cfg(debug_assertions)]
+                if let Some(delay) = this.debug_hash_delay {
+                    log::debug!("Delaying hashing by {:?}", delay);
+                    tokio::time::sleep(delay).await;
+                }
+
+                if let Ok(file) = asy::File::open(&path).await {
                     if asy::is_multiread_file(&file).await {
-                        let mut d = Sha512::new();
-                        let d: Vec<_> = loop {
-                            // hold the entirety of file data
-                            let mut buf = [0; DEFAULT_BUFSIZE];
-                            // update digest for the newly read data
-                            match file.read(&mut buf).await {
-                                // EOF or error
-                                Ok(0) | Err(_) => break d.finalize(),
-                                Ok(sz) => d.update(&buf[0..sz]),
-                            }
+                        let modified = file
+                            .metadata()
+                            .await
+                            .and_then(|metadata| metadata.modified())
+                            .ok();
+                        let start = Instant::now();
+                        let (d, etag, size) = this.hash_path(&path, file).await;
+                        if this.serve_metrics {
+                            this.metrics.add_hashing(start.elapsed());
                         }
-                        .into_iter()
-                        .collect();
 
-                        // get the digest string, and store into hash table when
-                        // empty
-                        this.digest
-                            .write()
-                            .await
-                            .insert(hex::encode(d), path.clone());
+                        if this.replace_on_reenqueue {
+                            this.remove_stale_digest(&d, &path).await;
+                        }
+
+                        // store the digest into hash table
+                        if let Some(etag) = etag {
+                            this.etags.write().await.insert(d.clone(), etag);
+                        }
+                        this.insert_digest(d, path.clone(), size, modified)
+                            .await?;
+                    } else {
+                        log::warn!("Skipping {}", Error::NoFifo(path.clone()));
                     }
                 }
 
-                log::trace!("Finished processing {}", path.display())
+                log::trace!("Finished processing {}", path.display());
+                errors::Result::Ok(())
             }))
         }
 
         for fut in futs {
-            fut.await?
+            fut.await??
+        }
+
+        if let Some(path) = &self.state_file {
+            if let Err(e) = self.write_state(path).await {
+                log::warn!("Cannot write state file {}: {}", path.display(), e);
+            }
         }
 
         Ok(())
     }
 
-    /// Query for an existing digest from the path.
-    pub async fn query_digest(&self, path: PathBuf) -> Option<String> {
-        self.digest
-            .read()
-            .await
-            .iter()
-            .find_map(|(d, p)| (*p == path).then(|| d.clone()))
+    /// Hash the opened `file` at `path`, unless its digest is cached in the
+    /// state file.  Return as in [`hash_file`].  This method will acquire a
+    /// read lock on `hashed`, and possibly also a write lock on it.
+    async fn hash_path(
+        &self,
+        path: &Path,
+        file: asy::File,
+    ) -> (String, Option<String>, u64) {
+        let metadata = match file.metadata().await {
+            Ok(metadata) if self.state_file.is_some() => metadata,
+            _ => {
+                let etag = self.hash_algorithm_for_etag;
+                return hash_file(
+                    file,
+                    self.hash,
+                    self.digest_encoding,
+                    etag,
+                    self.buf_size,
+                )
+                .await;
+            }
+        };
+        if let Some(cached) = self.cached_digest(path, &metadata).await {
+            log::trace!("Reusing cached digest of {}", path.display());
+            return cached;
+        }
+
+        let etag = self.hash_algorithm_for_etag;
+        let (digest, etag, size) = hash_file(
+            file,
+            self.hash,
+            self.digest_encoding,
+            etag,
+            self.buf_size,
+        )
+        .await;
+        if let Ok(modified) = metadata.modified() {
+            let entry = StateEntry {
+                path: path.to_owned(),
+                digest: digest.clone(),
+                etag: etag.clone(),
+                size,
+                modified,
+            };
+            self.hashed.write().await.insert(path.to_owned(), entry);
+        }
+        (digest, etag, size)
     }
 
-    /// Construct the URL for a given file path (left) or digest (right)
-    pub async fn file_url(
-        &self,
-        file: Either<String, PathBuf>,
-    ) -> Option<String> {
-        Some(format!(
-            "{}://{}:{}/{}/?h={}",
-            "http",
-            self.bind.primary_host(),
-            self.bind.port(),
-            "sha512",
-            match file {
-                Either::Left(digest) => digest,
-                Either::Right(path) => self.query_digest(path).await?,
+    /// Whether the initially queued files are processed.  See
+    /// [`Server::wait_ready`].
+    pub fn is_ready(&self) -> bool {
+        *self.ready.borrow()
+    }
+
+    /// Resolve once the initially queued files are processed, so that their
+    /// URLs can be advertised.  The HTTP server may accept connections before
+    /// this.
+    pub async fn wait_ready(&self) {
+        let mut ready = self.ready.subscribe();
+        while !*ready.borrow_and_update() {
+            // the sender lives as long as `self`
+            if ready.changed().await.is_err() {
+                break;
             }
-        ))
+        }
     }
 
-    /// Construct the QR code URL for a given file path (left) or digest
-    /// (right).  The URL format is "/qr/{method}/?h={hash}".
-    pub async fn qr_url(
+    /// Store a newly-computed digest for `path`, hashed when it was last
+    /// `modified`.  When another path already has the same digest, the
+    /// outcome is decided by `on_duplicate`.  This method will acquire write
+    /// locks on `digest` and `paths`, and possibly also write locks on
+    /// `modified` and `duplicates`.
+    async fn insert_digest(
         &self,
-        file: Either<String, PathBuf>,
-    ) -> Option<String> {
-        Some(format!(
-            "{}://{}:{}/qr/{}/?h={}",
-            "http",
-            self.bind.primary_host(),
-            self.bind.port(),
-            "sha512",
-            match file {
-                Either::Left(digest) => digest,
-                Either::Right(path) => self.query_digest(path).await?,
+        digest: String,
+        path: PathBuf,
+        size: u64,
+        modified: Option<SystemTime>,
+    ) -> errors::Result<()> {
+        let mut lock = self.digest.write().await;
+        let existing = match lock.get(&digest) {
+            Some(existing) if *existing != path => existing,
+            _ => {
+                let mut sizes = self.sizes.write().await;
+                if sizes.insert(digest.clone(), size).is_none() {
+                    self.total_bytes.fetch_add(size, Ordering::Relaxed);
+                    self.enqueued
+                        .write()
+                        .await
+                        .insert(digest.clone(), SystemTime::now());
+                }
+                self.paths
+                    .write()
+                    .await
+                    .insert(path.clone(), digest.clone());
+                self.set_modified(&path, modified).await;
+                // a re-enqueued file is served again
+                self.expired.write().await.remove(&digest);
+                lock.insert(digest, path);
+                return Ok(());
             }
-        ))
+        };
+
+        match self.on_duplicate {
+            DuplicateOptions::Skip => log::warn!(
+                "Skipping path: {} (same content as {})",
+                path.display(),
+                existing.display()
+            ),
+            DuplicateOptions::Error => {
+                Err(Error::Duplicate(path, existing.clone()))?
+            }
+            DuplicateOptions::Keep => {
+                log::info!(
+                    "Keeping duplicate path: {} (same content as {})",
+                    path.display(),
+                    existing.display()
+                );
+                let mut dups = self.duplicates.write().await;
+                self.paths
+                    .write()
+                    .await
+                    .insert(path.clone(), digest.clone());
+                self.set_modified(&path, modified).await;
+                let paths = dups.entry(digest).or_default();
+                if !paths.contains(&path) {
+                    paths.push(path)
+                }
+            }
+        }
+
+        Ok(())
     }
 
-    /// The entry point to start the file server with [`actix_web`].
-    pub async fn start_actix(self) -> errors::Result<()> {
-        // listen the specified TCP ports
-        let port = self.bind.port();
-        let listen = self.bind.hosts_iter().flat_map(|ip| {
-            TcpListener::bind(SocketAddr::from((ip, port))).ok()
-        });
+    /// Record when `path` was last modified as it was hashed, or forget it
+    /// when unknown.  This method will acquire a write lock on `modified`.
+    async fn set_modified(&self, path: &Path, modified: Option<SystemTime>) {
+        let mut lock = self.modified.write().await;
+        match modified {
+            Some(modified) => lock.insert(path.to_owned(), modified),
+            None => lock.remove(path),
+        };
+    }
 
-        // wrap to web data
-        let this = Data::new(self);
+    /// Stop serving any digest other than `digest` that is served at `path`,
+    /// as its content has changed since it was hashed.  See
+    /// [`Server::remove_digest`] for the locks acquired.
+    async fn remove_stale_digest(&self, digest: &str, path: &Path) {
+        let stale = match self.paths.read().await.get(path) {
+            Some(d) if d != digest => d.clone(),
+            _ => return,
+        };
+        // a changed duplicate does not make the served file stale
+        let served = self.digest.read().await.get(&stale) == Some(&path.into());
+        if served {
+            log::info!(
+                "Replacing stale digest of {}: {}",
+                path.display(),
+                stale
+            );
+            self.remove_digest(&stale).await;
+        }
+    }
 
-        // process queued files
-        Arc::clone(&this).process_digest().await?;
+    /// Whether the file at `path` has expired, though it may not be purged
+    /// yet.  This method will acquire a read lock on `expiry`.
+    pub async fn is_expired(&self, path: &Path) -> bool {
+        let expiry = self.expiry.read().await;
+        expiry
+            .get(path)
+            .is_some_and(|deadline| *deadline <= Instant::now())
+    }
 
-        // create the HTTP server
-        let http_server = {
-            let mut http_server = HttpServer::new(move || {
-                App::new()
-                    // middlewares: compression, logging, etc.
-                    .wrap(Compress::default())
-                    .wrap(Logger::new("%a %r => %s @%Dms"))
-                    // embed server state
-                    .app_data(this.clone())
-                    // main services
-                    .service(get_sha512)
-                    .service(list_files)
-                    .service(favicon)
-                    .service(show_qr)
-                    .service(enqueue_file)
-                    // redirect (alias) services
-                    .default_service(to(default_service))
-            });
-            for listen in listen {
-                http_server = http_server.listen(listen)?
+    /// The number of downloads allowed for the file at `path`, if limited.
+    /// This method will acquire a read lock on `download_limits`.
+    pub async fn download_limit(&self, path: &Path) -> Option<u64> {
+        let download_limits = self.download_limits.read().await;
+        download_limits.get(path).copied().or(self.max_downloads)
+    }
+
+    /// Whether `digest` was served until its file expired or was exhausted.
+    /// This method will acquire a read lock on `expired`.
+    pub async fn digest_expired(&self, digest: &str) -> bool {
+        self.expired.read().await.contains(digest)
+    }
+
+    /// Stop serving the files that have expired, and remember their digests
+    /// to answer with 410 status.  Expired duplicates only stop being listed
+    /// as such.  This method will acquire a write lock on `expiry`, and
+    /// possibly also write locks on `files` and `expired`, and the locks of
+    /// [`Server::remove_digest`].  Return the number of removed files.
+    pub async fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let paths: Vec<_> = {
+            let mut expiry = self.expiry.write().await;
+            let paths: Vec<_> = expiry
+                .iter()
+                .filter(|(_, deadline)| **deadline <= now)
+                .map(|(path, _)| path.clone())
+                .collect();
+            for path in &paths {
+                expiry.remove(path);
             }
-            http_server
+            paths
         };
+        if paths.is_empty() {
+            return 0;
+        }
 
-        log::trace!("Starting HTTP server");
-        http_server.run().await?;
-
-        Ok(())
+        self.files
+            .write()
+            .await
+            .retain(|path| !paths.contains(path));
+        let mut removed = 0;
+        for path in paths {
+            let digest = match self.query_digest(path.clone()).await {
+                Some(digest) => digest,
+                None => continue,
+            };
+            let served = self.digest.read().await.get(&digest) == Some(&path);
+            if served && self.retire_digest(&digest).await {
+                log::info!("Expired path: {} ({})", path.display(), digest);
+                removed += 1;
+            } else if let Some(dups) =
+                self.duplicates.write().await.get_mut(&digest)
+            {
+                dups.retain(|dup| *dup != path);
+                self.paths.write().await.remove(&path);
+                self.modified.write().await.remove(&path);
+            }
+        }
+        removed
+    }
+
+    /// Stop serving the file with the given digest, and answer it with 410
+    /// status from now on.  See [`Server::remove_digest`] for the locks
+    /// acquired, and also a write lock on `expired`.  Return whether anything
+    /// was removed.
+    pub async fn retire_digest(&self, digest: &str) -> bool {
+        let removed = self.remove_digest(digest).await;
+        if removed {
+            self.expired.write().await.insert(digest.to_owned());
+        }
+        removed
+    }
+
+    /// Stop serving the file at `path`, e.g. when it is deleted.  A duplicate
+    /// path only stops being listed as such.  See [`Server::remove_digest`]
+    /// for the locks acquired.  Return whether anything was removed.
+    pub async fn remove_path(&self, path: &Path) -> bool {
+        let digest = match self.paths.read().await.get(path) {
+            Some(digest) => digest.clone(),
+            None => return false,
+        };
+        let served =
+            self.digest.read().await.get(&digest) == Some(&path.into());
+        if served {
+            return self.remove_digest(&digest).await;
+        }
+        if let Some(dups) = self.duplicates.write().await.get_mut(&digest) {
+            dups.retain(|dup| dup != path);
+        }
+        self.modified.write().await.remove(path);
+        self.paths.write().await.remove(path).is_some()
+    }
+
+    /// Serve the files created in the watched directories, and stop serving
+    /// the deleted ones, until the server is dropped.  Changes are handled
+    /// in batches, once `watcher` reports no change for [`WATCH_DEBOUNCE`].
+    async fn run_watcher(this: Weak<Self>, mut watcher: DirWatcher) {
+        while let Some(paths) = watcher.next_batch(WATCH_DEBOUNCE).await {
+            let server = match this.upgrade() {
+                Some(server) => server,
+                None => break,
+            };
+            let mut created = Vec::new();
+            for path in paths {
+                match tokio::fs::metadata(&path).await {
+                    Ok(metadata) if metadata.is_file() => created.push(path),
+                    Ok(metadata) if metadata.is_dir() && server.recursive => {
+                        created.push(path)
+                    }
+                    Ok(_) => (),
+                    // deleted, or moved away
+                    Err(_) => {
+                        server.remove_path(&path).await;
+                    }
+                }
+            }
+            if created.is_empty() {
+                continue;
+            }
+            server.enqueue(created).await;
+            if let Err(e) = server.process_digest(true).await {
+                log::warn!("Cannot serve watched files: {}", e);
+            }
+        }
+    }
+
+    /// Stop serving the file with the given digest.  This method will acquire
+    /// a write lock on `digest`, and also write locks on `paths`, `modified`,
+    /// `duplicates`, `etags`, `enqueued`, `download_counts`, `sizes`, and
+    /// `thumbnails`.  Return whether anything was removed.
+    pub async fn remove_digest(&self, digest: &str) -> bool {
+        let removed = self.digest.write().await.remove(digest);
+        let dups = self.duplicates.write().await.remove(digest);
+        {
+            let mut paths = self.paths.write().await;
+            let mut modified = self.modified.write().await;
+            for path in removed.iter().chain(dups.iter().flatten()) {
+                // the path may have been re-hashed into another digest
+                if paths.get(path).map(String::as_str) == Some(digest) {
+                    paths.remove(path);
+                    modified.remove(path);
+                }
+            }
+        }
+        self.etags.write().await.remove(digest);
+        self.enqueued.write().await.remove(digest);
+        self.download_counts.write().await.remove(digest);
+        if let Some(size) = self.sizes.write().await.remove(digest) {
+            self.total_bytes.fetch_sub(size, Ordering::Relaxed);
+        }
+        self.thumbnails
+            .write()
+            .await
+            .retain(|(d, _), _| d != digest);
+        if let Some(path) = &removed {
+            log::info!("Removed path: {} ({})", path.display(), digest);
+        }
+        removed.is_some()
+    }
+
+    /// Whether the file at `path` has changed in size or modification time
+    /// since it was hashed as `digest`.  A missing file is not considered
+    /// modified.  This method will acquire read locks on `sizes` and
+    /// `modified`.
+    pub async fn is_modified(&self, digest: &str, path: &Path) -> bool {
+        let metadata = match tokio::fs::metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        let size = self.sizes.read().await.get(digest).copied();
+        if size.is_some_and(|size| size != metadata.len()) {
+            return true;
+        }
+        let modified = self.modified.read().await.get(path).copied();
+        match (modified, metadata.modified()) {
+            (Some(hashed), Ok(modified)) => hashed != modified,
+            _ => false,
+        }
+    }
+
+    /// Hash the file at `path` again, as it has changed since it was hashed,
+    /// and serve it under its new digest instead.  See
+    /// [`Server::remove_path`] and [`Server::process_digest`] for the locks
+    /// acquired.
+    pub async fn rehash_path(
+        self: Arc<Self>,
+        path: &Path,
+    ) -> errors::Result<()> {
+        log::info!("Re-hashing modified path: {}", path.display());
+        self.remove_path(path).await;
+        self.files.write().await.push_back(path.to_owned());
+        self.process_digest(false).await
+    }
+
+    /// Whether the file at `path` still has the content of `digest`.  The
+    /// size is compared first, and the file is hashed again only when it
+    /// matches.  This method will acquire a read lock on `sizes`.
+    pub async fn verify_digest(&self, digest: &str, path: &Path) -> bool {
+        let file = match asy::File::open(path).await {
+            Ok(file) => file,
+            Err(_) => return false,
+        };
+        let size = self.sizes.read().await.get(digest).copied();
+        if let (Some(size), Ok(metadata)) = (size, file.metadata().await) {
+            if metadata.len() != size {
+                return false;
+            }
+        }
+        let encoding = self.digest_encoding;
+        hash_file(file, self.hash, encoding, None, self.buf_size)
+            .await
+            .0
+            == digest
+    }
+
+    /// The number of served files, excluding duplicates.  This method will
+    /// acquire a read lock on `digest`.
+    pub async fn file_count(&self) -> usize {
+        self.digest.read().await.len()
+    }
+
+    /// The total size in bytes of all served files, excluding duplicates.
+    pub async fn total_bytes(&self) -> u64 {
+        self.total_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Resolve a digest, or a unique prefix of one, into the full digest and
+    /// its path.  Respond with 404 status when nothing matches, and with 409
+    /// status listing the candidates when the prefix is ambiguous.  This
+    /// method will acquire a read lock on `digest`.
+    pub async fn resolve_digest(
+        &self,
+        prefix: &str,
+    ) -> errors::Result<(String, PathBuf)> {
+        let digest = self.digest.read().await;
+        if let Some(path) = digest.get(prefix) {
+            return Ok((prefix.to_owned(), path.clone()));
+        }
+
+        let mut found: Vec<_> = digest
+            .iter()
+            .filter(|(d, _)| !prefix.is_empty() && d.starts_with(prefix))
+            .collect();
+        match found.len() {
+            0 => Err(StatusCode::NOT_FOUND)?,
+            1 => {
+                let (d, path) = found.remove(0);
+                Ok((d.clone(), path.clone()))
+            }
+            _ => {
+                let mut candidates: Vec<_> =
+                    found.into_iter().map(|(d, _)| d.as_str()).collect();
+                candidates.sort_unstable();
+                Err((
+                    StatusCode::CONFLICT,
+                    format!(
+                        "Ambiguous digest prefix, candidates:\n{}\n",
+                        candidates.join("\n")
+                    ),
+                ))?
+            }
+        }
+    }
+
+    /// Query for an existing digest from the path.  This method will acquire
+    /// a read lock on `paths`.
+    pub async fn query_digest(&self, path: PathBuf) -> Option<String> {
+        self.paths.read().await.get(&path).cloned()
+    }
+
+    /// Describe every served file, sorted by path.  This method will acquire
+    /// read locks on `digest`, `sizes`, and `download_counts`.
+    pub async fn manifest_entries(&self) -> Vec<ManifestEntry> {
+        let digest = self.digest.read().await;
+        let sizes = self.sizes.read().await;
+        let counts = self.download_counts.read().await;
+        let mut entries: Vec<_> = digest
+            .iter()
+            .map(|(d, path)| ManifestEntry {
+                digest: d.clone(),
+                filename: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                path: path.clone(),
+                size: sizes.get(d).copied().unwrap_or_default(),
+                downloads: counts.get(d).copied().unwrap_or_default(),
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+
+    /// Write the manifest of served files to `path` as JSON.  The manifest is
+    /// written to a temporary file in the same directory and then renamed,
+    /// so that `path` never holds a partial manifest.
+    pub async fn write_manifest(&self, path: &Path) -> errors::Result<()> {
+        let json = serde_json::to_vec_pretty(&self.manifest_entries().await)
+            .map_err(std::io::Error::from)?;
+        write_atomic(path, json).await
+    }
+
+    /// Write the digests of served paths to `path` as JSON, to be reused by
+    /// later runs with the same `state_file`.  This method will acquire read
+    /// locks on `hashed` and `paths`.
+    async fn write_state(&self, path: &Path) -> errors::Result<()> {
+        let mut files: Vec<_> = {
+            let hashed = self.hashed.read().await;
+            let paths = self.paths.read().await;
+            hashed
+                .values()
+                // only UTF-8 paths are representable in JSON
+                .filter(|entry| entry.path.to_str().is_some())
+                .filter(|entry| paths.get(&entry.path) == Some(&entry.digest))
+                .cloned()
+                .collect()
+        };
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let state = State {
+            hash: self.hash,
+            encoding: self.digest_encoding,
+            etag: self.hash_algorithm_for_etag.map(EtagAlgorithm::method),
+            files,
+        };
+        let json =
+            serde_json::to_vec_pretty(&state).map_err(std::io::Error::from)?;
+        write_atomic(path, json).await
+    }
+
+    /// The digest, `ETag` value, and size of `path` from the state file, as
+    /// long as the file is unchanged since it was hashed.  This method will
+    /// acquire a read lock on `hashed`.
+    async fn cached_digest(
+        &self,
+        path: &Path,
+        metadata: &Metadata,
+    ) -> Option<(String, Option<String>, u64)> {
+        let hashed = self.hashed.read().await;
+        let entry = hashed.get(path)?;
+        let modified = metadata.modified().ok()?;
+        (entry.size == metadata.len() && entry.modified == modified)
+            .then(|| (entry.digest.clone(), entry.etag.clone(), entry.size))
+    }
+
+    /// Take a token for rendering a QR code or a thumbnail for the client at
+    /// `ip`, when rendering is rate-limited.  This method will acquire a write lock on
+    /// `qr_buckets`.
+    pub async fn limit_qr(&self, ip: Option<IpAddr>) -> errors::Result<()> {
+        let (limit, ip) = match (self.qr_rate_limit, ip) {
+            (Some(limit), Some(ip)) if limit > 0 => (f64::from(limit), ip),
+            _ => return Ok(()),
+        };
+        let rate = limit / 60.0;
+
+        let mut buckets = self.qr_buckets.write().await;
+        // forget clients whose buckets have refilled
+        let now = Instant::now();
+        buckets.retain(|_, bucket| !bucket.is_full(rate, limit, now));
+
+        buckets
+            .entry(ip)
+            .or_insert_with(|| Bucket::new(limit))
+            .take(rate, limit)
+            .map_err(|wait| {
+                log::warn!("Rate-limiting QR code rendering for {}", ip);
+                Error::RateLimited(wait.as_secs_f64().ceil() as u64)
+            })
+    }
+
+    /// Take a token for any request from the client at `ip`, when requests
+    /// are rate-limited.  This method will acquire a write lock on
+    /// `rate_buckets`.
+    pub async fn limit_request(
+        &self,
+        ip: Option<IpAddr>,
+    ) -> errors::Result<()> {
+        let (limit, ip) = match (self.rate_limit, ip) {
+            (Some(limit), Some(ip)) if limit > 0 => (f64::from(limit), ip),
+            _ => return Ok(()),
+        };
+        if self.rate_limit_exempt_loopback && ip.is_loopback() {
+            return Ok(());
+        }
+        let burst = f64::from(self.rate_burst.max(1));
+
+        self.rate_buckets
+            .write()
+            .await
+            .entry(ip)
+            .or_insert_with(|| Bucket::new(burst))
+            .take(limit, burst)
+            .map_err(|wait| {
+                log::warn!("Rate-limiting requests from {}", ip);
+                Error::RateLimited(wait.as_secs_f64().ceil() as u64)
+            })
+    }
+
+    /// Forget the clients whose request buckets have refilled, as they hold
+    /// no information.  This method will acquire a write lock on
+    /// `rate_buckets`.
+    pub async fn purge_rate_buckets(&self) {
+        let limit = match self.rate_limit {
+            Some(limit) if limit > 0 => f64::from(limit),
+            _ => return,
+        };
+        let burst = f64::from(self.rate_burst.max(1));
+        let now = Instant::now();
+        self.rate_buckets
+            .write()
+            .await
+            .retain(|_, bucket| !bucket.is_full(limit, burst, now));
+    }
+
+    /// Count a download by the client at `ip`, when concurrent downloads are
+    /// limited.  The download stays active until the returned guard is
+    /// dropped.
+    pub fn start_download(
+        &self,
+        ip: Option<IpAddr>,
+    ) -> errors::Result<Option<ActiveGuard>> {
+        let (limit, ip) = match (self.max_per_ip, ip) {
+            (Some(limit), Some(ip)) => (limit, ip),
+            _ => return Ok(None),
+        };
+        match self.downloads.acquire(ip, limit) {
+            Some(guard) => Ok(Some(guard)),
+            None => {
+                log::warn!("Too many concurrent downloads from {}", ip);
+                Err((
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "Too many concurrent downloads.\n",
+                ))?
+            }
+        }
+    }
+
+    /// The URL scheme, as configured, or depending on whether TLS is
+    /// configured.
+    pub const fn scheme(&self) -> &'static str {
+        if let Some(scheme) = self.url_scheme {
+            scheme.as_str()
+        } else if self.tls.enabled() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    /// The scheme, host, port and base path of generated URLs, without a
+    /// trailing slash.
+    pub fn base_url(&self) -> String {
+        let host = match &self.external_host {
+            Some(host) => host.clone(),
+            // IPv6 hosts are bracketed
+            None => match self.bind.primary_host() {
+                IpAddr::V6(ip) => format!("[{}]", ip),
+                ip => ip.to_string(),
+            },
+        };
+        let port = self.external_port.unwrap_or_else(|| self.bind.port());
+        format!("{}://{}:{}{}", self.scheme(), host, port, self.base_path)
+    }
+
+    /// Construct the URL for a given file path (left) or digest (right)
+    pub async fn file_url(
+        &self,
+        file: Either<String, PathBuf>,
+    ) -> Option<String> {
+        Some(format!(
+            "{}/{}/?h={}",
+            self.base_url(),
+            self.hash,
+            match file {
+                Either::Left(digest) => digest,
+                Either::Right(path) => self.query_digest(path).await?,
+            }
+        ))
+    }
+
+    /// The signature of the download of `digest` until `expires`, in seconds
+    /// since the Unix epoch, or `None` when signed URLs are not enabled.
+    fn sign(&self, digest: &str, expires: u64) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        let message = format!("{}\n{}\n{}", self.hash, digest, expires);
+        let mac = hash::hmac_sha256(secret.as_bytes(), message.as_bytes());
+        Some(hash::encode(DigestEncoding::Base64url, &mac))
+    }
+
+    /// Construct the signed URL downloading `digest` for `ttl` from now, or
+    /// `None` when signed URLs are not enabled.  See
+    /// [`Server::verify_signed`].
+    pub fn signed_url(&self, digest: &str, ttl: Duration) -> Option<String> {
+        let expires = (SystemTime::now() + ttl)
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        Some(format!(
+            "{}/signed/{}/?h={}&exp={}&sig={}",
+            self.base_url(),
+            self.hash,
+            digest,
+            expires,
+            self.sign(digest, expires)?
+        ))
+    }
+
+    /// Check a signed URL for `digest`, which is answered with 404 status
+    /// when signed URLs are not enabled, 403 status when `signature` does not
+    /// match, and 410 status once `expires` has passed.
+    pub fn verify_signed(
+        &self,
+        digest: &str,
+        expires: u64,
+        signature: &str,
+    ) -> errors::Result<()> {
+        let expected = match self.sign(digest, expires) {
+            Some(expected) => expected,
+            None => Err(StatusCode::NOT_FOUND)?,
+        };
+        // compare in constant time, so that the time does not reveal a
+        // valid prefix
+        if !bool::from(expected.as_bytes().ct_eq(signature.as_bytes())) {
+            Err((StatusCode::FORBIDDEN, "Invalid signature.\n"))?
+        }
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        if now > expires {
+            Err((StatusCode::GONE, "Signed URL has expired.\n"))?
+        }
+        Ok(())
+    }
+
+    /// Construct the URL for the file listing page.
+    pub fn list_url(&self) -> String {
+        format!("{}/list.html", self.base_url())
+    }
+
+    /// The URLs of the file listing page through every reachable address:
+    /// [`Server::list_url`] first, then one for each global address served
+    /// by the bound hosts.  See [`BindOptions::global_hosts`].
+    pub fn list_urls(&self) -> Vec<String> {
+        let mut urls = vec![self.list_url()];
+        for ip in self.bind.global_hosts() {
+            // IPv6 hosts are bracketed
+            let url = format!(
+                "{}://{}{}/list.html",
+                self.scheme(),
+                SocketAddr::new(ip, self.bind.port()),
+                self.base_path
+            );
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        urls
+    }
+
+    /// Construct the QR code URL for a given file path (left) or digest
+    /// (right).  The URL format is "/qr/{method}/?h={hash}".
+    pub async fn qr_url(
+        &self,
+        file: Either<String, PathBuf>,
+    ) -> Option<String> {
+        Some(format!(
+            "{}/qr/{}/?h={}",
+            self.base_url(),
+            self.hash,
+            match file {
+                Either::Left(digest) => digest,
+                Either::Right(path) => self.query_digest(path).await?,
+            }
+        ))
+    }
+
+    /// The entry point to start the file server with [`actix_web`].  On
+    /// Ctrl-C or SIGTERM, the server stops accepting connections and waits
+    /// up to `drain_timeout` for in-flight downloads, then writes the
+    /// manifest, if any, and returns so that all temporary files are removed.
+    pub async fn start_actix(self) -> errors::Result<()> {
+        // the server state is shared, so this sees all later changes
+        let this = self.clone();
+        let http_server = self.http_builder().await?;
+
+        // stop gracefully on the first signal, and immediately on the next
+        let handle = http_server.handle();
+        let drain_timeout = this.drain_timeout;
+        spawn(async move {
+            match shutdown_signal().await {
+                Ok(name) => log::info!(
+                    "Received {}, waiting up to {:?} for downloads to finish",
+                    name,
+                    drain_timeout
+                ),
+                Err(e) => {
+                    log::error!("Cannot listen for shutdown signals: {}", e);
+                    return;
+                }
+            }
+            let stop = spawn(handle.clone().stop(true));
+            if let Ok(name) = shutdown_signal().await {
+                log::warn!("Received {} again, stopping immediately", name);
+                handle.stop(false).await;
+            }
+            stop.await.ok();
+        });
+
+        // report the initially queued files once they are hashed
+        let ready = this.clone();
+        spawn(async move {
+            ready.wait_ready().await;
+            log::info!(
+                "Serving {} file(s), {} bytes in total",
+                ready.file_count().await,
+                ready.total_bytes().await
+            );
+        });
+
+        log::trace!("Starting HTTP server");
+        http_server.await?;
+
+        if let Some(path) = &this.manifest {
+            this.write_manifest(path).await?;
+            log::info!("Wrote manifest to {}", path.display());
+        }
+
+        let mdns_service = this.mdns_service.lock()?.take();
+        if let Some(service) = mdns_service {
+            match service.unregister() {
+                Ok(()) => log::info!("Stopped advertising over mDNS"),
+                Err(e) => {
+                    log::warn!("Cannot stop advertising over mDNS: {}", e)
+                }
+            }
+        }
+
+        // actix usually removes the socket itself when stopped
+        #[cfg(unix)]
+        if let Some(path) = &this.bind.unix_socket {
+            match tokio::fs::remove_file(path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    log::warn!(
+                        "Cannot remove Unix socket {}: {}",
+                        path.display(),
+                        e
+                    )
+                }
+                _ => {}
+            }
+        }
+
+        log::info!("Shut down cleanly");
+        Ok(())
+    }
+
+    /// Bind the listeners, and build the HTTP server.  The queued files are
+    /// processed in the background, see [`Server::wait_ready`].  The returned
+    /// server does not run until it is polled, and can be stopped gracefully
+    /// via [`actix_web::dev::Server::handle`].  It does not handle signals by
+    /// itself; see [`Server::start_actix`].
+    pub async fn http_builder(
+        mut self,
+    ) -> errors::Result<actix_web::dev::Server> {
+        // validate the listen backlog
+        let backlog = self.bind.backlog();
+        if !BindOptions::BACKLOG_RANGE.contains(&backlog) {
+            Err(Error::InvalidArg(format!(
+                "backlog {} is not within {:?}",
+                backlog,
+                BindOptions::BACKLOG_RANGE
+            )))?
+        }
+        log::info!("Using listen backlog: {}", backlog);
+
+        // load the TLS certificates, if any
+        let tls = server_config(&self.tls)?;
+        if tls.is_some() {
+            log::info!(
+                "Serving over TLS, client authentication: {}",
+                self.tls.tls_client_ca.is_some()
+            );
+        }
+
+        // listen the specified TCP ports; when an arbitrary port is requested,
+        // all hosts share the port assigned to the first listener
+        for ip in self.bind.duplicate_hosts() {
+            log::warn!("Ignoring duplicate bind address {}", ip);
+        }
+        let mut port = self.bind.port();
+        let mut listen = Vec::new();
+        for ip in self.bind.hosts_iter() {
+            let addr = SocketAddr::from((ip, port));
+            match tcp_listener(addr, backlog) {
+                Ok(listener) => {
+                    if port == 0 {
+                        port = listener.local_addr()?.port();
+                    }
+                    listen.push(listener);
+                }
+                Err(e) => log::warn!("Cannot bind to {}: {}", addr, e),
+            }
+        }
+        self.bind.port = Some(port);
+        if !self.bind.advertise_host_served() {
+            log::warn!(
+                "Advertised host {} is not a bound or available address",
+                self.bind.primary_host()
+            );
+        }
+
+        // advertise the bound global IPv4 addresses over mDNS
+        if self.mdns {
+            let ips: Vec<_> = self
+                .bind
+                .global_hosts()
+                .into_iter()
+                .filter_map(|ip| match ip {
+                    IpAddr::V4(ip) => Some(ip),
+                    IpAddr::V6(_) => None,
+                })
+                .collect();
+            if ips.is_empty() {
+                log::warn!("No global IPv4 address to advertise over mDNS");
+            } else {
+                match MdnsService::register(&self.mdns_name, &ips, port) {
+                    Ok(service) => {
+                        let host = format!("{}.local", self.mdns_name);
+                        log::info!("Advertising {} over mDNS", host);
+                        *self.mdns_service.lock()? = Some(service);
+                        // the advertised host name is resolvable without DNS
+                        // setup, but only once advertised
+                        self.external_host.get_or_insert(host);
+                    }
+                    Err(e) => log::warn!("Cannot advertise over mDNS: {}", e),
+                }
+            }
+        }
+
+        // save the listing QR code for external displays
+        if let Some(path) = &self.startup_qr_png {
+            match save_qr(
+                &self.qr_transform.apply(&self.list_url()),
+                ImageOptions::Png,
+                &self.qr_style,
+                path,
+            )
+            .await
+            {
+                Ok(()) => log::info!("Saved QR code to {}", path.display()),
+                Err(e) => log::error!(
+                    "Cannot save QR code to {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+
+        // list every reachable URL, as clients may be on any network
+        let urls = self.list_urls();
+        let list: Vec<_> =
+            urls.iter().map(|url| format!("  - {}", url)).collect();
+        log::info!("File listing available at:\n{}", list.join("\n"));
+
+        // print the listing QR codes for headless use, e.g. over SSH
+        if self.qr_terminal {
+            for url in &urls {
+                match qr_to_terminal(&self.qr_transform.apply(url)) {
+                    Ok(qr) => println!("{}\n{}", url, qr),
+                    Err(e) => log::warn!("Cannot print QR code: {}", e),
+                }
+            }
+        }
+
+        // wrap to web data
+        let hsts = self.hsts;
+        let compress = self.compress;
+        let serve_metrics = self.serve_metrics;
+        let metrics = Arc::clone(&self.metrics);
+        let drain_timeout = self.drain_timeout;
+        let base_path = self.base_path.clone();
+        #[cfg(unix)]
+        let unix_socket = self.bind.unix_socket.clone();
+        let this = Data::new(self);
+
+        // process queued files in the background, so that health checks are
+        // answered meanwhile
+        let hashing = Arc::clone(&this);
+        spawn(async move {
+            if let Err(e) = Arc::clone(&hashing).process_digest(false).await {
+                log::error!("Cannot process queued files: {}", e);
+            }
+            hashing.ready.send_replace(true);
+        });
+
+        // serve new files in watched directories until the server is dropped
+        if !this.watch_dirs.is_empty() {
+            let watcher = DirWatcher::new(&this.watch_dirs, this.recursive)?;
+            let weak = Arc::downgrade(&this.clone().into_inner());
+            spawn(Self::run_watcher(weak, watcher));
+        }
+
+        // purge expired files and idle rate limits until the server is
+        // dropped
+        let weak = Arc::downgrade(&this.clone().into_inner());
+        spawn(async move {
+            let mut interval = tokio::time::interval(PURGE_INTERVAL);
+            loop {
+                interval.tick().await;
+                match weak.upgrade() {
+                    Some(server) => {
+                        server.purge_expired().await;
+                        server.purge_rate_buckets().await;
+                    }
+                    None => break,
+                };
+            }
+        });
+
+        // create the HTTP server
+        let http_server = {
+            let mut http_server = HttpServer::new(move || {
+                App::new()
+                    // middlewares: rate limiting, compression, logging, etc.
+                    .wrap(RateLimit)
+                    .wrap(Condition::new(compress, Compress::default()))
+                    .wrap(Logger::new("%a %r => %s @%Dms"))
+                    .wrap(Condition::new(
+                        hsts.is_some(),
+                        Hsts::new(hsts.unwrap_or_default()),
+                    ))
+                    .wrap(Condition::new(
+                        serve_metrics,
+                        ResponseMetrics::new(Arc::clone(&metrics)),
+                    ))
+                    .wrap(ErrorPages)
+                    .wrap(Cors)
+                    // embed server state
+                    .app_data(this.clone())
+                    // main services, under the base path
+                    .service(
+                        scope(&base_path)
+                            .service(get_sha512)
+                            .service(get_signed)
+                            .service(health)
+                            .service(health_ready)
+                            .service(list_files)
+                            .service(list_json)
+                            .service(show_feed)
+                            .service(show_metrics)
+                            .service(favicon)
+                            .service(show_qr)
+                            .service(thumbnail)
+                            .service(browse_archive)
+                            .service(browse_entry)
+                            .service(download_zip)
+                            .service(download_tar)
+                            .service(enqueue_file)
+                            .service(upload_file)
+                            .service(remove_file)
+                            .configure(|cfg| {
+                                // deployment details need credentials
+                                if this.auth.is_some() {
+                                    cfg.service(show_config);
+                                }
+                            }),
+                    )
+                    // redirect (alias) services
+                    .default_service(to(default_service))
+            });
+            http_server = http_server
+                .backlog(backlog)
+                .shutdown_timeout(drain_timeout.as_secs())
+                .disable_signals();
+            for listen in listen {
+                http_server = match &tls {
+                    Some(tls) => http_server.listen_rustls(listen, tls.clone()),
+                    None => http_server.listen(listen),
+                }?
+            }
+            // a local reverse proxy terminates TLS, if any
+            #[cfg(unix)]
+            if let Some(path) = &unix_socket {
+                http_server = http_server.listen_uds(unix_listener(path)?)?;
+                log::info!("Listening on Unix socket {}", path.display());
+            }
+            http_server
+        };
+
+        Ok(http_server.run())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use clap::Parser;
+    use sha2::{Digest, Sha512};
+    use tokio::{
+        io::{AsyncRead, AsyncReadExt, AsyncWriteExt, ReadBuf},
+        net::TcpStream,
+        time::{sleep, timeout},
+    };
+
+    use either::Either;
+    use lib::{
+        config::{DigestEncoding, HashMethod},
+        hash::digest_hex,
+    };
+
+    use super::{hash_file, DirWatcher, Server};
+    use crate::cli::Cli;
+
+    /// A reader counting how many reads it serves.
+    struct CountingReader<'a> {
+        data: &'a [u8],
+        reads: usize,
+    }
+
+    impl AsyncRead for CountingReader<'_> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let this = self.get_mut();
+            this.reads += 1;
+            Pin::new(&mut this.data).poll_read(cx, buf)
+        }
+    }
+
+    /// Larger buffers hash the same content in fewer reads.
+    #[actix_web::test]
+    async fn test_hash_buf_size() {
+        let data = vec![7; 1 << 20];
+        let digest = hex::encode(Sha512::digest(&data));
+        for (buf_size, reads) in [(1 << 10, 1025), (64 << 10, 17)] {
+            let mut reader = CountingReader { data: &data, reads: 0 };
+            let (d, _, size) = hash_file(
+                &mut reader,
+                HashMethod::Sha512,
+                DigestEncoding::Hex,
+                None,
+                buf_size,
+            )
+            .await;
+            assert_eq!((d.as_str(), size), (digest.as_str(), 1 << 20));
+            assert_eq!(reader.reads, reads, "{}", buf_size);
+        }
+    }
+
+    /// The file count and total size follow enqueued and removed files, and
+    /// ignore duplicates.
+    #[actix_web::test]
+    async fn test_file_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = ["a", "b", "c"].map(|name| dir.path().join(name));
+        std::fs::write(&paths[0], b"12345").unwrap();
+        std::fs::write(&paths[1], b"123").unwrap();
+        std::fs::write(&paths[2], b"123").unwrap();
+
+        let files = paths.iter().map(|p| p.as_os_str());
+        let cli =
+            Cli::parse_from(["qrshare".as_ref()].into_iter().chain(files));
+        let server = Arc::new(Server::new(cli).await.unwrap());
+        Arc::clone(&server).process_digest(false).await.unwrap();
+        assert_eq!(server.file_count().await, 2);
+        assert_eq!(server.total_bytes().await, 8);
+
+        let digest = hex::encode(Sha512::digest(b"12345"));
+        assert!(server.remove_digest(&digest).await);
+        assert_eq!(server.file_count().await, 1);
+        assert_eq!(server.total_bytes().await, 3);
+    }
+
+    /// Paths are looked up in both served files and duplicates.
+    #[actix_web::test]
+    async fn test_query_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = ["a", "b", "c"].map(|name| dir.path().join(name));
+        std::fs::write(&paths[0], b"12345").unwrap();
+        std::fs::write(&paths[1], b"123").unwrap();
+        std::fs::write(&paths[2], b"123").unwrap();
+
+        let files = paths.iter().map(|p| p.as_os_str());
+        let cli =
+            Cli::parse_from(["qrshare".as_ref()].into_iter().chain(files));
+        let server = Arc::new(Server::new(cli).await.unwrap());
+        Arc::clone(&server).process_digest(false).await.unwrap();
+
+        let paths = paths.map(|p| p.canonicalize().unwrap());
+        let digest = hex::encode(Sha512::digest(b"123"));
+        for path in &paths[1..] {
+            let found = server.query_digest(path.clone()).await;
+            assert_eq!(found.as_ref(), Some(&digest));
+        }
+
+        assert!(server.remove_digest(&digest).await);
+        for path in &paths[1..] {
+            assert_eq!(server.query_digest(path.clone()).await, None);
+        }
+        let found = server.query_digest(paths[0].clone()).await;
+        assert_eq!(found, Some(hex::encode(Sha512::digest(b"12345"))));
+    }
+
+    /// Files created in a watched directory are served once written, and
+    /// stop being served when deleted.
+    #[actix_web::test]
+    async fn test_watch() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a");
+        std::fs::write(&file, b"initial").unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            format!("--watch={}", dir.path().display()).as_ref(),
+            file.as_os_str(),
+        ]);
+        let server = Arc::new(Server::new(cli).await.unwrap());
+        Arc::clone(&server).process_digest(false).await.unwrap();
+        let watcher = DirWatcher::new(&server.watch_dirs, false).unwrap();
+        tokio::spawn(Server::run_watcher(Arc::downgrade(&server), watcher));
+
+        let served = |digest: String| {
+            let server = Arc::clone(&server);
+            async move { server.digest.read().await.contains_key(&digest) }
+        };
+        let digest = hex::encode(Sha512::digest(b"watched"));
+        let new = dir.path().join("b");
+        std::fs::write(&new, b"watched").unwrap();
+        for _ in 0..100 {
+            if served(digest.clone()).await {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert!(served(digest.clone()).await);
+
+        std::fs::remove_file(&new).unwrap();
+        for _ in 0..100 {
+            if !served(digest.clone()).await {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert!(!served(digest).await);
+        assert_eq!(server.file_count().await, 1);
+    }
+
+    /// The manifest records each served file with its download count, and
+    /// replaces any existing file.
+    #[actix_web::test]
+    async fn test_write_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, b"manifest").unwrap();
+        let path = dir.path().join("manifest.json");
+        std::fs::write(&path, b"old").unwrap();
+
+        let cli = Cli::parse_from(["qrshare".as_ref(), file.as_os_str()]);
+        let server = Arc::new(Server::new(cli).await.unwrap());
+        Arc::clone(&server).process_digest(false).await.unwrap();
+        let digest = hex::encode(Sha512::digest(b"manifest"));
+        server
+            .download_counts
+            .write()
+            .await
+            .insert(digest.clone(), 2);
+
+        server.write_manifest(&path).await.unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "digest": digest,
+                "filename": "a.txt",
+                "path": file.canonicalize().unwrap(),
+                "size": 8,
+                "downloads": 2,
+            }])
+        );
+        // only the file and the manifest remain
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    /// A FIFO is read once into a temporary file, which is served instead.
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn test_fifo() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("fifo");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let writer = {
+            let fifo = fifo.clone();
+            std::thread::spawn(move || std::fs::write(fifo, b"piped").unwrap())
+        };
+
+        let cli = Cli::parse_from(["qrshare", fifo.to_str().unwrap()]);
+        let server = Arc::new(Server::new(cli).await.unwrap());
+        writer.join().unwrap();
+        Arc::clone(&server).process_digest(false).await.unwrap();
+        let digest = hex::encode(Sha512::digest(b"piped"));
+        let path = server.digest.read().await[&digest].clone();
+        assert_ne!(path, fifo);
+        assert_eq!(path.file_name().unwrap(), "fifo");
+        assert_eq!(std::fs::read(&path).unwrap(), b"piped");
+
+        // the buffered copy is deleted with the server
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    /// Glob patterns are expanded only with `--glob`.
+    #[actix_web::test]
+    async fn test_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.pdf", "b.pdf", "c.txt"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        let pattern = dir.path().join("*.pdf");
+        let pattern = pattern.to_str().unwrap();
+
+        let cli = Cli::parse_from(["qrshare", "--glob=true", pattern]);
+        let server = Arc::new(Server::new(cli).await.unwrap());
+        Arc::clone(&server).process_digest(false).await.unwrap();
+        assert_eq!(server.file_count().await, 2);
+
+        let cli = Cli::parse_from(["qrshare", "--strict=true", pattern]);
+        assert!(Server::new(cli).await.is_err());
+        let none = dir.path().join("*.none");
+        let none = none.to_str().unwrap();
+        let args = ["qrshare", "--glob=true", "--strict=true", none];
+        assert!(Server::new(Cli::parse_from(args)).await.is_err());
+    }
+
+    /// Directories are walked up to the maximum depth, without following
+    /// symbolic link loops.
+    #[actix_web::test]
+    async fn test_recursive() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a"), b"a").unwrap();
+        std::fs::write(dir.path().join("sub/b"), b"b").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("sub/loop"))
+            .unwrap();
+
+        for (depth, count) in [(None, 2), (Some("1"), 1)] {
+            let mut args = vec!["qrshare", "--recursive=true"];
+            args.extend(
+                depth.map(|depth| ["--max-depth", depth]).iter().flatten(),
+            );
+            args.push(dir.path().to_str().unwrap());
+            let server = Server::new(Cli::parse_from(args)).await.unwrap();
+            let server = Arc::new(server);
+            Arc::clone(&server).process_digest(false).await.unwrap();
+            assert_eq!(server.file_count().await, count, "{:?}", depth);
+        }
+    }
+
+    /// Hidden and excluded entries are skipped when walking directories, but
+    /// hidden files given directly are served.
+    #[actix_web::test]
+    async fn test_walk_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join("build")).unwrap();
+        for name in [".git/HEAD", ".DS_Store", "build/a", "b.log", "c"] {
+            std::fs::write(root.join(name), name).unwrap();
+        }
+        let hidden = dir.path().join(".hidden");
+        std::fs::write(&hidden, b"hidden").unwrap();
+
+        let args = |extra: &[&str]| {
+            let mut args = vec!["qrshare", "--recursive=true"];
+            args.extend(extra);
+            args.push(root.to_str().unwrap());
+            args.push(hidden.to_str().unwrap());
+            Cli::parse_from(args)
+        };
+        for (extra, count) in [
+            (&[][..], 4),
+            (&["--include-hidden=true"][..], 6),
+            (&["--exclude=*.log", "--exclude=build"][..], 2),
+        ] {
+            let server = Arc::new(Server::new(args(extra)).await.unwrap());
+            Arc::clone(&server).process_digest(false).await.unwrap();
+            assert_eq!(server.file_count().await, count, "{:?}", extra);
+        }
+    }
+
+    /// Files are served by their final extensions, case-insensitively, both
+    /// at startup and when enqueued later.
+    #[actix_web::test]
+    async fn test_ext_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let names = ["a.PDF", "b.png", "c.sh", "d.tar.gz", "e"];
+        let paths = names.map(|name| dir.path().join(name));
+        for path in &paths {
+            std::fs::write(path, path.to_str().unwrap()).unwrap();
+        }
+        let cli = |extra: &[&str]| {
+            let mut args = vec!["qrshare"];
+            args.extend(extra);
+            args.extend(paths.iter().map(|p| p.to_str().unwrap()));
+            Cli::parse_from(args)
+        };
+        for (extra, count) in [
+            (&["--allow-ext=pdf,png"][..], 2),
+            (&["--deny-ext", "sh,.GZ"][..], 3),
+            (&["--allow-ext=pdf,sh", "--deny-ext=sh"][..], 1),
+        ] {
+            let server = Arc::new(Server::new(cli(extra)).await.unwrap());
+            Arc::clone(&server).process_digest(false).await.unwrap();
+            assert_eq!(server.file_count().await, count, "{:?}", extra);
+        }
+        assert!(Server::new(cli(&["--strict=true", "--deny-ext=sh"]))
+            .await
+            .is_err());
+
+        let args = ["qrshare", "--allow-ext=png", paths[1].to_str().unwrap()];
+        let server =
+            Arc::new(Server::new(Cli::parse_from(args)).await.unwrap());
+        server.enqueue([paths[0].clone(), paths[2].clone()]).await;
+        Arc::clone(&server).process_digest(false).await.unwrap();
+        assert_eq!(server.file_count().await, 1);
+    }
+
+    /// Only files under the serve root are enqueued, even through symbolic
+    /// links.
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn test_serve_root() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let seed = root.path().join("seed");
+        std::fs::write(&seed, b"seed").unwrap();
+        std::fs::write(root.path().join("in"), b"in").unwrap();
+        std::fs::write(outside.path().join("out"), b"out").unwrap();
+        let link = root.path().join("link");
+        std::os::unix::fs::symlink(outside.path().join("out"), &link).unwrap();
+
+        let cli = Cli::parse_from([
+            "qrshare",
+            "--serve-root",
+            root.path().to_str().unwrap(),
+            seed.to_str().unwrap(),
+        ]);
+        let server = Arc::new(Server::new(cli).await.unwrap());
+        server
+            .enqueue([
+                root.path().join("in"),
+                outside.path().join("out"),
+                link,
+                // tempfile creates both directories under the same parent
+                root.path()
+                    .join("..")
+                    .join(outside.path().file_name().unwrap())
+                    .join("out"),
+            ])
+            .await;
+        Arc::clone(&server).process_digest(false).await.unwrap();
+        assert_eq!(server.file_count().await, 2);
+        let digest = hex::encode(Sha512::digest("in"));
+        assert!(server.digest.read().await.contains_key(&digest));
+        let digest = hex::encode(Sha512::digest("out"));
+        assert!(!server.digest.read().await.contains_key(&digest));
+    }
+
+    /// Digests in the state file are reused only for unchanged files.
+    #[actix_web::test]
+    async fn test_state_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, b"state").unwrap();
+        let state = dir.path().join("state.json");
+        let args = [
+            "qrshare".as_ref(),
+            "--state-file".as_ref(),
+            state.as_os_str(),
+            file.as_os_str(),
+        ];
+        let process = || async {
+            let server = Server::new(Cli::parse_from(args)).await.unwrap();
+            let server = Arc::new(server);
+            Arc::clone(&server).process_digest(false).await.unwrap();
+            server.query_digest(file.canonicalize().unwrap()).await
+        };
+
+        let digest = hex::encode(Sha512::digest(b"state"));
+        assert_eq!(process().await, Some(digest.clone()));
+
+        // a cached digest is trusted without hashing again
+        let json = std::fs::read_to_string(&state).unwrap();
+        assert!(json.contains(&digest), "{}", json);
+        std::fs::write(&state, json.replace(&digest, "cached")).unwrap();
+        assert_eq!(process().await.as_deref(), Some("cached"));
+
+        // a changed file is hashed again
+        std::fs::write(&file, b"changed").unwrap();
+        let digest = hex::encode(Sha512::digest(b"changed"));
+        assert_eq!(process().await, Some(digest.clone()));
+        let json = std::fs::read_to_string(&state).unwrap();
+        assert!(json.contains(&digest), "{}", json);
+    }
+
+    /// Signed URLs are only accepted with the signature of their digest and
+    /// expiry, until they expire.
+    #[actix_web::test]
+    async fn test_signed_url() {
+        use actix_web::ResponseError;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap();
+        let server = Server::new(Cli::parse_from(["qrshare", path]))
+            .await
+            .unwrap();
+        assert_eq!(server.signed_url("abc", Duration::from_secs(60)), None);
+        let e = server.verify_signed("abc", u64::MAX, "").unwrap_err();
+        assert_eq!(e.status_code().as_u16(), 404);
+
+        let cli = Cli::parse_from(["qrshare", "--secret=s3cret", path]);
+        let server = Server::new(cli).await.unwrap();
+        let url = server.signed_url("abc", Duration::from_secs(60)).unwrap();
+        let (_, query) = url.split_once("/signed/sha512/?").unwrap();
+        let params: Vec<_> = query.split('&').collect();
+        assert_eq!(params.len(), 3, "{}", url);
+        assert_eq!(params[0], "h=abc");
+        let expires = params[1].strip_prefix("exp=").unwrap().parse().unwrap();
+        let signature = params[2].strip_prefix("sig=").unwrap();
+        server.verify_signed("abc", expires, signature).unwrap();
+
+        for (digest, expires, signature, status) in [
+            ("abd", expires, signature, 403),
+            ("abc", expires + 1, signature, 403),
+            ("abc", expires, "", 403),
+        ] {
+            let e = server.verify_signed(digest, expires, signature);
+            assert_eq!(e.unwrap_err().status_code().as_u16(), status);
+        }
+        let expired = server.sign("abc", 1).unwrap();
+        let e = server.verify_signed("abc", 1, &expired).unwrap_err();
+        assert_eq!(e.status_code().as_u16(), 410);
+    }
+
+    /// Generated URLs use the configured scheme, host and port, and default
+    /// to the bound ones.
+    #[actix_web::test]
+    async fn test_base_url() {
+        let cli = Cli::parse_from(["qrshare", "-H127.0.0.1", "-p8080", "."]);
+        let server = Server::new(cli).await.unwrap();
+        assert_eq!(server.list_url(), "http://127.0.0.1:8080/list.html");
+
+        let cli = Cli::parse_from(["qrshare", "-H::1", "-p8080", "."]);
+        let server = Server::new(cli).await.unwrap();
+        assert_eq!(server.list_url(), "http://[::1]:8080/list.html");
+        assert_eq!(server.list_urls(), ["http://[::1]:8080/list.html"]);
+
+        // the mDNS host name is only used once advertised
+        let cli = Cli::parse_from([
+            "qrshare",
+            "-H127.0.0.1",
+            "-p8080",
+            "--mdns=true",
+            "--mdns-name=office",
+            ".",
+        ]);
+        let server = Server::new(cli).await.unwrap();
+        assert_eq!(server.mdns_name, "office");
+        assert_eq!(server.list_url(), "http://127.0.0.1:8080/list.html");
+        let cli = Cli::parse_from(["qrshare", "--mdns-name=a.b", "."]);
+        assert!(Server::new(cli).await.is_err());
+
+        // every bound global address is listed, after the primary one
+        let cli = Cli::parse_from([
+            "qrshare",
+            "-H1.2.3.4",
+            "-H2001:db8::1",
+            "-H127.0.0.1",
+            "-p8080",
+            "--external-host=example.com",
+            ".",
+        ]);
+        let server = Server::new(cli).await.unwrap();
+        assert_eq!(
+            server.list_urls(),
+            [
+                "http://example.com:8080/list.html",
+                "http://1.2.3.4:8080/list.html",
+                "http://[2001:db8::1]:8080/list.html",
+            ]
+        );
+
+        let cli = Cli::parse_from([
+            "qrshare",
+            "-H127.0.0.1",
+            "-p8080",
+            "--scheme=https",
+            "--external-host=example.com",
+            "--external-port=443",
+            ".",
+        ]);
+        let server = Server::new(cli).await.unwrap();
+        assert_eq!(server.list_url(), "https://example.com:443/list.html");
+        assert_eq!(
+            server.qr_url(Either::Left("abc".to_owned())).await.unwrap(),
+            "https://example.com:443/qr/sha512/?h=abc"
+        );
+    }
+
+    /// Files are hashed, and served under the route, in the configured hash
+    /// algorithm.
+    #[actix_web::test]
+    async fn test_hash_method() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"abc").unwrap();
+
+        for method in ["sha256", "sha512", "blake2b", "blake3"] {
+            let cli = Cli::parse_from([
+                "qrshare".as_ref(),
+                format!("--hash={}", method).as_ref(),
+                file.path().as_os_str(),
+            ]);
+            let server = Arc::new(Server::new(cli).await.unwrap());
+            Arc::clone(&server).process_digest(false).await.unwrap();
+
+            let expected = digest_hex(server.hash, "abc");
+            let url = server
+                .file_url(Either::Right(file.path().canonicalize().unwrap()))
+                .await
+                .unwrap();
+            assert!(url.ends_with(&format!("/{}/?h={}", method, expected)));
+        }
+    }
+
+    /// The server becomes ready once the initially queued files are
+    /// processed.
+    #[actix_web::test]
+    async fn test_wait_ready() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"ready").unwrap();
+
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "-H127.0.0.1".as_ref(),
+            "-p0".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Server::new(cli).await.unwrap();
+        let waiter = server.clone();
+        let wait = timeout(Duration::from_millis(100), waiter.wait_ready());
+        assert!(wait.await.is_err());
+
+        let _http_server = server.http_builder().await.unwrap();
+        let wait = timeout(Duration::from_secs(1), waiter.wait_ready());
+        wait.await.unwrap();
+        assert_eq!(waiter.file_count().await, 1);
+    }
+
+    /// Start a server delaying downloads by one second, request `content`, and
+    /// stop the server while the download is delayed.  Return the response.
+    #[cfg(debug_assertions)]
+    async fn stop_during_download(content: &[u8]) -> String {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+            .to_string();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, content).unwrap();
+        let digest = hex::encode(Sha512::digest(content));
+
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "-H127.0.0.1".as_ref(),
+            "-p".as_ref(),
+            port.as_ref(),
+            "--debug-delay=1000".as_ref(),
+            "--drain-timeout=5".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Server::new(cli).await.unwrap();
+        let ready = server.clone();
+        let http_server = server.http_builder().await.unwrap();
+        let handle = http_server.handle();
+        let http_server = tokio::spawn(http_server);
+        ready.wait_ready().await;
+
+        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))
+            .await
+            .unwrap();
+
+        // a connection still queued in the accept thread is dropped on stop,
+        // so wait for a response on the same connection, which proves that a
+        // worker owns it, before requesting the download
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut health = Vec::new();
+        let mut buf = [0; 1024];
+        while !health_complete(&health) {
+            let read = stream.read(&mut buf).await.unwrap();
+            assert_ne!(read, 0, "{}", String::from_utf8_lossy(&health));
+            health.extend_from_slice(&buf[..read]);
+        }
+
+        let request = format!(
+            "GET /sha512/?h={} HTTP/1.1\r\nHost: localhost\r\n\
+             Connection: close\r\n\r\n",
+            digest
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        sleep(Duration::from_millis(300)).await;
+        let stop = tokio::spawn(handle.stop(true));
+
+        let mut response = String::new();
+        timeout(Duration::from_secs(5), stream.read_to_string(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+
+        stop.await.unwrap();
+        http_server.await.unwrap().unwrap();
+        response
+    }
+
+    /// Whether `response` holds the headers and the whole body, as given by
+    /// `Content-Length`.
+    fn health_complete(response: &[u8]) -> bool {
+        let response = String::from_utf8_lossy(response);
+        let (headers, body) = match response.split_once("\r\n\r\n") {
+            Some(split) => split,
+            None => return false,
+        };
+        let length = headers
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            })
+            .unwrap_or_default();
+        body.len() >= length
+    }
+
+    /// The server listens on a Unix socket alongside TCP, which requires an
+    /// external host for generated URLs.
+    #[cfg(unix)]
+    #[actix_web::test]
+    async fn test_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("qrshare.sock");
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, b"unix").unwrap();
+        let args = |external: &[&str]| {
+            let mut args = vec!["qrshare".to_owned(), "-H127.0.0.1".to_owned()];
+            args.push(format!("--unix-socket={}", socket.display()));
+            args.extend(external.iter().map(|arg| arg.to_string()));
+            args.push(file.display().to_string());
+            Cli::parse_from(args)
+        };
+        assert!(Server::new(args(&[])).await.is_err());
+
+        // a stale socket is replaced
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        let server = Server::new(args(&["--external-host=example.com"]))
+            .await
+            .unwrap();
+        let ready = server.clone();
+        let http_server = server.http_builder().await.unwrap();
+        let handle = http_server.handle();
+        let http_server = tokio::spawn(http_server);
+        ready.wait_ready().await;
+
+        let mut stream =
+            tokio::net::UnixStream::connect(&socket).await.unwrap();
+        let digest = hex::encode(Sha512::digest(b"unix"));
+        let request = format!("GET /sha512/?h={} HTTP/1.0\r\n\r\n", digest);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.0 200"), "{}", response);
+        assert!(response.ends_with("unix"), "{}", response);
+
+        handle.stop(true).await;
+        http_server.await.unwrap().unwrap();
+    }
+
+    /// A download in progress when the server is stopped completes within the
+    /// drain timeout.
+    #[cfg(debug_assertions)]
+    #[actix_web::test]
+    async fn test_drain_timeout() {
+        let content = "slow download";
+        let response = stop_during_download(content.as_bytes()).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with(content), "{}", response);
     }
 }
@@ -4,27 +4,147 @@
 use std::path::PathBuf;
 
 use actix_http::StatusCode;
+use actix_multipart::Multipart;
 use actix_web::{
-    get, post,
-    web::{Data, Json, Query},
-    HttpResponse, Responder,
+    delete, get, post, route,
+    web::{Data, Json, Path, Query},
+    HttpRequest, HttpResponse, Responder,
 };
 use either::Either;
 
-use crate::Server;
-use lib::errors;
+use crate::{middleware::BasicAuth, server::FileLimits, Server};
+use lib::{config::HumanDuration, errors};
 
 #[derive(serde::Deserialize)]
 struct GetQuery {
     #[serde(rename = "h")]
     digest: String,
+    /// Whether the browser should display the file or save it, overriding
+    /// `--force-download`.
+    disposition: Option<Disposition>,
 }
 
+/// The query of a signed download URL.  See [`Server::signed_url`].
+#[derive(serde::Deserialize)]
+struct SignedQuery {
+    #[serde(rename = "h")]
+    digest: String,
+    /// The expiry, in seconds since the Unix epoch.
+    #[serde(rename = "exp")]
+    expires: u64,
+    #[serde(rename = "sig")]
+    signature: String,
+    disposition: Option<Disposition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Disposition {
+    Inline,
+    Attachment,
+}
+
+#[derive(serde::Deserialize)]
+struct QrQuery {
+    #[serde(rename = "h")]
+    digest: String,
+    /// The QR code format, `png` or `svg`, overriding content negotiation.
+    #[serde(alias = "format")]
+    fmt: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct ListQuery {
+    /// Only list files whose names contain this string, case-insensitively.
+    q: Option<String>,
+    /// The column to sort the listing by, by default the file name.
+    sort: Option<SortKey>,
+    /// The sort order, by default ascending.
+    order: Option<SortOrder>,
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortKey {
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// A served file, as listed in `list.json` and in upload responses.
+#[derive(serde::Serialize)]
+struct ListEntry {
+    digest: String,
+    filename: String,
+    download_url: String,
+    /// Only with a configured secret.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signed_url: Option<String>,
+    qr_url: String,
+    size: u64,
+}
+
+/// The state of the server, as reported by `/health`.
+#[derive(serde::Serialize)]
+struct Health {
+    uptime_secs: u64,
+    files: usize,
+    ready: bool,
+}
+
+/// The digests of files to bundle, from repeated `h` parameters.
+#[derive(serde::Deserialize)]
+#[serde(from = "Vec<(String, String)>")]
+struct TarQuery {
+    digests: Vec<String>,
+}
+
+impl From<Vec<(String, String)>> for TarQuery {
+    fn from(params: Vec<(String, String)>) -> Self {
+        let digests = params
+            .into_iter()
+            .filter(|(key, _)| key == "h")
+            .map(|(_, digest)| digest)
+            .collect();
+        Self { digests }
+    }
+}
+
+/// The widths of generated thumbnails, in pixels.
+const THUMB_WIDTHS: [u32; 5] = [64, 128, 256, 512, 1024];
+
+#[derive(serde::Deserialize)]
+struct ThumbQuery {
+    /// The maximum thumbnail width, in pixels, rounded up to one of
+    /// [`THUMB_WIDTHS`].
+    #[serde(rename = "w")]
+    width: Option<u32>,
+}
+
+/// Paths to enqueue, with an optional time-to-live and download limit
+/// overriding `--ttl` and `--max-downloads`.
 #[derive(serde::Deserialize)]
 #[serde(untagged)]
 enum Enqueue {
-    Single { path: PathBuf },
-    Multiple { path: Vec<PathBuf> },
+    Single {
+        path: PathBuf,
+        ttl: Option<HumanDuration>,
+        max_downloads: Option<u64>,
+    },
+    Multiple {
+        path: Vec<PathBuf>,
+        ttl: Option<HumanDuration>,
+        max_downloads: Option<u64>,
+    },
 }
 
 type Iter<II> = <II as IntoIterator>::IntoIter;
@@ -33,8 +153,26 @@ type EnqueueIter = Either<Iter<[PathBuf; 1]>, Iter<Vec<PathBuf>>>;
 impl Enqueue {
     pub fn into_paths(self) -> EnqueueIter {
         match self {
-            Self::Single { path } => Either::Left([path].into_iter()),
-            Self::Multiple { path } => Either::Right(path.into_iter()),
+            Self::Single { path, .. } => Either::Left([path].into_iter()),
+            Self::Multiple { path, .. } => Either::Right(path.into_iter()),
+        }
+    }
+
+    /// The number of paths to enqueue.
+    pub fn path_count(&self) -> usize {
+        match self {
+            Self::Single { .. } => 1,
+            Self::Multiple { path, .. } => path.len(),
+        }
+    }
+
+    pub fn limits(&self) -> FileLimits {
+        match self {
+            Self::Single { ttl, max_downloads, .. }
+            | Self::Multiple { ttl, max_downloads, .. } => FileLimits {
+                ttl: ttl.map(|ttl| ttl.0),
+                max_downloads: *max_downloads,
+            },
         }
     }
 }
@@ -46,53 +184,127 @@ impl IntoIterator for Enqueue {
     }
 }
 
-#[get("/sha512/")]
+/// Whether `method` names the configured hash algorithm, as routes are
+/// named after it.
+fn check_method(server: &Server, method: &str) -> errors::Result<()> {
+    if method == server.hash.as_str() {
+        Ok(())
+    } else {
+        Err(StatusCode::NOT_FOUND.into())
+    }
+}
+
+/// Download a file, or only report its headers with `HEAD`.
+#[route(
+    "/{method}/",
+    method = "GET",
+    method = "HEAD",
+    wrap = "BasicAuth::download()"
+)]
 #[inline]
 async fn get_sha512(
+    req: HttpRequest,
+    method: Path<String>,
     query: Query<GetQuery>,
     server: Data<Server>,
 ) -> impl Responder {
     log::trace!("get_sha512()");
-    inner::do_get_sha512(query, server).await
+    check_method(&server, &method)?;
+    inner::do_get_sha512(req, query, server).await
+}
+
+/// Download a file through a signed URL, which stops working once it
+/// expires.  See [`Server::signed_url`].
+#[route(
+    "/signed/{method}/",
+    method = "GET",
+    method = "HEAD",
+    wrap = "BasicAuth::download()"
+)]
+#[inline]
+async fn get_signed(
+    req: HttpRequest,
+    method: Path<String>,
+    query: Query<SignedQuery>,
+    server: Data<Server>,
+) -> impl Responder {
+    log::trace!("get_signed()");
+    check_method(&server, &method)?;
+    let Query(SignedQuery { digest, expires, signature, disposition }) = query;
+    server.verify_signed(&digest, expires, &signature)?;
+    let query = Query(GetQuery { digest, disposition });
+    inner::do_get_sha512(req, query, server).await
 }
 
 /// Default service: list all available files.  See also [`list_files`].
-pub async fn default_service() -> impl Responder {
+pub async fn default_service(server: Data<Server>) -> impl Responder {
     log::trace!("list_files_noext()");
     HttpResponse::PermanentRedirect()
-        .append_header(("Location", "/list.html"))
+        .append_header(("Location", format!("{}/list.html", server.base_path)))
         .finish()
 }
 
-#[get("/list.html")]
+#[get("/list.html", wrap = "BasicAuth::download()")]
 #[inline]
-async fn list_files(server: Data<Server>) -> errors::Result<impl Responder> {
+async fn list_files(
+    server: Data<Server>,
+    query: Query<ListQuery>,
+) -> errors::Result<impl Responder> {
     log::trace!("list_files()");
-    inner::do_list_files(server).await
+    inner::do_list_files(server, query).await
 }
 
-/// Whether we should forbid remote file enqueuing.  Forbidding remote file
-/// enqueuing *should* still allow "local" (127.0.0.1, ::1) connections to
-/// enqueue the files?  Or maybe just add HTTP authentication and call it good.
-///
-/// This probably needs more thoughts.  TODO
+/// List all available files as JSON, for client applications.
+#[get("/list.json", wrap = "BasicAuth::download()")]
+#[inline]
+async fn list_json(
+    server: Data<Server>,
+    query: Query<ListQuery>,
+) -> errors::Result<impl Responder> {
+    log::trace!("list_json()");
+    inner::do_list_json(server, query).await
+}
+
+/// An RSS feed of all available files, newest first.
+#[get("/feed.xml", wrap = "BasicAuth::download()")]
+#[inline]
+async fn show_feed(server: Data<Server>) -> errors::Result<impl Responder> {
+    log::trace!("show_feed()");
+    inner::do_feed(server).await
+}
+
+/// Whether we should forbid remote file enqueuing without authentication.
+/// Loopback (127.0.0.1, ::1) connections may still enqueue files.
 const FORBID_REMOTE_ENQUEUE: bool = !cfg!(feature = "insecure");
 
+/// Whether to forbid enqueuing and similar requests.  They are allowed with
+/// feature "insecure", when authentication is configured, as [`BasicAuth`]
+/// has then authenticated the request, or from loopback peers.
+///
+/// The peer is the direct connection, so behind a reverse proxy on the same
+/// host every request is from loopback; configure authentication instead.
+fn forbid_enqueue(req: &HttpRequest, server: &Server) -> bool {
+    let loopback = req.peer_addr().is_some_and(|addr| addr.ip().is_loopback());
+    FORBID_REMOTE_ENQUEUE && server.auth.is_none() && !loopback
+}
+
 /// # SECURITY NOTE
 ///
 /// Care must be taken here.  By allowing this API, we are essentially allowing
 /// a remote user to retrieve all files accessible to the current user.
 ///
-/// For now, this is only allowed with feature "insecure".
-#[post("/serve")]
+/// For now, this is only allowed with feature "insecure", with HTTP Basic
+/// authentication, or from loopback peers.
+#[post("/serve", wrap = "BasicAuth::new()")]
 #[inline]
 async fn enqueue_file(
+    req: HttpRequest,
     server: Data<Server>,
     body: Json<Enqueue>,
 ) -> impl Responder {
     log::trace!("enqueue_file()");
 
-    if FORBID_REMOTE_ENQUEUE {
+    if forbid_enqueue(&req, &server) {
         log::trace!("enqueue_file() is forbidden.");
         Err(StatusCode::FORBIDDEN.into())
     } else {
@@ -100,89 +312,510 @@ async fn enqueue_file(
     }
 }
 
+/// Upload files as `multipart/form-data` into the configured upload
+/// directory, and serve them.  This is subject to the same restrictions as
+/// [`enqueue_file`], and is only enabled with `--upload-dir`.
+#[post("/upload", wrap = "BasicAuth::new()")]
+#[inline]
+async fn upload_file(
+    req: HttpRequest,
+    server: Data<Server>,
+    payload: Multipart,
+) -> impl Responder {
+    log::trace!("upload_file()");
+
+    if forbid_enqueue(&req, &server) {
+        log::trace!("upload_file() is forbidden.");
+        Err(StatusCode::FORBIDDEN.into())
+    } else {
+        inner::do_upload_file(server, payload).await
+    }
+}
+
+/// Stop serving a file.  This is subject to the same restrictions as
+/// [`enqueue_file`].
+#[delete("/{method}/", wrap = "BasicAuth::new()")]
+#[inline]
+async fn remove_file(
+    req: HttpRequest,
+    server: Data<Server>,
+    method: Path<String>,
+    query: Query<GetQuery>,
+) -> impl Responder {
+    log::trace!("remove_file()");
+    check_method(&server, &method)?;
+
+    if forbid_enqueue(&req, &server) {
+        log::trace!("remove_file() is forbidden.");
+        Err(StatusCode::FORBIDDEN.into())
+    } else {
+        inner::do_remove_file(server, query).await
+    }
+}
+
+/// Show the effective configuration, with secrets redacted.  This is only
+/// registered when authentication is configured, to avoid leaking deployment
+/// details, and always requires credentials.
+#[get("/config", wrap = "BasicAuth::new()")]
+#[inline]
+async fn show_config(server: Data<Server>) -> impl Responder {
+    log::trace!("show_config()");
+    inner::do_show_config(server).await
+}
+
+/// Report the uptime, the number of served files, and whether the initially
+/// queued files are hashed, for process supervisors.  This never requires
+/// authentication, nor touches the file system.
+#[get("/health")]
+#[inline]
+async fn health(server: Data<Server>) -> errors::Result<impl Responder> {
+    log::trace!("health()");
+    inner::do_health(server, false).await
+}
+
+/// Same as [`health`], but with 503 status until the initially queued files
+/// are hashed.
+#[get("/health/ready")]
+#[inline]
+async fn health_ready(server: Data<Server>) -> errors::Result<impl Responder> {
+    log::trace!("health_ready()");
+    inner::do_health(server, true).await
+}
+
+/// Counters in the Prometheus text format, with `--metrics`.  Otherwise 404
+/// status.
+#[get("/metrics")]
+#[inline]
+async fn show_metrics(server: Data<Server>) -> errors::Result<impl Responder> {
+    log::trace!("show_metrics()");
+    inner::do_show_metrics(server).await
+}
+
 /// Favicon
 #[get("/favicon.ico")]
 #[inline]
-async fn favicon(_: Data<Server>) -> impl Responder {
+async fn favicon(req: HttpRequest, server: Data<Server>) -> impl Responder {
     log::trace!("favicon()");
-    inner::serve_file_at("favicon.ico".as_ref()).await
+    inner::do_favicon(req, server).await
 }
 
 /// Show QR code image
-#[get("/qr/sha512/")]
+#[get("/qr/{method}/", wrap = "BasicAuth::download()")]
 #[inline]
 async fn show_qr(
+    req: HttpRequest,
     server: Data<Server>,
-    query: Query<GetQuery>,
+    method: Path<String>,
+    query: Query<QrQuery>,
 ) -> impl Responder {
     log::trace!("show_qr()");
-    inner::do_show_qr(server, query).await
+    check_method(&server, &method)?;
+    inner::do_show_qr(req, server, query).await
+}
+
+/// Show a thumbnail of an image file
+#[get("/thumb/{digest}", wrap = "BasicAuth::download()")]
+#[inline]
+async fn thumbnail(
+    req: HttpRequest,
+    server: Data<Server>,
+    digest: Path<String>,
+    query: Query<ThumbQuery>,
+) -> impl Responder {
+    log::trace!("thumbnail()");
+    inner::do_thumbnail(req, server, digest, query).await
+}
+
+/// List the entries of an archive file
+#[get("/browse/{digest}/", wrap = "BasicAuth::download()")]
+#[inline]
+async fn browse_archive(
+    server: Data<Server>,
+    digest: Path<String>,
+) -> impl Responder {
+    log::trace!("browse_archive()");
+    inner::do_browse_archive(server, digest).await
+}
+
+/// Stream a single entry of an archive file
+#[get("/browse/{digest}/{entry:.+}", wrap = "BasicAuth::download()")]
+#[inline]
+async fn browse_entry(
+    req: HttpRequest,
+    server: Data<Server>,
+    path: Path<(String, String)>,
+) -> impl Responder {
+    log::trace!("browse_entry()");
+    inner::do_browse_entry(req, server, path).await
+}
+
+/// Download all available files as a single zip archive
+#[get("/download/all.zip", wrap = "BasicAuth::download()")]
+#[inline]
+async fn download_zip(
+    req: HttpRequest,
+    server: Data<Server>,
+) -> impl Responder {
+    log::trace!("download_zip()");
+    inner::do_download_zip(req, server).await
+}
+
+/// Download the selected files as a single tar archive
+#[get("/download/tar", wrap = "BasicAuth::download()")]
+#[inline]
+async fn download_tar(
+    req: HttpRequest,
+    server: Data<Server>,
+    query: Query<TarQuery>,
+) -> impl Responder {
+    log::trace!("download_tar()");
+    inner::do_download_tar(req, server, query).await
 }
 
 mod inner {
     //! Implementation for services.
 
     use std::{
+        collections::HashSet,
         ffi::OsStr,
         fmt::Display,
-        path::{Path, PathBuf},
+        fs::{File, Metadata},
+        future::ready,
+        io::{self, BufWriter, Write},
+        path::{Component, Path, PathBuf},
         sync::Arc,
+        time::UNIX_EPOCH,
     };
 
-    use actix_files::NamedFile;
+    use actix_files::{file_extension_to_mime, NamedFile};
     use actix_http::StatusCode;
+    use actix_multipart::Multipart;
     use actix_web::{
-        http::header::ContentType,
-        web::{Data, Json, Query},
-        HttpResponse, Responder,
+        body::{BodySize, MessageBody},
+        http::header::{
+            self, Accept, Charset, ContentDisposition, ContentType,
+            DispositionParam, DispositionType, ETag, EntityTag, ExtendedValue,
+            HeaderValue, HttpDate, IfNoneMatch, TryIntoHeaderPair,
+        },
+        http::Method,
+        web::{self, Bytes, Data, Json, Query},
+        HttpMessage, HttpRequest, HttpResponse, Responder,
     };
     use build_html::{Html, HtmlContainer, HtmlPage, Table};
+    use clap::ValueEnum;
     use either::Either;
+    use futures::{StreamExt, TryStreamExt};
+    use lib::{
+        archive,
+        config::{ImageOptions, ListTheme},
+        qr::gen::{render_png, render_qr, render_qr_with_fallback},
+        ratelimit::{ActiveGuard, GuardedBody, ThrottledBody},
+        thumb::thumbnail,
+        utils::{data_uri, format_size, format_time, path_encode, xml_escape},
+    };
     use qrcode::QrCode;
+    use tokio::{
+        io::{AsyncWrite, AsyncWriteExt},
+        sync::{mpsc, oneshot},
+    };
+    use tokio_util::io::ReaderStream;
 
-    use super::{Enqueue, GetQuery};
+    use super::{
+        Disposition, Enqueue, GetQuery, Health, ListEntry, ListQuery, QrQuery,
+        SortKey, SortOrder, TarQuery, ThumbQuery, THUMB_WIDTHS,
+    };
     use crate::Server;
     use lib::errors;
 
+    /// Writes chunks to a streamed response body from a blocking thread,
+    /// failing once the client disconnects.
+    struct ChannelWriter(mpsc::Sender<io::Result<Bytes>>);
+
+    impl Write for ChannelWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0
+                .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+                .map_err(|_| io::ErrorKind::BrokenPipe)?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Stream the chunks received from `rx` as a response body.
+    fn body_stream(
+        rx: mpsc::Receiver<io::Result<Bytes>>,
+    ) -> impl futures::Stream<Item = io::Result<Bytes>> {
+        futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (chunk, rx))
+        })
+    }
+
     pub(super) async fn do_get_sha512(
-        Query(GetQuery { digest: d }): Query<GetQuery>,
+        req: HttpRequest,
+        Query(GetQuery { digest: d, disposition }): Query<GetQuery>,
         server: Data<Server>,
     ) -> errors::Result<impl Responder> {
         log::trace!("/sha512");
-        let path = {
-            let digest = server.digest.read().await;
-            digest.get(&d).ok_or(StatusCode::NOT_FOUND)?.to_owned()
+        let (d, path, limit) = servable_file(&server, &d).await?;
+
+        // never serve content that does not match the requested digest
+        if server.is_modified(&d, &path).await {
+            log::warn!("Content changed since hashing: {}", path.display());
+            server.clone().into_inner().rehash_path(&path).await?;
+            let url = server.file_url(Either::Right(path.clone())).await;
+            Err((
+                StatusCode::CONFLICT,
+                match url {
+                    Some(url) => format!(
+                        "File content has changed since it was hashed, \
+                        now served at {}\n",
+                        url
+                    ),
+                    None => {
+                        "File content has changed since it was hashed.\n".into()
+                    }
+                },
+            ))?
+        }
+
+        // a `HEAD` request shares the headers of a download, but the body is
+        // never sent, so it is not counted as one
+        let head = req.method() == Method::HEAD;
+        let guard = match head {
+            true => None,
+            false => {
+                server.start_download(req.peer_addr().map(|addr| addr.ip()))?
+            }
+        };
+
+        if server.verify_on_download && !server.verify_digest(&d, &path).await {
+            log::warn!("Content changed since hashing: {}", path.display());
+            Err((
+                StatusCode::CONFLICT,
+                "File content has changed since it was hashed.\n",
+            ))?
+        }
+
+        // the file content is immutable, so a matching ETag is always fresh.
+        // Without a dedicated algorithm, the digest itself is the ETag.
+        let etag = server.etags.read().await.get(&d).cloned();
+        let etag = EntityTag::new_strong(etag.unwrap_or_else(|| d.clone()));
+        let fresh = match req.get_header() {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(tags)) => {
+                tags.iter().any(|tag| tag.weak_eq(&etag))
+            }
+            None => false,
         };
+        if fresh {
+            return Ok(HttpResponse::NotModified()
+                .insert_header(ETag(etag))
+                .finish());
+        }
 
         let filename = path
             .file_name()
             .and_then(OsStr::to_str)
-            .ok_or(StatusCode::NOT_FOUND)?
-            .to_string();
-        let header = (
-            "Content-Disposition",
-            format!(r#"attachment; filename="{}""#, filename),
-        );
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let inline = match disposition {
+            Some(disposition) => disposition == Disposition::Inline,
+            None => !server.force_download,
+        };
+        let content_type = if inline || !server.force_download {
+            guess_mime(&path).await
+        } else {
+            mime::APPLICATION_OCTET_STREAM
+        };
+        let disposition = match inline {
+            true => DispositionType::Inline,
+            false => DispositionType::Attachment,
+        };
+        let disposition = content_disposition(disposition, filename);
 
-        let bytes = tokio::fs::read(path)
+        #[cfg(debug_assertions)]
+        if let Some(delay) = server.debug_delay {
+            log::debug!("Delaying download by {:?}", delay);
+            tokio::time::sleep(delay).await;
+        }
+
+        // stream the file instead of reading it into memory, which also
+        // handles range requests with 64-bit offsets regardless of the target
+        let file = NamedFile::open_async(&path)
             .await
             .map_err(|_| StatusCode::NOT_FOUND)?;
 
-        Ok(HttpResponse::build(StatusCode::OK)
-            .insert_header(header)
-            .message_body(bytes)
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?)
+        let mut resp = file
+            .set_content_type(content_type)
+            .set_content_disposition(disposition)
+            .use_etag(false)
+            .use_last_modified(false)
+            .into_response(&req);
+
+        // only count downloads that are served, not failed range requests
+        if !head
+            && matches!(
+                resp.status(),
+                StatusCode::OK | StatusCode::PARTIAL_CONTENT
+            )
+        {
+            count_download(&server, &d, &path, limit).await?;
+            if server.serve_metrics {
+                // streamed files always have a known size
+                if let BodySize::Sized(bytes) = resp.body().size() {
+                    server.metrics.count_download(bytes);
+                }
+            }
+        }
+        // a quoted hex string is always a valid header value
+        let (name, value) = ETag(etag).try_into_pair().unwrap();
+        resp.headers_mut().insert(name, value);
+        // files are served as they are, and are often compressed already
+        resp.headers_mut().insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static("identity"),
+        );
+        Ok(limit_body(&server, resp.map_into_boxed_body(), guard))
+    }
+
+    /// Resolve `digest` into a served file that has neither expired nor
+    /// reached its download limit.  Return its full digest, its path, and its
+    /// download limit.
+    async fn servable_file(
+        server: &Server,
+        digest: &str,
+    ) -> errors::Result<(String, PathBuf, Option<u64>)> {
+        if server.digest_expired(digest).await {
+            Err((StatusCode::GONE, "File has expired.\n"))?
+        }
+        let (digest, path) = server.resolve_digest(digest).await?;
+        if server.is_expired(&path).await {
+            Err((StatusCode::GONE, "File has expired.\n"))?
+        }
+        let limit = server.download_limit(&path).await;
+        let count = server.download_counts.read().await.get(&digest).copied();
+        if limit.is_some_and(|limit| count.unwrap_or_default() >= limit) {
+            Err((StatusCode::GONE, "Download limit reached.\n"))?
+        }
+        Ok((digest, path, limit))
+    }
+
+    /// Count a download of the file at `path` with `digest`, and stop serving
+    /// it once it reaches its download `limit`.  This fails when concurrent
+    /// downloads have reached the limit since [`servable_file`].
+    async fn count_download(
+        server: &Server,
+        digest: &str,
+        path: &Path,
+        limit: Option<u64>,
+    ) -> errors::Result<()> {
+        let mut counts = server.download_counts.write().await;
+        let count = counts.entry(digest.to_owned()).or_default();
+        if limit.is_some_and(|limit| *count >= limit) {
+            Err((StatusCode::GONE, "Download limit reached.\n"))?
+        }
+        *count += 1;
+        if limit.is_some_and(|limit| *count >= limit) {
+            drop(counts);
+            log::info!(
+                "Download limit reached: {} ({})",
+                path.display(),
+                digest
+            );
+            server.retire_digest(digest).await;
+        }
+        Ok(())
+    }
+
+    /// Throttle the body of `resp` to `max_bps`, and keep the download
+    /// active with `guard` until the body is dropped.
+    fn limit_body(
+        server: &Server,
+        resp: HttpResponse,
+        guard: Option<ActiveGuard>,
+    ) -> HttpResponse {
+        let resp = match server.max_bps {
+            0 => resp,
+            bps => resp
+                .map_body(|_, body| ThrottledBody::new(body, bps))
+                .map_into_boxed_body(),
+        };
+        match guard {
+            Some(guard) => resp
+                .map_body(|_, body| GuardedBody::new(body, guard))
+                .map_into_boxed_body(),
+            None => resp,
+        }
     }
 
     fn a_href(url: impl Display, desc: impl Display) -> String {
-        format!(r#"<a href="{}">{}</a>"#, url, desc)
+        format!(
+            r#"<a href="{}">{}</a>"#,
+            xml_escape(&url.to_string()),
+            xml_escape(&desc.to_string())
+        )
+    }
+
+    /// Guess the MIME type of the file at `path` from its extension, or from
+    /// its leading bytes when it has no extension.
+    async fn guess_mime(path: &Path) -> mime::Mime {
+        if let Some(ext) = path.extension().and_then(OsStr::to_str) {
+            return file_extension_to_mime(ext);
+        }
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || infer::get_from_path(path))
+            .await
+            .ok()
+            .and_then(Result::ok)
+            .flatten()
+            .and_then(|kind| kind.mime_type().parse().ok())
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM)
     }
 
-    /// Convert a digest pair into HTML strings.
+    /// The `Content-Disposition` of a download named `filename`.  Non-ASCII
+    /// names are sent as an RFC 5987 `filename*` parameter, after an ASCII
+    /// `filename` fallback for older clients.
+    pub(super) fn content_disposition(
+        disposition: DispositionType,
+        filename: &str,
+    ) -> ContentDisposition {
+        let mut parameters = vec![];
+        if filename.is_ascii() {
+            parameters.push(DispositionParam::Filename(filename.to_owned()));
+        } else {
+            let fallback = filename
+                .chars()
+                .map(|c| if c.is_ascii() { c } else { '_' })
+                .collect();
+            parameters.push(DispositionParam::Filename(fallback));
+            parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_owned()),
+                language_tag: None,
+                value: filename.as_bytes().to_vec(),
+            }));
+        }
+        ContentDisposition { disposition, parameters }
+    }
+
+    /// An `<img>` tag embedding the SVG QR code of `digest`, as served by
+    /// [`do_show_qr`].
+    fn inline_qr(server: &Server, digest: &str) -> errors::Result<String> {
+        let url =
+            format!("{}/{}/?h={}", server.base_url(), server.hash, digest);
+        let payload = server.qr_transform.apply(&url);
+        let svg = render_qr(&payload, ImageOptions::Svg, &server.qr_style)?;
+        let src = data_uri(mime::IMAGE_SVG.as_ref(), &svg);
+        Ok(format!(r#"<img src="{}" alt="QR code">"#, src))
+    }
+
+    /// Convert a digest pair, with the file metadata if readable, into HTML
+    /// strings.
     async fn htmlize_digest_pair(
         server: &Server,
-        (digest, path): (&String, &PathBuf),
-    ) -> Option<[String; 3]> {
+        (digest, path, metadata): &(String, PathBuf, Option<Metadata>),
+    ) -> Option<[String; 5]> {
         // get the download HTML tag from the digest
         let download = a_href(
             server.file_url(Either::Left(digest.clone())).await?,
@@ -190,35 +823,134 @@ mod inner {
         );
 
         // get the QR HTML tag from the digest
-        let qr = a_href(
-            server.qr_url(Either::Left(digest.clone())).await?,
-            "QR code",
-        );
+        let qr_url = server.qr_url(Either::Left(digest.clone())).await?;
+        let qr = match server.inline_qr.then(|| inline_qr(server, digest)) {
+            Some(Ok(img)) => {
+                format!(r#"<a href="{}">{}</a>"#, xml_escape(&qr_url), img)
+            }
+            Some(Err(e)) => {
+                log::warn!("Cannot embed QR code, linking instead: {}", e);
+                a_href(qr_url, "QR code")
+            }
+            None => a_href(qr_url, "QR code"),
+        };
+        let qr = match server.signed_url(digest, server.signed_ttl) {
+            Some(url) => format!("{} {}", qr, a_href(url, "signed link")),
+            None => qr,
+        };
+
+        let size = metadata
+            .as_ref()
+            .map_or_else(|| "-".to_owned(), |md| format_size(md.len()));
+        let modified = metadata
+            .as_ref()
+            .and_then(|md| md.modified().ok())
+            .map_or_else(|| "-".to_owned(), format_time);
 
         // only first 10 chars are important
         const HASH_SHOW_CHARS: usize = 10;
         let digest = digest[..HASH_SHOW_CHARS].to_string();
 
-        Some([digest, download, qr])
+        Some([digest, download, size, modified, qr])
+    }
+
+    /// Whether the file name of `path` contains `query`, case-insensitively.
+    /// An empty query matches everything.
+    fn name_matches(path: &Path, query: &str) -> bool {
+        query.is_empty()
+            || path.file_name().is_some_and(|name| {
+                name.to_string_lossy()
+                    .to_lowercase()
+                    .contains(&query.to_lowercase())
+            })
+    }
+
+    /// Sort listing rows in ascending order of `key`, and then of file names.
+    /// Rows without metadata sort first.
+    fn sort_rows(
+        rows: &mut [(String, PathBuf, Option<Metadata>)],
+        key: SortKey,
+    ) {
+        rows.sort_by_key(|(_, path, _)| path.file_name().map(OsStr::to_owned));
+        match key {
+            SortKey::Name => (),
+            SortKey::Size => {
+                rows.sort_by_key(|(_, _, md)| md.as_ref().map(Metadata::len))
+            }
+            SortKey::Modified => rows.sort_by_key(|(_, _, md)| {
+                md.as_ref().and_then(|md| md.modified().ok())
+            }),
+        }
     }
 
+    /// The style of the [`ListTheme::Responsive`] file listing, where wide
+    /// tables scroll horizontally on small screens.
+    const RESPONSIVE_STYLE: &str = "body { font-family: sans-serif; \
+        margin: 1em auto; max-width: 60em; padding: 0 1em; } \
+        table { display: block; overflow-x: auto; border-collapse: collapse; } \
+        th, td { padding: 0.4em 0.8em; text-align: left; \
+        border-bottom: 1px solid #ddd; } \
+        td:first-child { font-family: monospace; word-break: break-all; }";
+
     pub(super) async fn do_list_files(
         server: Data<Server>,
+        Query(ListQuery { q, sort, order }): Query<ListQuery>,
     ) -> errors::Result<impl Responder> {
         log::trace!(
             "Listing server, currently {} file(s).",
             server.digest.read().await.len()
         );
+        if server.serve_metrics {
+            server.metrics.count_listing();
+        }
 
-        let table = {
-            let digest = server.digest.read().await;
+        // prefer the user-provided index, when present
+        if let Some(path) = &server.index_file {
+            match NamedFile::open_async(path).await {
+                Ok(file) => return Ok(actix_web::Either::Left(file)),
+                Err(_) => log::debug!(
+                    "Index file {} not found, generating the listing",
+                    path.display()
+                ),
+            }
+        }
+
+        let q = q.unwrap_or_default();
+
+        let mut rows = vec![];
+        let pairs: Vec<_> = server
+            .digest
+            .read()
+            .await
+            .iter()
+            .filter(|(_, p)| name_matches(p, &q))
+            .map(|(d, p)| (d.clone(), p.clone()))
+            .collect();
+        for (digest, path) in pairs {
+            if server.is_expired(&path).await {
+                continue;
+            }
+            // the metadata may be unreadable, e.g. after the file is removed
+            let metadata = tokio::fs::metadata(&path).await.ok();
+            rows.push((digest, path, metadata));
+        }
+        sort_rows(&mut rows, sort.unwrap_or_default());
+        if order == Some(SortOrder::Desc) {
+            rows.reverse();
+        }
 
-            let mut table =
-                Table::new().with_header_row(["digests", "file names", ""]);
+        let table = {
+            let mut table = Table::new().with_header_row([
+                "digests",
+                "file names",
+                "sizes",
+                "modified",
+                "",
+            ]);
 
-            for pair in &*digest {
+            for row in &rows {
                 table.add_body_row(
-                    htmlize_digest_pair(&server, pair)
+                    htmlize_digest_pair(&server, row)
                         .await
                         .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?,
                 )
@@ -227,61 +959,2807 @@ mod inner {
             table
         };
 
-        static TITLE: &str = "QR Share: Files";
-        let page = HtmlPage::new()
-            .with_title(TITLE)
-            .with_header(1, TITLE)
+        let title = xml_escape(&server.list_title);
+        let mut page = HtmlPage::new()
+            .with_title(&title)
+            .with_meta([
+                ("name", "viewport"),
+                ("content", "width=device-width, initial-scale=1"),
+            ])
+            .with_header(1, &title);
+        match server.theme {
+            ListTheme::Responsive => {
+                page.add_style(RESPONSIVE_STYLE);
+                page.add_table(table);
+            }
             // this seems to be mostly how nginx autoindex displays file
             // listings
-            .with_preformatted(table.to_html_string());
+            ListTheme::Plain => page.add_preformatted(table.to_html_string()),
+        }
+        if let Some(css) = &server.list_css {
+            page.add_style(css);
+        }
 
         let response = HttpResponse::build(StatusCode::OK)
             .content_type(ContentType::html())
             .body(page.to_html_string());
 
-        Ok(response)
+        Ok(actix_web::Either::Right(response))
+    }
+
+    pub(super) async fn do_list_json(
+        server: Data<Server>,
+        Query(ListQuery { q, .. }): Query<ListQuery>,
+    ) -> errors::Result<impl Responder> {
+        let q = q.unwrap_or_default();
+        let mut entries = vec![];
+        for entry in server.manifest_entries().await {
+            if !name_matches(&entry.path, &q)
+                || server.is_expired(&entry.path).await
+            {
+                continue;
+            }
+            let digest = Either::Left(entry.digest.clone());
+            entries.push(ListEntry {
+                download_url: server
+                    .file_url(digest.clone())
+                    .await
+                    .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?,
+                signed_url: server.signed_url(&entry.digest, server.signed_ttl),
+                qr_url: server
+                    .qr_url(digest)
+                    .await
+                    .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?,
+                digest: entry.digest,
+                filename: entry.filename,
+                size: entry.size,
+            });
+        }
+        Ok(HttpResponse::Ok().json(entries))
+    }
+
+    pub(super) async fn do_feed(
+        server: Data<Server>,
+    ) -> errors::Result<impl Responder> {
+        if !server.feed {
+            Err(StatusCode::NOT_FOUND)?
+        }
+
+        let mut files: Vec<_> = {
+            let digest = server.digest.read().await;
+            let enqueued = server.enqueued.read().await;
+            digest
+                .iter()
+                .map(|(d, p)| {
+                    let time = enqueued.get(d).copied();
+                    (d.clone(), p.clone(), time.unwrap_or(UNIX_EPOCH))
+                })
+                .collect()
+        };
+        files.sort_by(|(_, _, a), (_, _, b)| b.cmp(a));
+
+        let mut items = String::new();
+        for (digest, path, time) in files {
+            let url = server
+                .file_url(Either::Left(digest.clone()))
+                .await
+                .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+            items += &format!(
+                concat!(
+                    "<item><title>{}</title><link>{}</link>",
+                    r#"<guid isPermaLink="false">{}</guid>"#,
+                    "<pubDate>{}</pubDate></item>",
+                ),
+                xml_escape(&path.file_name().unwrap().to_string_lossy()),
+                xml_escape(&url),
+                digest,
+                HttpDate::from(time),
+            );
+        }
+
+        let feed = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<rss version="2.0"><channel>"#,
+                "<title>QR Share: Files</title><link>{}</link>",
+                "<description>Files served by QR Share</description>",
+                "{}</channel></rss>",
+            ),
+            xml_escape(&server.list_url()),
+            items,
+        );
+
+        Ok(HttpResponse::Ok()
+            .content_type("application/rss+xml; charset=utf-8")
+            .body(feed))
     }
 
     pub(super) async fn do_enqueue_file(
         server: Data<Server>,
         Json(files): Json<Enqueue>,
     ) -> errors::Result<impl Responder> {
-        server.enqueue(files).await;
-        Arc::clone(&server).process_digest().await?;
+        // reject large batches before touching the file system
+        let max = server.max_enqueue_batch;
+        if files.path_count() > max {
+            Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("At most {} paths can be enqueued at once.\n", max),
+            ))?
+        }
+        let limits = files.limits();
+        server.enqueue_with(files, limits).await;
+        // re-enqueued files are only hashed again when they may be replaced
+        let skip_existing = !server.replace_on_reenqueue;
+        Arc::clone(&server).process_digest(skip_existing).await?;
 
         Ok("Files successfully enqueued.\n")
     }
 
-    /// Serve a file at `path` as a response, or 404 status if failed.
-    pub(super) async fn serve_file_at(
-        path: &Path,
-    ) -> errors::Result<impl Responder> {
-        log::info!("Serving file: {}", path.display());
-        if let Ok(file) = NamedFile::open(path) {
-            Ok(file)
-        } else {
-            log::error!("Cannot serve file: {}", path.display());
-            Err(StatusCode::NOT_FOUND.into())
+    /// The path of the uploaded file `name` within `dir`, or [`None`] when
+    /// `name` is not a plain file name, which could escape `dir`.
+    pub(super) fn upload_path(dir: &Path, name: &str) -> Option<PathBuf> {
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            // backslashes are separators to Windows clients
+            (Some(Component::Normal(_)), None) if !name.contains('\\') => {
+                Some(dir.join(name))
+            }
+            _ => None,
         }
     }
 
-    pub(super) async fn do_show_qr(
-        server: Data<Server>,
-        Query(GetQuery { digest }): Query<GetQuery>,
-    ) -> errors::Result<impl Responder> {
-        let scheme = "http";
-        let host = server.bind.primary_host();
-        let port = server.bind.port();
-        let method = "sha512";
+    /// Write the files in `payload` into `dir`, pushing their paths into
+    /// `written` as they are created.  Fields without file names are ignored.
+    async fn receive_uploads(
+        dir: &Path,
+        max_size: u64,
+        payload: &mut Multipart,
+        written: &mut Vec<PathBuf>,
+    ) -> errors::Result<()> {
+        let invalid = |e: actix_multipart::MultipartError| {
+            errors::Error::from((StatusCode::BAD_REQUEST, e.to_string()))
+        };
+        let mut total = 0;
+        while let Some(mut field) = payload.try_next().await.map_err(invalid)? {
+            let name = match field.content_disposition().get_filename() {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let path = match upload_path(dir, &name) {
+                Some(path) => path,
+                None => Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid file name: {}\n", name),
+                ))?,
+            };
 
-        let url =
-            format!("{}://{}:{}/{}/?h={}", scheme, host, port, method, digest);
-        log::info!("Showing QR code for {}", url);
+            let open = tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await;
+            let mut file = match open {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err((
+                    StatusCode::CONFLICT,
+                    format!("File already exists: {}\n", name),
+                ))?,
+                Err(e) => Err(e)?,
+            };
+            written.push(path);
 
-        let qr = QrCode::new(url)?;
+            while let Some(chunk) = field.try_next().await.map_err(invalid)? {
+                total += chunk.len() as u64;
+                if total > max_size {
+                    Err(StatusCode::PAYLOAD_TOO_LARGE)?
+                }
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+        }
+        Ok(())
+    }
 
-        Ok(HttpResponse::Ok()
-            .content_type(ContentType(mime::IMAGE_SVG))
-            .message_body(qr.render::<qrcode::render::svg::Color>().build()))
+    pub(super) async fn do_upload_file(
+        server: Data<Server>,
+        mut payload: Multipart,
+    ) -> errors::Result<impl Responder> {
+        let dir = match &server.upload_dir {
+            Some(dir) => dir,
+            None => Err((StatusCode::FORBIDDEN, "Uploads are disabled.\n"))?,
+        };
+        // files outside the served root would be received but never served
+        if !server.within_root(dir) {
+            Err((
+                StatusCode::FORBIDDEN,
+                "The upload directory is outside the served root.\n",
+            ))?
+        }
+
+        // partial uploads are removed, so that they are not served later
+        let mut written = vec![];
+        let received = receive_uploads(
+            dir,
+            server.max_upload_size,
+            &mut payload,
+            &mut written,
+        )
+        .await;
+        if let Err(e) = received {
+            for path in &written {
+                let _ = tokio::fs::remove_file(path).await;
+            }
+            Err(e)?
+        }
+        if written.is_empty() {
+            Err((StatusCode::BAD_REQUEST, "No files uploaded.\n"))?
+        }
+        log::info!("Received {} uploaded file(s)", written.len());
+
+        server.enqueue(written.iter().cloned()).await;
+        let skip_existing = !server.replace_on_reenqueue;
+        Arc::clone(&server).process_digest(skip_existing).await?;
+
+        let mut entries = vec![];
+        for path in written {
+            // duplicate uploads may be rejected
+            let digest = match server.query_digest(path.clone()).await {
+                Some(digest) => digest,
+                None => continue,
+            };
+            let url_digest = Either::Left(digest.clone());
+            entries.push(ListEntry {
+                download_url: server
+                    .file_url(url_digest.clone())
+                    .await
+                    .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?,
+                signed_url: server.signed_url(&digest, server.signed_ttl),
+                qr_url: server
+                    .qr_url(url_digest)
+                    .await
+                    .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?,
+                digest,
+                filename: path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                size: tokio::fs::metadata(&path).await?.len(),
+            });
+        }
+        Ok(HttpResponse::Ok().json(entries))
+    }
+
+    pub(super) async fn do_remove_file(
+        server: Data<Server>,
+        Query(GetQuery { digest, .. }): Query<GetQuery>,
+    ) -> errors::Result<impl Responder> {
+        if server.remove_digest(&digest).await {
+            Ok("File successfully removed.\n")
+        } else {
+            Err(StatusCode::NOT_FOUND.into())
+        }
+    }
+
+    pub(super) async fn do_health(
+        server: Data<Server>,
+        readiness: bool,
+    ) -> errors::Result<impl Responder> {
+        let health = Health {
+            uptime_secs: server.started.elapsed().as_secs(),
+            files: server.file_count().await,
+            ready: server.is_ready(),
+        };
+        let status = match readiness && !health.ready {
+            true => StatusCode::SERVICE_UNAVAILABLE,
+            false => StatusCode::OK,
+        };
+        Ok(HttpResponse::build(status).json(health))
+    }
+
+    pub(super) async fn do_show_metrics(
+        server: Data<Server>,
+    ) -> errors::Result<impl Responder> {
+        if !server.serve_metrics {
+            Err(StatusCode::NOT_FOUND)?
+        }
+        let files = server.file_count().await;
+        Ok(HttpResponse::Ok()
+            .content_type("text/plain; version=0.0.4; charset=utf-8")
+            .body(server.metrics.render(files)))
+    }
+
+    pub(super) async fn do_show_config(
+        server: Data<Server>,
+    ) -> errors::Result<impl Responder> {
+        Ok(HttpResponse::Ok().json(&server.config))
+    }
+
+    /// Serve a file at `path` as a response, or 404 status if failed.
+    pub(super) async fn serve_file_at(
+        path: &Path,
+    ) -> errors::Result<impl Responder> {
+        log::info!("Serving file: {}", path.display());
+        if let Ok(file) = NamedFile::open(path) {
+            Ok(file)
+        } else {
+            log::error!("Cannot serve file: {}", path.display());
+            Err(StatusCode::NOT_FOUND.into())
+        }
+    }
+
+    /// The bundled favicon, served unless a favicon file is configured.
+    pub(super) const FAVICON: &[u8] = include_bytes!("../assets/favicon.png");
+
+    pub(super) async fn do_favicon(
+        req: HttpRequest,
+        server: Data<Server>,
+    ) -> errors::Result<impl Responder> {
+        if server.favicon_qr {
+            server
+                .limit_qr(req.peer_addr().map(|addr| addr.ip()))
+                .await?;
+            let url = server.list_url();
+            log::info!("Showing favicon QR code for {}", url);
+
+            // favicons are displayed at 16 to 64 pixels wide
+            const FAVICON_SIZE: u32 = 64;
+            let payload = server.qr_transform.apply(&url);
+            let png = render_png(&QrCode::new(payload)?, Some(FAVICON_SIZE))?;
+
+            Ok(actix_web::Either::Left(
+                HttpResponse::Ok()
+                    .content_type(ContentType::png())
+                    .body(png),
+            ))
+        } else if let Some(path) = &server.favicon {
+            let file = serve_file_at(path).await?;
+            Ok(actix_web::Either::Right(file))
+        } else {
+            Ok(actix_web::Either::Left(
+                HttpResponse::Ok()
+                    .content_type(ContentType::png())
+                    .body(FAVICON),
+            ))
+        }
+    }
+
+    pub(super) async fn do_thumbnail(
+        req: HttpRequest,
+        server: Data<Server>,
+        digest: web::Path<String>,
+        Query(ThumbQuery { width }): Query<ThumbQuery>,
+    ) -> errors::Result<impl Responder> {
+        const DEFAULT_WIDTH: u32 = 200;
+        // bound the cached thumbnails of each file
+        let width = width.unwrap_or(DEFAULT_WIDTH);
+        let width = THUMB_WIDTHS
+            .into_iter()
+            .find(|&w| w >= width)
+            .unwrap_or(THUMB_WIDTHS[THUMB_WIDTHS.len() - 1]);
+        // thumbnails are previews, so they are not counted as downloads
+        let (digest, path, _) = servable_file(&server, &digest).await?;
+        let key = (digest, width);
+
+        let cached = server.thumbnails.read().await.get(&key).cloned();
+        let bytes = match cached {
+            Some(bytes) => bytes,
+            None => {
+                // decoding is as costly as rendering QR codes
+                server
+                    .limit_qr(req.peer_addr().map(|addr| addr.ip()))
+                    .await?;
+
+                // decoding and resizing are CPU-bound
+                let bytes = tokio::task::spawn_blocking(move || {
+                    thumbnail(&path, width)
+                })
+                .await??
+                .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+
+                log::info!("Generated thumbnail for {} at {}px", key.0, width);
+                server.thumbnails.write().await.insert(key, bytes.clone());
+                bytes
+            }
+        };
+
+        Ok(HttpResponse::Ok()
+            .content_type(ContentType::jpeg())
+            .body(bytes))
+    }
+
+    /// Find the served archive with `digest` and its format, as in
+    /// [`servable_file`].
+    async fn find_archive(
+        server: &Server,
+        digest: &str,
+    ) -> errors::Result<(String, PathBuf, Option<u64>, archive::Format)> {
+        let (digest, path, limit) = servable_file(server, digest).await?;
+        let format = archive::Format::from_path(&path)
+            .ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+        Ok((digest, path, limit, format))
+    }
+
+    pub(super) async fn do_browse_archive(
+        server: Data<Server>,
+        digest: web::Path<String>,
+    ) -> errors::Result<impl Responder> {
+        let (_, path, _, format) = find_archive(&server, &digest).await?;
+        let title = format!(
+            "QR Share: {}",
+            xml_escape(&path.file_name().unwrap().to_string_lossy())
+        );
+
+        // reading the archive headers is blocking
+        let entries =
+            tokio::task::spawn_blocking(move || archive::list(&path, format))
+                .await??;
+
+        let mut table = Table::new().with_header_row(["sizes", "entries"]);
+        for entry in entries {
+            table.add_body_row([
+                entry.size.to_string(),
+                // the leading "./" keeps a colon from being read as a scheme
+                a_href(format!("./{}", path_encode(&entry.name)), &entry.name),
+            ]);
+        }
+
+        let page = HtmlPage::new()
+            .with_title(&title)
+            .with_header(1, &title)
+            .with_preformatted(table.to_html_string());
+
+        Ok(HttpResponse::build(StatusCode::OK)
+            .content_type(ContentType::html())
+            .body(page.to_html_string()))
+    }
+
+    pub(super) async fn do_browse_entry(
+        req: HttpRequest,
+        server: Data<Server>,
+        path: web::Path<(String, String)>,
+    ) -> errors::Result<impl Responder> {
+        let (digest, name) = path.into_inner();
+        let (digest, path, limit, format) =
+            find_archive(&server, &digest).await?;
+        let guard =
+            server.start_download(req.peer_addr().map(|addr| addr.ip()))?;
+        let content_type = file_extension_to_mime(
+            name.rsplit_once('.').map_or("", |(_, ext)| ext),
+        );
+
+        // archive readers are blocking, so read the entry on a blocking
+        // thread and stream its chunks through a channel, which closes when
+        // the client disconnects
+        let (found_tx, found_rx) = oneshot::channel();
+        let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(4);
+        let buf_size = server.buf_size;
+        let archive = path.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            archive::with_entry(&archive, format, &name, |reader| {
+                let _ = found_tx.send(());
+                let mut buf = vec![0; buf_size];
+                loop {
+                    let chunk = match reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => Ok(Bytes::copy_from_slice(&buf[..n])),
+                        Err(e) => Err(e),
+                    };
+                    let failed = chunk.is_err();
+                    if tx.blocking_send(chunk).is_err() || failed {
+                        break;
+                    }
+                }
+            })
+        });
+
+        if found_rx.await.is_err() {
+            // the entry is missing, or the archive cannot be read
+            task.await??.ok_or(StatusCode::NOT_FOUND)?;
+        }
+        count_download(&server, &digest, &path, limit).await?;
+
+        let resp = HttpResponse::Ok()
+            .content_type(content_type)
+            .streaming(body_stream(rx));
+        Ok(limit_body(&server, resp, guard))
+    }
+
+    /// Pair each of `paths` with its base name, adding a number before the
+    /// extension when names collide, as in `a (1).txt`.
+    pub(super) fn unique_names(
+        paths: impl IntoIterator<Item = PathBuf>,
+    ) -> Vec<(String, PathBuf)> {
+        let mut seen = HashSet::new();
+        paths
+            .into_iter()
+            .map(|path| {
+                let name = path.file_name().map_or_else(
+                    || "file".to_owned(),
+                    |name| name.to_string_lossy().into_owned(),
+                );
+                let (stem, ext) = match name.rsplit_once('.') {
+                    Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+                    _ => (name.as_str(), ""),
+                };
+                let mut unique = name.clone();
+                for i in 1.. {
+                    if seen.insert(unique.clone()) {
+                        break;
+                    }
+                    unique = match ext {
+                        "" => format!("{} ({})", stem, i),
+                        ext => format!("{} ({}).{}", stem, i, ext),
+                    };
+                }
+                (unique, path)
+            })
+            .collect()
+    }
+
+    /// Write the zip archive of `files` to `writer`, skipping those that
+    /// cannot be opened.
+    fn write_zip(
+        files: &[(String, PathBuf)],
+        writer: impl Write,
+    ) -> io::Result<()> {
+        let mut zip = archive::ZipStream::new(writer);
+        for (name, path) in files {
+            let mut file = match File::open(path) {
+                Ok(file) => file,
+                Err(e) => {
+                    log::warn!("Skipping {} in zip: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let metadata = match file.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    log::warn!("Skipping {} in zip: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+            zip.append(name, modified, metadata.len(), &mut file)?;
+        }
+        zip.finish().map(drop)
+    }
+
+    pub(super) async fn do_download_zip(
+        req: HttpRequest,
+        server: Data<Server>,
+    ) -> errors::Result<impl Responder> {
+        // skip the files that have expired or reached their download limit
+        let digests: Vec<_> =
+            server.digest.read().await.keys().cloned().collect();
+        let mut servable = vec![];
+        for digest in digests {
+            if let Ok(file) = servable_file(&server, &digest).await {
+                servable.push(file);
+            }
+        }
+        let guard =
+            server.start_download(req.peer_addr().map(|addr| addr.ip()))?;
+        let mut paths = vec![];
+        for (digest, path, limit) in servable {
+            if count_download(&server, &digest, &path, limit).await.is_ok() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let files = unique_names(paths);
+
+        // the archive is written on a blocking thread and streamed through a
+        // channel as it is written, like archive entries
+        let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(4);
+        let buf_size = server.buf_size;
+        tokio::task::spawn_blocking(move || {
+            let writer =
+                BufWriter::with_capacity(buf_size, ChannelWriter(tx.clone()));
+            if let Err(e) = write_zip(&files, writer) {
+                log::warn!("Failed to stream the zip archive: {}", e);
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+
+        let resp = HttpResponse::Ok()
+            .content_type("application/zip")
+            // the entries are stored, not deflated, but the served files are
+            // mostly compressed formats, so skip the compression middleware
+            .insert_header((header::CONTENT_ENCODING, "identity"))
+            .insert_header(content_disposition(
+                DispositionType::Attachment,
+                "qrshare.zip",
+            ))
+            .streaming(body_stream(rx));
+        Ok(limit_body(&server, resp, guard))
+    }
+
+    /// Write the tar archive of `files` to `writer`.  The archive is only
+    /// terminated when all files are written, so that a failed archive is
+    /// not mistaken for a complete one.
+    async fn write_tar(
+        files: &[(String, PathBuf)],
+        writer: impl AsyncWrite + Unpin + Send,
+    ) -> io::Result<()> {
+        let mut tar = tokio_tar::Builder::new_non_terminated(writer);
+        for (name, path) in files {
+            let mut file = tokio::fs::File::open(path).await?;
+            tar.append_file(name, &mut file).await?;
+        }
+        tar.into_inner().await?.shutdown().await
+    }
+
+    pub(super) async fn do_download_tar(
+        req: HttpRequest,
+        server: Data<Server>,
+        Query(TarQuery { digests }): Query<TarQuery>,
+    ) -> errors::Result<impl Responder> {
+        if digests.is_empty() {
+            Err((StatusCode::BAD_REQUEST, "No digests selected.\n"))?
+        }
+
+        let mut selected = vec![];
+        let mut missing = vec![];
+        for digest in digests {
+            match servable_file(&server, &digest).await {
+                Ok(file) if !selected.contains(&file) => selected.push(file),
+                Ok(_) => {}
+                Err(errors::Error::HttpResponse(StatusCode::NOT_FOUND, _)) => {
+                    missing.push(digest)
+                }
+                Err(e) => Err(e)?,
+            }
+        }
+        if !missing.is_empty() {
+            Err((
+                StatusCode::NOT_FOUND,
+                format!("Unknown digests:\n{}\n", missing.join("\n")),
+            ))?
+        }
+        let guard =
+            server.start_download(req.peer_addr().map(|addr| addr.ip()))?;
+        let mut paths = vec![];
+        for (digest, path, limit) in selected {
+            count_download(&server, &digest, &path, limit).await?;
+            paths.push(path);
+        }
+        let files = unique_names(paths);
+
+        // the archive is written by a task and streamed through a pipe as it
+        // is written, and a failure ends the body with the error
+        let (writer, reader) = tokio::io::duplex(server.buf_size);
+        let (failed_tx, failed_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            if let Err(e) = write_tar(&files, writer).await {
+                log::warn!("Failed to stream the tar archive: {}", e);
+                let _ = failed_tx.send(e);
+            }
+        });
+        let failed = futures::stream::once(failed_rx)
+            .filter_map(|failed| ready(failed.ok().map(Err)));
+
+        let resp = HttpResponse::Ok()
+            .content_type("application/x-tar")
+            .insert_header(content_disposition(
+                DispositionType::Attachment,
+                "qrshare.tar",
+            ))
+            .streaming(ReaderStream::new(reader).chain(failed));
+        Ok(limit_body(&server, resp, guard))
+    }
+
+    pub(super) async fn do_show_qr(
+        req: HttpRequest,
+        server: Data<Server>,
+        Query(QrQuery { digest, fmt }): Query<QrQuery>,
+    ) -> errors::Result<impl Responder> {
+        server
+            .limit_qr(req.peer_addr().map(|addr| addr.ip()))
+            .await?;
+
+        let ft = match fmt {
+            Some(fmt) => match ImageOptions::from_str(&fmt, true) {
+                Ok(ft @ (ImageOptions::Png | ImageOptions::Svg)) => ft,
+                _ => {
+                    Err((StatusCode::BAD_REQUEST, "Unknown QR code format.\n"))?
+                }
+            },
+            None if server.qr_format_from_accept => {
+                accepted_format(&req, server.qr)
+            }
+            None => server.qr,
+        };
+
+        let url =
+            format!("{}/{}/?h={}", server.base_url(), server.hash, digest);
+        log::info!("Showing QR code for {}", url);
+
+        let payload = server.qr_transform.apply(&url);
+        let (ft, bytes) = render_qr_with_fallback(
+            &payload,
+            ft,
+            &server.qr_style,
+            server.qr_fallback,
+        )?;
+
+        if server.serve_metrics {
+            server.metrics.count_qr_code();
+        }
+        let mut resp = HttpResponse::Ok();
+        resp.content_type(match ft {
+            ImageOptions::Png => ContentType::png(),
+            _ => ContentType(mime::IMAGE_SVG),
+        });
+        if server.qr_format_from_accept {
+            resp.insert_header((header::VARY, "Accept"));
+        }
+        Ok(resp.body(bytes))
+    }
+
+    /// The QR code format preferred by the `Accept` header of `req`, or
+    /// `default` when neither PNG nor SVG is explicitly accepted.  Wildcards
+    /// such as `image/*` are not explicit.
+    fn accepted_format(
+        req: &HttpRequest,
+        default: ImageOptions,
+    ) -> ImageOptions {
+        let ranked = req
+            .get_header::<Accept>()
+            .map(|accept| accept.ranked())
+            .unwrap_or_default();
+        let accepted =
+            ranked.iter().find_map(|mime| match mime.essence_str() {
+                "image/png" => Some(ImageOptions::Png),
+                "image/svg+xml" => Some(ImageOptions::Svg),
+                _ => None,
+            });
+        match accepted.unwrap_or(default) {
+            ImageOptions::None => ImageOptions::Svg,
+            ft => ft,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+    };
+
+    use actix_web::{
+        body::{BodySize, MessageBody},
+        http::{header, Method, StatusCode},
+        middleware::{Compress, Condition},
+        test,
+        web::{self, Bytes, Data, Json},
+        App,
+    };
+    use clap::Parser;
+    use lib::qr::gen::PayloadTransform;
+    use sha2::{Digest, Sha256, Sha512};
+
+    use super::Enqueue;
+    use crate::{
+        cli::Cli,
+        middleware::{Cors, ErrorPages, RateLimit, ResponseMetrics},
+        Server,
+    };
+
+    /// Create a server with `files` enqueued, without hashing them.
+    async fn server_with(files: &[&Path]) -> Data<Server> {
+        let files = files.iter().map(|p| p.as_os_str());
+        let cli =
+            Cli::parse_from(["qrshare".as_ref()].into_iter().chain(files));
+        Data::new(Server::new(cli).await.unwrap())
+    }
+
+    /// Downloads carry an ETag in the configured algorithm, and a matching
+    /// `If-None-Match` is answered with 304.
+    #[actix_web::test]
+    async fn test_etag() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"etag").unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--hash-algorithm-for-etag=sha256".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+
+        let uri = format!("/sha512/?h={}", hex::encode(Sha512::digest("etag")));
+        let etag = format!(r#""{}""#, hex::encode(Sha256::digest("etag")));
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), &etag);
+
+        let req = test::TestRequest::get()
+            .uri(&uri)
+            .insert_header((header::IF_NONE_MATCH, etag))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 304);
+    }
+
+    /// Without `--hash-algorithm-for-etag`, the ETag is the digest, and a
+    /// matching `If-None-Match` is answered with 304 and no body.
+    #[actix_web::test]
+    async fn test_etag_digest() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"etag").unwrap();
+        let server = server_with(&[file.path()]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+
+        let digest = hex::encode(Sha512::digest("etag"));
+        let uri = format!("/sha512/?h={}", digest);
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(etag, format!(r#""{}""#, digest).as_str());
+
+        for value in [etag, header::HeaderValue::from_static("*")] {
+            let req = test::TestRequest::get()
+                .uri(&uri)
+                .insert_header((header::IF_NONE_MATCH, value))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), 304);
+            assert!(test::read_body(resp).await.is_empty());
+        }
+    }
+
+    /// A `HEAD` request has the headers of a download, without counting as
+    /// one.
+    #[actix_web::test]
+    async fn test_head() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"head").unwrap();
+        let server = server_with(&[file.path()]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(server.clone())
+                .service(super::get_sha512),
+        )
+        .await;
+
+        let digest = hex::encode(Sha512::digest("head"));
+        let uri = format!("/sha512/?h={}", digest);
+        let req = test::TestRequest::default()
+            .method(Method::HEAD)
+            .uri(&uri)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        // the body is dropped by the connection, after its size is reported
+        assert_eq!(resp.response().body().size(), BodySize::Sized(4));
+        let headers = resp.headers();
+        assert_eq!(
+            headers.get(header::ETAG).unwrap(),
+            format!(r#""{}""#, digest).as_str()
+        );
+        assert!(headers.contains_key(header::CONTENT_TYPE));
+        assert!(headers.contains_key(header::CONTENT_DISPOSITION));
+        assert!(server.download_counts.read().await.is_empty());
+    }
+
+    /// With `--verify-on-download`, a file modified after hashing is
+    /// rejected with 409.
+    #[actix_web::test]
+    async fn test_verify_on_download() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"original").unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--verify-on-download=true".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+
+        let uri =
+            format!("/sha512/?h={}", hex::encode(Sha512::digest("original")));
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        // same size, different content
+        std::fs::write(file.path(), b"modified").unwrap();
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 409);
+    }
+
+    /// A file modified after hashing is rejected with 409, and served under
+    /// its new digest instead.
+    #[actix_web::test]
+    async fn test_rehash_modified() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"before").unwrap();
+        let server = server_with(&[file.path()]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+
+        let uri = |content: &str| {
+            format!("/sha512/?h={}", hex::encode(Sha512::digest(content)))
+        };
+        let req = test::TestRequest::get().uri(&uri("before")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        std::fs::write(file.path(), b"after, longer").unwrap();
+        let req = test::TestRequest::get().uri(&uri("before")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 409);
+        let body = test::read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.trim_end().ends_with(&uri("after, longer")), "{}", body);
+
+        let req = test::TestRequest::get().uri(&uri("before")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 404);
+        let req = test::TestRequest::get()
+            .uri(&uri("after, longer"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(test::read_body(resp).await, "after, longer");
+    }
+
+    /// A client cannot start more downloads than allowed, until an earlier
+    /// response body is dropped.
+    #[actix_web::test]
+    async fn test_max_per_ip() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"max-per-ip").unwrap();
+        let digest = hex::encode(Sha512::digest("max-per-ip"));
+
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--max-per-ip=1".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+
+        let get = |ip: [u8; 4]| {
+            test::TestRequest::get()
+                .uri(&format!("/sha512/?h={}", digest))
+                .peer_addr((ip, 12345).into())
+                .to_request()
+        };
+        let first = test::call_service(&app, get([10, 0, 0, 1])).await;
+        assert_eq!(first.status().as_u16(), 200);
+        let resp = test::call_service(&app, get([10, 0, 0, 1])).await;
+        assert_eq!(resp.status().as_u16(), 429);
+        let resp = test::call_service(&app, get([10, 0, 0, 2])).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        drop(first);
+        let resp = test::call_service(&app, get([10, 0, 0, 1])).await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    /// With `--max-bps`, a download takes at least its size over the cap.
+    #[actix_web::test]
+    async fn test_max_bps() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[b'x'; 2000]).unwrap();
+        let digest = hex::encode(Sha512::digest([b'x'; 2000]));
+
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--max-bps=4000".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+
+        let start = std::time::Instant::now();
+        let req = test::TestRequest::get()
+            .uri(&format!("/sha512/?h={}", digest))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(resp.response().body().size(), BodySize::Sized(2000));
+        assert_eq!(test::read_body(resp).await.len(), 2000);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(500));
+    }
+
+    /// With `--rate-limit`, requests beyond the burst are rejected per client
+    /// with 429 status and `Retry-After`, except from exempt loopback peers.
+    #[actix_web::test]
+    async fn test_rate_limit() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--rate-limit=1".as_ref(),
+            "--rate-burst=2".as_ref(),
+            "--rate-limit-exempt-loopback=true".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        let app = test::init_service(
+            App::new()
+                .wrap(RateLimit)
+                .app_data(server)
+                .service(super::list_json),
+        )
+        .await;
+
+        let get = |ip: [u8; 4]| {
+            test::TestRequest::get()
+                .uri("/list.json")
+                .peer_addr((ip, 12345).into())
+                .to_request()
+        };
+        for _ in 0..2 {
+            let resp = test::call_service(&app, get([10, 0, 0, 1])).await;
+            assert_eq!(resp.status().as_u16(), 200);
+        }
+        let resp = test::call_service(&app, get([10, 0, 0, 1])).await;
+        assert_eq!(resp.status().as_u16(), 429);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "1");
+        let resp = test::call_service(&app, get([10, 0, 0, 2])).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        for _ in 0..3 {
+            let resp = test::call_service(&app, get([127, 0, 0, 1])).await;
+            assert_eq!(resp.status().as_u16(), 200);
+        }
+    }
+
+    /// A unique digest prefix resolves to its file, and an ambiguous one
+    /// lists the candidates.
+    #[actix_web::test]
+    async fn test_digest_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<_> = (0..32)
+            .map(|i| {
+                let path = dir.path().join(i.to_string());
+                std::fs::write(&path, i.to_string()).unwrap();
+                path
+            })
+            .collect();
+        let server =
+            server_with(&paths.iter().map(|p| p.as_path()).collect::<Vec<_>>())
+                .await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+
+        let get = |h: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/sha512/?h={}", h))
+                .to_request()
+        };
+        let digest = hex::encode(Sha512::digest("0"));
+        let resp = test::call_service(&app, get(&digest[..16])).await;
+        assert_eq!(test::read_body(resp).await, "0");
+
+        // four of these digests share the first hex digit with that of "0"
+        let resp = test::call_service(&app, get(&digest[..1])).await;
+        assert_eq!(resp.status().as_u16(), 409);
+        let body = test::read_body(resp).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(&digest), "{}", body);
+
+        let resp = test::call_service(&app, get("xyz")).await;
+        assert_eq!(resp.status().as_u16(), 404);
+    }
+
+    /// Digests are encoded as configured in download URLs and ETags, and
+    /// looked up in the same encoding.
+    #[actix_web::test]
+    async fn test_digest_encoding() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"encoded").unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--digest-encoding=base64url".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let sha512 = Sha512::digest("encoded");
+        let digest = base64::encode_config(sha512, base64::URL_SAFE_NO_PAD);
+        assert_eq!(digest.len(), 86);
+        assert!(server.digest.read().await.contains_key(&digest));
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+
+        let uri = format!("/sha512/?h={}", digest);
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = format!(r#""{}""#, digest);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), etag.as_str());
+        assert_eq!(test::read_body(resp).await, "encoded");
+    }
+
+    /// Re-enqueuing a file through the API does not hash it again.
+    #[actix_web::test]
+    async fn test_enqueue_skip_existing() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"old").unwrap();
+        let server = server_with(&[file.path()]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+
+        // the changed content would have a new digest if hashed again
+        file.write_all(b"new").unwrap();
+        let path = file.path().to_owned();
+        let enqueue =
+            Json(Enqueue::Single { path, ttl: None, max_downloads: None });
+        super::inner::do_enqueue_file(server.clone(), enqueue)
+            .await
+            .unwrap();
+        assert_eq!(server.file_count().await, 1);
+        let digest = hex::encode(Sha512::digest("old"));
+        assert!(server.digest.read().await.contains_key(&digest));
+    }
+
+    /// Batches beyond the limit are rejected without enqueuing anything.
+    #[actix_web::test]
+    async fn test_max_enqueue_batch() {
+        use actix_web::ResponseError;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path: Vec<_> =
+            (0..3).map(|i| dir.path().join(i.to_string())).collect();
+        for p in &path {
+            std::fs::write(p, p.to_str().unwrap()).unwrap();
+        }
+        let seed = dir.path().join("seed");
+        std::fs::write(&seed, b"seed").unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--max-enqueue-batch=2".as_ref(),
+            seed.as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+
+        let enqueue = Json(Enqueue::Multiple {
+            path: path.clone(),
+            ttl: None,
+            max_downloads: None,
+        });
+        let e = super::inner::do_enqueue_file(server.clone(), enqueue)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(e.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(server.file_count().await, 1);
+
+        let enqueue = Json(Enqueue::Multiple {
+            path: path[..2].to_vec(),
+            ttl: None,
+            max_downloads: None,
+        });
+        super::inner::do_enqueue_file(server.clone(), enqueue)
+            .await
+            .unwrap();
+        assert_eq!(server.file_count().await, 3);
+    }
+
+    /// Re-enqueuing a changed file replaces its old digest.
+    #[actix_web::test]
+    async fn test_replace_on_reenqueue() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"old").unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--replace-on-reenqueue=true".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+
+        file.write_all(b"new").unwrap();
+        server.enqueue([file.path().to_owned()]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        assert_eq!(server.file_count().await, 1);
+
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+        for (content, status) in [("old", 404), ("oldnew", 200)] {
+            let digest = hex::encode(Sha512::digest(content));
+            let req = test::TestRequest::get()
+                .uri(&format!("/sha512/?h={}", digest))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), status);
+        }
+    }
+
+    /// The feed lists each file once, and is only served when enabled.
+    #[actix_web::test]
+    async fn test_feed() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"feed").unwrap();
+        let name = file.path().file_name().unwrap().to_str().unwrap();
+
+        for enabled in [false, true] {
+            let cli = Cli::parse_from([
+                "qrshare".as_ref(),
+                format!("--feed={}", enabled).as_ref(),
+                file.path().as_os_str(),
+            ]);
+            let server = Data::new(Server::new(cli).await.unwrap());
+            server
+                .clone()
+                .into_inner()
+                .process_digest(false)
+                .await
+                .unwrap();
+            let app = test::init_service(
+                App::new().app_data(server).service(super::show_feed),
+            )
+            .await;
+
+            let req = test::TestRequest::get().uri("/feed.xml").to_request();
+            let resp = test::call_service(&app, req).await;
+            if !enabled {
+                assert_eq!(resp.status().as_u16(), 404);
+                continue;
+            }
+            let body = test::read_body(resp).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert_eq!(body.matches("<item>").count(), 1);
+            assert!(body.contains(&format!("<title>{}</title>", name)));
+            assert!(body.contains(&hex::encode(Sha512::digest("feed"))));
+        }
+    }
+
+    /// Archive entries are listed and streamed, and other files are rejected.
+    #[actix_web::test]
+    async fn test_browse_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.tar");
+        let mut tar = tar::Builder::new(std::fs::File::create(&path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_cksum();
+        tar.append_data(&mut header, "dir/a b.txt", &b"hello"[..])
+            .unwrap();
+        tar.finish().unwrap();
+        let other = dir.path().join("a.txt");
+        std::fs::write(&other, b"not an archive").unwrap();
+
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            path.as_os_str(),
+            other.as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(server)
+                .service(super::browse_archive)
+                .service(super::browse_entry),
+        )
+        .await;
+
+        let digest = hex::encode(Sha512::digest(std::fs::read(&path).unwrap()));
+        let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+
+        let resp =
+            test::call_service(&app, get(format!("/browse/{}/", digest)));
+        let body = test::read_body(resp.await).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#"<a href="./dir/a%20b.txt">dir/a b.txt</a>"#));
+
+        let uri = format!("/browse/{}/dir/a%20b.txt", digest);
+        let resp = test::call_service(&app, get(uri)).await;
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/plain");
+        assert_eq!(test::read_body(resp).await, "hello");
+
+        let uri = format!("/browse/{}/dir/b.txt", digest);
+        let resp = test::call_service(&app, get(uri)).await;
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let digest = hex::encode(Sha512::digest("not an archive"));
+        let resp =
+            test::call_service(&app, get(format!("/browse/{}/", digest)));
+        assert_eq!(resp.await.status().as_u16(), 415);
+    }
+
+    /// Colliding base names are numbered before their extensions.
+    #[actix_web::test]
+    async fn test_unique_names() {
+        let names: Vec<_> = super::inner::unique_names(
+            ["a/x.txt", "b/x.txt", "c/x.txt", "x", "y/x"].map(PathBuf::from),
+        )
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+        assert_eq!(names, ["x.txt", "x (1).txt", "x (2).txt", "x", "x (1)"]);
+    }
+
+    /// All files are downloaded in one zip archive, named uniquely.
+    #[actix_web::test]
+    async fn test_download_zip() {
+        let dirs = [(); 2].map(|()| tempfile::tempdir().unwrap());
+        let paths: Vec<_> =
+            dirs.iter().map(|dir| dir.path().join("a.txt")).collect();
+        std::fs::write(&paths[0], b"first").unwrap();
+        std::fs::write(&paths[1], b"second").unwrap();
+        let server = server_with(&[&paths[0], &paths[1]]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::download_zip),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/download/all.zip")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            r#"attachment; filename="qrshare.zip""#
+        );
+        let body = test::read_body(resp).await;
+
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+        let mut contents = vec![];
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i).unwrap();
+            let mut content = String::new();
+            file.read_to_string(&mut content).unwrap();
+            contents.push((file.name().to_owned(), content));
+        }
+        contents.sort();
+
+        // the path that sorts first keeps its name
+        let (first, second) = if paths[0] < paths[1] {
+            ("first", "second")
+        } else {
+            ("second", "first")
+        };
+        let contents: Vec<_> = contents
+            .iter()
+            .map(|(name, content)| (name.as_str(), content.as_str()))
+            .collect();
+        assert_eq!(contents, [("a (1).txt", second), ("a.txt", first)]);
+    }
+
+    /// Selected files are downloaded in one tar archive, and unknown digests
+    /// are reported.
+    #[actix_web::test]
+    async fn test_download_tar() {
+        let dir = tempfile::tempdir().unwrap();
+        let names = ["a", "b", "c"];
+        let paths: Vec<_> =
+            names.iter().map(|name| dir.path().join(name)).collect();
+        for (path, name) in paths.iter().zip(names) {
+            std::fs::write(path, name).unwrap();
+        }
+        let server = server_with(&[&paths[0], &paths[1], &paths[2]]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::download_tar),
+        )
+        .await;
+        let [a, c] = ["a", "c"].map(|s| hex::encode(Sha512::digest(s)));
+
+        let uri = format!("/download/tar?h={}&h={}&h={}", a, c, a);
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            r#"attachment; filename="qrshare.tar""#
+        );
+        let body = test::read_body(resp).await;
+        let mut contents = vec![];
+        for entry in tar::Archive::new(&body[..]).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            contents.push((name, content));
+        }
+        let contents: Vec<_> = contents
+            .iter()
+            .map(|(name, content)| (name.as_str(), content.as_str()))
+            .collect();
+        assert_eq!(contents, [("a", "a"), ("c", "c")]);
+
+        let unknown = hex::encode(Sha512::digest("unknown"));
+        for (uri, status) in [
+            (format!("/download/tar?h={}&h={}", a, unknown), 404),
+            ("/download/tar".to_owned(), 400),
+        ] {
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), status, "{}", uri);
+            let body = test::read_body(resp).await;
+            if status == 404 {
+                assert!(body.ends_with(format!("{}\n", unknown).as_bytes()));
+            }
+        }
+    }
+
+    /// Uploaded file names cannot escape the upload directory.
+    #[actix_web::test]
+    async fn test_upload_path() {
+        let dir = Path::new("/srv/upload");
+        let path = super::inner::upload_path(dir, "a b.txt");
+        assert_eq!(path, Some(dir.join("a b.txt")));
+        for name in ["", ".", "..", "../a", "a/b", "/etc/passwd", "..\\a"] {
+            assert_eq!(super::inner::upload_path(dir, name), None, "{}", name);
+        }
+    }
+
+    /// Uploaded files are served, and oversized or escaping uploads, or
+    /// uploads that would not be served, are rejected without leaving files
+    /// behind.
+    #[actix_web::test]
+    async fn test_upload_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"served").unwrap();
+        let upload_dir = tempfile::tempdir().unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--upload-dir".as_ref(),
+            upload_dir.path().as_os_str(),
+            "--max-upload-size=8".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(server.clone())
+                .route("/upload", web::post().to(super::inner::do_upload_file)),
+        )
+        .await;
+
+        let upload = |files: &[(&str, &str)]| {
+            let mut body = String::new();
+            for (name, content) in files {
+                body += &format!(
+                    "--BOUNDARY\r\nContent-Disposition: form-data; \
+                     name=\"file\"; filename=\"{}\"\r\n\r\n{}\r\n",
+                    name, content
+                );
+            }
+            body += "--BOUNDARY--\r\n";
+            test::TestRequest::post()
+                .uri("/upload")
+                .insert_header((
+                    header::CONTENT_TYPE,
+                    "multipart/form-data; boundary=BOUNDARY",
+                ))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, upload(&[("a.txt", "up")])).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let json: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        let digest = hex::encode(Sha512::digest("up"));
+        assert_eq!(json[0]["digest"], digest.as_str());
+        assert_eq!(json[0]["filename"], "a.txt");
+        assert!(server.digest.read().await.contains_key(&digest));
+
+        for (files, status) in [
+            (&[("b.txt", "12345"), ("c.txt", "6789")][..], 413),
+            (&[("../d.txt", "up")][..], 400),
+            (&[("a.txt", "again")][..], 409),
+        ] {
+            let resp = test::call_service(&app, upload(files)).await;
+            assert_eq!(resp.status().as_u16(), status, "{:?}", files);
+        }
+        let mut names: Vec<_> = std::fs::read_dir(upload_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["a.txt"]);
+
+        // the upload directory is outside the served root
+        let root = tempfile::tempdir().unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--upload-dir".as_ref(),
+            upload_dir.path().as_os_str(),
+            "--serve-root".as_ref(),
+            root.path().as_os_str(),
+            "--watch".as_ref(),
+            root.path().as_os_str(),
+        ]);
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(Server::new(cli).await.unwrap()))
+                .route("/upload", web::post().to(super::inner::do_upload_file)),
+        )
+        .await;
+        let resp = test::call_service(&app, upload(&[("e.txt", "up")])).await;
+        assert_eq!(resp.status().as_u16(), 403);
+        assert!(!upload_dir.path().join("e.txt").exists());
+    }
+
+    /// With credentials configured, enqueuing requires them, and downloads
+    /// only when configured to.
+    #[actix_web::test]
+    async fn test_basic_auth() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"auth").unwrap();
+        let other = tempfile::NamedTempFile::new().unwrap();
+        for (downloads, download_status) in [(false, 200), (true, 401)] {
+            let cli = Cli::parse_from([
+                "qrshare".as_ref(),
+                "--auth-user=user".as_ref(),
+                "--auth-pass=pass".as_ref(),
+                format!("--auth-downloads={}", downloads).as_ref(),
+                file.path().as_os_str(),
+            ]);
+            let server = Data::new(Server::new(cli).await.unwrap());
+            server
+                .clone()
+                .into_inner()
+                .process_digest(false)
+                .await
+                .unwrap();
+            let app = test::init_service(
+                App::new()
+                    .app_data(server)
+                    .service(super::get_sha512)
+                    .service(super::enqueue_file),
+            )
+            .await;
+
+            let enqueue = |authorization: Option<&str>| {
+                let mut req = test::TestRequest::post()
+                    .uri("/serve")
+                    .set_json(serde_json::json!({ "path": other.path() }));
+                if let Some(authorization) = authorization {
+                    req = req
+                        .insert_header((header::AUTHORIZATION, authorization));
+                }
+                req.to_request()
+            };
+            // "user:pass" and "user:wrong"
+            for (authorization, status) in [
+                (None, 401),
+                (Some("Basic dXNlcjp3cm9uZw=="), 401),
+                (Some("Bearer dXNlcjpwYXNz"), 401),
+                (Some("Basic dXNlcjpwYXNz"), 200),
+            ] {
+                let resp =
+                    test::call_service(&app, enqueue(authorization)).await;
+                assert_eq!(
+                    resp.status().as_u16(),
+                    status,
+                    "{:?}",
+                    authorization
+                );
+                if status == 401 {
+                    assert_eq!(
+                        resp.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+                        r#"Basic realm="qrshare", charset="UTF-8""#
+                    );
+                }
+            }
+
+            let uri =
+                format!("/sha512/?h={}", hex::encode(Sha512::digest("auth")));
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), download_status);
+        }
+    }
+
+    /// Without authentication, enqueuing is allowed from loopback peers only.
+    #[cfg(not(feature = "insecure"))]
+    #[actix_web::test]
+    async fn test_enqueue_loopback() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let other = tempfile::NamedTempFile::new().unwrap();
+        let server = server_with(&[file.path()]).await;
+        let app = test::init_service(
+            App::new().app_data(server).service(super::enqueue_file),
+        )
+        .await;
+
+        for (peer, status) in [
+            ("127.0.0.1:1234", 200),
+            ("[::1]:1234", 200),
+            ("192.0.2.1:1234", 403),
+            ("[2001:db8::1]:1234", 403),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/serve")
+                .peer_addr(peer.parse().unwrap())
+                .set_json(serde_json::json!({ "path": other.path() }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), status, "{}", peer);
+        }
+    }
+
+    /// Expired files are answered with 410 status and left out of the
+    /// listing, while files enqueued with a longer time-to-live remain.
+    #[actix_web::test]
+    async fn test_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = ["short", "long"].map(|name| dir.path().join(name));
+        for path in &paths {
+            std::fs::write(path, path.file_name().unwrap().to_str().unwrap())
+                .unwrap();
+        }
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--ttl=1s".as_ref(),
+            paths[0].as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        let ttl = Some("1h".parse().unwrap());
+        let enqueue = Json(Enqueue::Single {
+            path: paths[1].clone(),
+            ttl,
+            max_downloads: None,
+        });
+        super::inner::do_enqueue_file(server.clone(), enqueue)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(server.clone())
+                .service(super::get_sha512)
+                .service(super::list_files),
+        )
+        .await;
+
+        let get = |name: &str| {
+            let digest = hex::encode(Sha512::digest(name));
+            let uri = format!("/sha512/?h={}", digest);
+            test::TestRequest::get().uri(&uri).to_request()
+        };
+        let resp = test::call_service(&app, get("short")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        let req = test::TestRequest::get().uri("/list.html").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!body.contains("short") && body.contains("long"), "{}", body);
+
+        // expired files are answered with 410 status before and after they
+        // are purged
+        for purged in [1, 0] {
+            let resp = test::call_service(&app, get("short")).await;
+            assert_eq!(resp.status().as_u16(), 410);
+            assert_eq!(server.purge_expired().await, purged);
+        }
+        let resp = test::call_service(&app, get("long")).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(server.file_count().await, 1);
+    }
+
+    /// Files stop being served after their download limit, where failed
+    /// range requests are not counted.
+    #[actix_web::test]
+    async fn test_max_downloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = ["global", "own"].map(|name| dir.path().join(name));
+        for path in &paths {
+            std::fs::write(path, path.file_name().unwrap().to_str().unwrap())
+                .unwrap();
+        }
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--max-downloads=2".as_ref(),
+            paths[0].as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        let enqueue = Json(Enqueue::Single {
+            path: paths[1].clone(),
+            ttl: None,
+            max_downloads: Some(1),
+        });
+        super::inner::do_enqueue_file(server.clone(), enqueue)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(server.clone())
+                .service(super::get_sha512),
+        )
+        .await;
+
+        let get = |name: &str, range: Option<&str>| {
+            let digest = hex::encode(Sha512::digest(name));
+            let uri = format!("/sha512/?h={}", digest);
+            let req = test::TestRequest::get().uri(&uri);
+            match range {
+                Some(range) => req.insert_header((header::RANGE, range)),
+                None => req,
+            }
+            .to_request()
+        };
+        for (name, range, status) in [
+            ("global", None, 200),
+            ("global", Some("bytes=0-1"), 206),
+            ("global", None, 410),
+            ("own", Some("bytes=100-"), 416),
+            ("own", None, 200),
+            ("own", None, 410),
+        ] {
+            let resp = test::call_service(&app, get(name, range)).await;
+            assert_eq!(resp.status().as_u16(), status, "{} {:?}", name, range);
+        }
+        assert_eq!(server.file_count().await, 0);
+    }
+
+    /// Archives and previews honor the download limits too, and files in the
+    /// zip archive count as downloads.
+    #[actix_web::test]
+    async fn test_archive_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = ["a", "b"].map(|name| dir.path().join(name));
+        for path in &paths {
+            std::fs::write(path, path.file_name().unwrap().to_str().unwrap())
+                .unwrap();
+        }
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--max-downloads=1".as_ref(),
+            paths[0].as_os_str(),
+            paths[1].as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(server.clone())
+                .service(super::get_sha512)
+                .service(super::thumbnail)
+                .service(super::browse_entry)
+                .service(super::download_zip)
+                .service(super::download_tar),
+        )
+        .await;
+        let zip_names = |body: Bytes| {
+            let zip = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+            zip.file_names().map(str::to_owned).collect::<Vec<_>>()
+        };
+
+        let [a, b] = ["a", "b"].map(|name| hex::encode(Sha512::digest(name)));
+        let uri = format!("/sha512/?h={}", a);
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+
+        // the exhausted file is left out, and the other one is now exhausted
+        let req = test::TestRequest::get()
+            .uri("/download/all.zip")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(zip_names(test::read_body(resp).await), ["b"]);
+        let req = test::TestRequest::get()
+            .uri("/download/all.zip")
+            .to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        assert!(zip_names(body).is_empty());
+
+        for uri in [
+            format!("/download/tar?h={}", a),
+            format!("/download/tar?h={}", b),
+            format!("/thumb/{}", a),
+            format!("/browse/{}/entry", b),
+        ] {
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), 410, "{}", uri);
+        }
+    }
+
+    /// Non-ASCII file names are sent in both ASCII and UTF-8.
+    #[actix_web::test]
+    async fn test_content_disposition() {
+        let attachment = |name| {
+            let disposition = header::DispositionType::Attachment;
+            super::inner::content_disposition(disposition, name).to_string()
+        };
+        assert_eq!(attachment("a.txt"), r#"attachment; filename="a.txt""#);
+        assert_eq!(
+            attachment("测试.txt"),
+            r#"attachment; filename="__.txt"; filename*=UTF-8''%E6%B5%8B%E8%AF%95.txt"#
+        );
+    }
+
+    /// Downloads are typed by extension, or by content without one.
+    #[actix_web::test]
+    async fn test_content_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let png = dir.path().join("image");
+        std::fs::write(&png, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        let pdf = dir.path().join("doc.pdf");
+        std::fs::write(&pdf, b"not really").unwrap();
+        let server = server_with(&[&png, &pdf]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(server.clone())
+                .service(super::get_sha512),
+        )
+        .await;
+
+        for (path, expected) in [(&png, "image/png"), (&pdf, "application/pdf")]
+        {
+            let digest =
+                server.query_digest(path.canonicalize().unwrap()).await;
+            let req = test::TestRequest::get()
+                .uri(&format!("/sha512/?h={}", digest.unwrap()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                expected
+            );
+        }
+    }
+
+    /// The health checks report the server as not ready until the initially
+    /// queued files are hashed, where only the readiness check fails.
+    #[actix_web::test]
+    async fn test_health_not_ready() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let server = server_with(&[file.path()]).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(server)
+                .service(super::health)
+                .service(super::health_ready),
+        )
+        .await;
+
+        // the initially queued files are not hashed yet
+        for (uri, status) in [("/health", 200), ("/health/ready", 503)] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status().as_u16(), status, "{}", uri);
+            let body = test::read_body(resp).await;
+            let json: serde_json::Value =
+                serde_json::from_slice(&body).unwrap();
+            assert_eq!(json["ready"], false, "{}", uri);
+            assert_eq!(json["files"], 0, "{}", uri);
+        }
+    }
+
+    /// With `--metrics`, downloads and responses are counted and exported at
+    /// `/metrics`, which is otherwise not found.
+    #[actix_web::test]
+    async fn test_metrics() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"metrics").unwrap();
+        for enabled in [false, true] {
+            let cli = Cli::parse_from([
+                "qrshare".as_ref(),
+                format!("--metrics={}", enabled).as_ref(),
+                file.path().as_os_str(),
+            ]);
+            let server = Data::new(Server::new(cli).await.unwrap());
+            server
+                .clone()
+                .into_inner()
+                .process_digest(false)
+                .await
+                .unwrap();
+            let app = test::init_service(
+                App::new()
+                    .wrap(Condition::new(
+                        enabled,
+                        ResponseMetrics::new(server.metrics.clone()),
+                    ))
+                    .app_data(server)
+                    .service(super::get_sha512)
+                    .service(super::show_metrics),
+            )
+            .await;
+
+            let digest = hex::encode(Sha512::digest("metrics"));
+            for uri in [format!("/sha512/?h={}", digest), "/missing".into()] {
+                let req = test::TestRequest::get().uri(&uri).to_request();
+                test::call_service(&app, req).await;
+            }
+            let req = test::TestRequest::get().uri("/metrics").to_request();
+            let resp = test::call_service(&app, req).await;
+            if !enabled {
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+                continue;
+            }
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = test::read_body(resp).await;
+            let text = std::str::from_utf8(&body).unwrap();
+            for line in [
+                "qrshare_downloads_total 1",
+                "qrshare_served_bytes_total 7",
+                "qrshare_files 1",
+                "qrshare_responses_total{status=\"200\"} 1",
+                "qrshare_responses_total{status=\"404\"} 1",
+            ] {
+                assert!(text.lines().any(|l| l == line), "{}\n{}", line, text);
+            }
+        }
+    }
+
+    /// Errors are in JSON or HTML when the request prefers it, and otherwise
+    /// in plain text.
+    #[actix_web::test]
+    async fn test_error_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, b"errors").unwrap();
+        let server = server_with(&[&path]).await;
+        let app = test::init_service(
+            App::new()
+                .wrap(ErrorPages)
+                .app_data(server)
+                .service(super::get_sha512),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/sha512/?h=zz")
+            .insert_header((header::ACCEPT, "text/html,application/json;q=0.9"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/html; charset=utf-8"
+        );
+        let body = test::read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("<h1>404 Not Found</h1>"), "{}", body);
+        assert!(body.contains(r#"<a href="/list.html">"#), "{}", body);
+
+        for (accept, json) in [
+            (None, false),
+            (Some("text/plain"), false),
+            (Some("application/json, text/plain, */*"), true),
+        ] {
+            let mut req = test::TestRequest::get().uri("/sha512/?h=zz");
+            if let Some(accept) = accept {
+                req = req.insert_header((header::ACCEPT, accept));
+            }
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            let body = test::read_body(resp).await;
+            if !json {
+                assert_eq!(body, "404 Not Found");
+                continue;
+            }
+            let json: serde_json::Value =
+                serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                json,
+                serde_json::json!({
+                    "error": "HttpResponse",
+                    "message": "404 Not Found",
+                    "status": 404,
+                })
+            );
+        }
+    }
+
+    /// The listing is compressed as accepted, but downloaded files are not.
+    #[actix_web::test]
+    async fn test_compress() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, "compressible ".repeat(1000)).unwrap();
+        let server = server_with(&[&path]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let digest = server.query_digest(path.canonicalize().unwrap()).await;
+        let app = test::init_service(
+            App::new()
+                .wrap(Compress::default())
+                .app_data(server)
+                .service(super::get_sha512)
+                .service(super::list_files),
+        )
+        .await;
+
+        let download = format!("/sha512/?h={}", digest.unwrap());
+        for (uri, encoding) in [("/list.html", "gzip"), (&download, "identity")]
+        {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((header::ACCEPT_ENCODING, "gzip"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_ENCODING).unwrap(),
+                encoding,
+                "{}",
+                uri
+            );
+        }
+    }
+
+    /// Cross-origin requests from allowed origins to the API routes, and their
+    /// preflight requests, are answered with CORS headers.
+    #[actix_web::test]
+    async fn test_cors() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--cors-origin=https://example.com".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        let app = test::init_service(
+            App::new()
+                .wrap(Cors)
+                .app_data(server)
+                .service(super::list_json)
+                .service(super::list_files),
+        )
+        .await;
+
+        for (origin, uri, allowed) in [
+            ("https://example.com", "/list.json", true),
+            ("https://example.com", "/list.html", false),
+            ("https://example.org", "/list.json", false),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .insert_header((header::ORIGIN, origin))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let allow_origin =
+                resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+            assert_eq!(allow_origin.is_some(), allowed, "{} {}", origin, uri);
+        }
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/serve")
+            .insert_header((header::ORIGIN, "https://example.com"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .insert_header((
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "authorization, content-type",
+            ))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let headers = resp.headers();
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "https://example.com"
+        );
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap(),
+            "authorization, content-type"
+        );
+    }
+
+    /// Preflight requests from other origins are not answered, and those from
+    /// allowed origins are answered without credentials, even when the route
+    /// requires authentication.
+    #[actix_web::test]
+    async fn test_cors_preflight() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        for origins in ["https://example.com", "*"] {
+            let cli = Cli::parse_from([
+                "qrshare".as_ref(),
+                format!("--cors-origin={}", origins).as_ref(),
+                "--auth-user=user".as_ref(),
+                "--auth-pass=pass".as_ref(),
+                file.path().as_os_str(),
+            ]);
+            let server = Data::new(Server::new(cli).await.unwrap());
+            let app = test::init_service(
+                App::new()
+                    .wrap(Cors)
+                    .app_data(server)
+                    .service(super::enqueue_file),
+            )
+            .await;
+
+            for (origin, allowed) in [
+                ("https://example.com", true),
+                ("https://example.org", origins == "*"),
+                ("null", origins == "*"),
+            ] {
+                let req = test::TestRequest::default()
+                    .method(Method::OPTIONS)
+                    .uri("/serve")
+                    .insert_header((header::ORIGIN, origin))
+                    .insert_header((
+                        header::ACCESS_CONTROL_REQUEST_METHOD,
+                        "POST",
+                    ))
+                    .insert_header((
+                        header::ACCESS_CONTROL_REQUEST_HEADERS,
+                        "authorization",
+                    ))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                let headers = resp.headers();
+                assert!(headers
+                    .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+                    .is_none());
+                if !allowed {
+                    assert_ne!(resp.status(), StatusCode::NO_CONTENT);
+                    assert!(
+                        headers
+                            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                            .is_none(),
+                        "{} {}",
+                        origins,
+                        origin
+                    );
+                    continue;
+                }
+                assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+                let expected = if origins == "*" { "*" } else { origin };
+                assert_eq!(
+                    headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+                    expected
+                );
+                assert_eq!(
+                    headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap(),
+                    "authorization"
+                );
+            }
+
+            // the actual request still requires the credentials
+            let req = test::TestRequest::post()
+                .uri("/serve")
+                .insert_header((header::ORIGIN, "https://example.com"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            assert!(resp
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .is_some());
+        }
+    }
+
+    /// The JSON listing holds the URLs and sizes of served files.
+    #[actix_web::test]
+    async fn test_list_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, b"json").unwrap();
+        let server = server_with(&[&path]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let base = server.base_url();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::list_json),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/list.json").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/json"
+        );
+        let body = test::read_body(resp).await;
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let digest = hex::encode(Sha512::digest(b"json"));
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "digest": digest,
+                "filename": "a.txt",
+                "download_url": format!("{}/sha512/?h={}", base, digest),
+                "qr_url": format!("{}/qr/sha512/?h={}", base, digest),
+                "size": 4,
+            }])
+        );
+    }
+
+    /// Signed URLs in the JSON listing download their file, unless tampered
+    /// with.
+    #[actix_web::test]
+    async fn test_signed_download() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"signed").unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--secret=s3cret".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let base = server.base_url();
+        let app = test::init_service(
+            App::new()
+                .app_data(server)
+                .service(super::get_signed)
+                .service(super::list_json),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/list.json").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let url = json[0]["signed_url"].as_str().unwrap();
+        let uri = url.strip_prefix(&base).unwrap();
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, "signed");
+
+        let digest = hex::encode(Sha512::digest(b"signed"));
+        let uri =
+            format!("/signed/sha512/?h={}&exp={}&sig=x", digest, u64::MAX);
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    /// The listing shows file sizes, or a dash when unreadable.
+    #[actix_web::test]
+    async fn test_list_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = ["a", "b"].map(|name| dir.path().join(name));
+        std::fs::write(&paths[0], [0; 2048]).unwrap();
+        std::fs::write(&paths[1], b"gone").unwrap();
+        let server = server_with(&[&paths[0], &paths[1]]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        std::fs::remove_file(&paths[1]).unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::list_files),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/list.html").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("<th>modified</th>"), "{}", body);
+        assert!(body.contains("<td>2.0 KiB</td>"), "{}", body);
+        assert!(body.contains("<td>-</td>"), "{}", body);
+    }
+
+    /// QR codes are embedded in the listing with `--inline-qr`.
+    #[actix_web::test]
+    async fn test_inline_qr() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--inline-qr=true".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::list_files),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/list.html").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(
+            body.contains(r#"<img src="data:image/svg+xml;base64,"#),
+            "{}",
+            body
+        );
+    }
+
+    /// The listing is sorted by the requested column and order.
+    #[actix_web::test]
+    async fn test_list_sort() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = [("b.txt", "bbb"), ("a.txt", "a"), ("c.txt", "cc")].map(
+            |(name, content)| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, content).unwrap();
+                path
+            },
+        );
+        let server =
+            server_with(&paths.iter().map(|p| p.as_path()).collect::<Vec<_>>())
+                .await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::list_files),
+        )
+        .await;
+
+        for (query, expected) in [
+            ("", ["a.txt", "b.txt", "c.txt"]),
+            ("?sort=size", ["a.txt", "c.txt", "b.txt"]),
+            ("?sort=size&order=desc", ["b.txt", "c.txt", "a.txt"]),
+        ] {
+            let uri = format!("/list.html{}", query);
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            let body = test::read_body(resp).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            let found = expected.map(|name| body.find(name).unwrap());
+            assert!(found.windows(2).all(|w| w[0] < w[1]), "{}", query);
+        }
+    }
+
+    /// File names are escaped in the listing.
+    #[actix_web::test]
+    async fn test_list_escape() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("foo<bar>&baz.txt");
+        std::fs::write(&path, b"escape").unwrap();
+        let server = server_with(&[&path]).await;
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new().app_data(server).service(super::list_files),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/list.html").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("foo&lt;bar&gt;&amp;baz.txt"), "{}", body);
+        assert!(!body.contains("<bar>"), "{}", body);
+    }
+
+    /// The listing is a styled table by default, and preformatted with the
+    /// plain theme, with the configured title and CSS.
+    #[actix_web::test]
+    async fn test_list_theme() {
+        let dir = tempfile::tempdir().unwrap();
+        let css = dir.path().join("style.css");
+        std::fs::write(&css, "body { color: teal; }").unwrap();
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, b"theme").unwrap();
+
+        for (theme, plain) in [("responsive", false), ("plain", true)] {
+            let cli = Cli::parse_from([
+                "qrshare".to_owned(),
+                format!("--theme={}", theme),
+                format!("--css={}", css.display()),
+                "--list-title=Shared <files>".to_owned(),
+                file.display().to_string(),
+            ]);
+            let server = Data::new(Server::new(cli).await.unwrap());
+            let app = test::init_service(
+                App::new().app_data(server).service(super::list_files),
+            )
+            .await;
+
+            let req = test::TestRequest::get().uri("/list.html").to_request();
+            let body =
+                test::read_body(test::call_service(&app, req).await).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(
+                body.contains("<title>Shared &lt;files&gt;</title>"),
+                "{}",
+                body
+            );
+            assert!(body.contains("body { color: teal; }"), "{}", body);
+            assert_eq!(body.contains("<pre>"), plain, "{}", body);
+            assert_eq!(body.contains("overflow-x"), !plain, "{}", body);
+        }
+
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--css=/nonexistent.css".as_ref(),
+            file.as_os_str(),
+        ]);
+        assert!(Server::new(cli).await.is_err());
+    }
+
+    /// Thumbnail widths are rounded up to a few cached sizes, and generating
+    /// them is rate-limited with QR code rendering.
+    #[actix_web::test]
+    async fn test_thumbnail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("icon.png");
+        std::fs::write(&path, super::inner::FAVICON).unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--qr-rate-limit=1".as_ref(),
+            path.as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        server
+            .clone()
+            .into_inner()
+            .process_digest(false)
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(server.clone())
+                .service(super::thumbnail),
+        )
+        .await;
+
+        let digest = hex::encode(Sha512::digest(super::inner::FAVICON));
+        let get = |width: u32| {
+            test::TestRequest::get()
+                .uri(&format!("/thumb/{}?w={}", digest, width))
+                .peer_addr(([10, 0, 0, 1], 12345).into())
+                .to_request()
+        };
+        // both are served from the 128-pixel thumbnail
+        for width in [100, 120] {
+            let resp = test::call_service(&app, get(width)).await;
+            assert_eq!(resp.status().as_u16(), 200, "{}", width);
+        }
+        let keys: Vec<_> =
+            server.thumbnails.read().await.keys().cloned().collect();
+        assert_eq!(keys, [(digest.clone(), 128)]);
+
+        let resp = test::call_service(&app, get(4096)).await;
+        assert_eq!(resp.status().as_u16(), 429);
+    }
+
+    /// The favicon is the configured file with its own type, or the bundled
+    /// icon otherwise.
+    #[actix_web::test]
+    async fn test_favicon() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, b"favicon").unwrap();
+        let icon = dir.path().join("icon.ico");
+        std::fs::write(&icon, b"\0\0\x01\0").unwrap();
+
+        let default = server_with(&[&file]).await;
+        let cli = Cli::parse_from([
+            "qrshare".to_owned(),
+            format!("--favicon={}", icon.display()),
+            file.display().to_string(),
+        ]);
+        let custom = Data::new(Server::new(cli).await.unwrap());
+        for (server, content_type, body) in [
+            (default, "image/png", super::inner::FAVICON),
+            (custom, "image/x-icon", b"\0\0\x01\0".as_ref()),
+        ] {
+            let app = test::init_service(
+                App::new().app_data(server).service(super::favicon),
+            )
+            .await;
+            let req = test::TestRequest::get().uri("/favicon.ico").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                content_type
+            );
+            assert_eq!(test::read_body(resp).await, body);
+        }
+
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--favicon=/nonexistent.ico".as_ref(),
+            file.as_os_str(),
+        ]);
+        assert!(Server::new(cli).await.is_err());
+    }
+
+    /// A single served directory uses its index file as the listing, and
+    /// falls back to the generated listing without one.
+    #[actix_web::test]
+    async fn test_index_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--index-file=index.html".as_ref(),
+            dir.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        let app = test::init_service(
+            App::new().app_data(server).service(super::list_files),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/list.html").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        assert!(String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains("QR Share: Files"));
+
+        std::fs::write(dir.path().join("index.html"), "my index").unwrap();
+        let req = test::TestRequest::get().uri("/list.html").to_request();
+        let body = test::read_body(test::call_service(&app, req).await).await;
+        assert_eq!(body, "my index");
+    }
+
+    /// The QR code payload is transformed, as shown in the caption.
+    #[actix_web::test]
+    async fn test_qr_transform() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--qr-caption-url=true".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let mut server = Server::new(cli).await.unwrap();
+        server.qr_transform =
+            PayloadTransform::new(|url| format!("app+{}", url));
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(server))
+                .service(super::show_qr),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/qr/sha512/?h=abc")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let body = test::read_body(resp).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(">app+http://"), "{}", body);
+    }
+
+    /// The QR code format follows the `fmt` query parameter, then the
+    /// `Accept` header, then the configured image format.
+    #[actix_web::test]
+    async fn test_qr_format_from_accept() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--qr-format-from-accept=true".as_ref(),
+            "--image=png".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        let app = test::init_service(
+            App::new().app_data(server).service(super::show_qr),
+        )
+        .await;
+
+        for (query, accept, expected) in [
+            ("", "image/svg+xml", "image/svg+xml"),
+            ("", "image/png;q=0.5, image/svg+xml", "image/svg+xml"),
+            ("", "image/*", "image/png"),
+            ("&fmt=svg", "image/png", "image/svg+xml"),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(&format!("/qr/sha512/?h=abc{}", query))
+                .insert_header((header::ACCEPT, accept))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            let headers = resp.headers();
+            assert_eq!(headers.get(header::CONTENT_TYPE).unwrap(), expected);
+            assert_eq!(headers.get(header::VARY).unwrap(), "Accept");
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/qr/sha512/?h=abc&fmt=gif")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    /// Served QR codes default to the configured format.
+    #[actix_web::test]
+    async fn test_qr_default_format() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let cli = Cli::parse_from([
+            "qrshare".as_ref(),
+            "--image=png".as_ref(),
+            file.path().as_os_str(),
+        ]);
+        let server = Data::new(Server::new(cli).await.unwrap());
+        let app = test::init_service(
+            App::new().app_data(server).service(super::show_qr),
+        )
+        .await;
+
+        for (query, expected) in
+            [("", "image/png"), ("&format=svg", "image/svg+xml")]
+        {
+            let req = test::TestRequest::get()
+                .uri(&format!("/qr/sha512/?h=abc{}", query))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                expected
+            );
+        }
+    }
+
+    /// Downloading a file larger than 4 GiB must not overflow any length or
+    /// offset on 32-bit targets.  The file is sparse, so it is cheap to make.
+    #[actix_web::test]
+    async fn test_download_large_file() {
+        const SIZE: u64 = (5 << 30) + 4;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.as_file_mut().set_len(SIZE - 4).unwrap();
+        file.as_file_mut().seek(SeekFrom::End(0)).unwrap();
+        file.write_all(b"tail").unwrap();
+
+        let server = server_with(&[file.path()]).await;
+        server
+            .digest
+            .write()
+            .await
+            .insert("big".into(), file.path().into());
+        let app = test::init_service(
+            App::new().app_data(server).service(super::get_sha512),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/sha512/?h=big").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(resp.response().body().size(), BodySize::Sized(SIZE));
+
+        let req = test::TestRequest::get()
+            .uri("/sha512/?h=big")
+            .insert_header((header::RANGE, format!("bytes={}-", SIZE - 4)))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 206);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_RANGE).unwrap(),
+            &format!("bytes {}-{}/{}", SIZE - 4, SIZE - 1, SIZE)
+        );
+        assert_eq!(test::read_body(resp).await, Bytes::from_static(b"tail"));
     }
 }
@@ -0,0 +1,400 @@
+//! Integration tests, which run the `qrshare` binary and exercise its HTTP
+//! endpoints.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    process::{Command, Stdio},
+    thread::sleep,
+    time::Duration,
+};
+
+use hyper::{body::Bytes, header, Body, Client, HeaderMap, Method, Request};
+use sha2::{Digest, Sha512};
+
+/// A running server, killed when dropped.
+struct Server {
+    child: std::process::Child,
+    base: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Server {
+    /// Serve `files` on an available local port, and wait until the files are
+    /// hashed.
+    fn spawn(files: &[&Path]) -> Self {
+        Self::spawn_with(&[], files)
+    }
+
+    /// Like [`Server::spawn`], with additional command-line arguments.
+    fn spawn_with(args: &[&str], files: &[&Path]) -> Self {
+        let server = Self::spawn_unready(args, files);
+        let base_path = args
+            .iter()
+            .find_map(|arg| arg.strip_prefix("--base-path="))
+            .unwrap_or_default();
+        let ready = format!("{}/health/ready", base_path.trim_end_matches('/'));
+
+        for _ in 0..100 {
+            if server.is_ready(&ready) {
+                return server;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        panic!("server on {} did not become ready", server.base);
+    }
+
+    /// Like [`Server::spawn_with`], but only wait until the port accepts
+    /// connections, possibly before the files are hashed.
+    fn spawn_unready(args: &[&str], files: &[&Path]) -> Self {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_qrshare"))
+            .args(["-H127.0.0.1", "-p", &port.to_string()])
+            .args(args)
+            .args(files)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = Self { child, base: format!("http://127.0.0.1:{}", port) };
+
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return server;
+            }
+            sleep(Duration::from_millis(100));
+        }
+        panic!("server did not start on port {}", port);
+    }
+
+    /// Whether `path` responds with 200 status, without an async runtime.
+    fn is_ready(&self, path: &str) -> bool {
+        let addr = self.base.trim_start_matches("http://");
+        let mut stream = match TcpStream::connect(addr) {
+            Ok(stream) => stream,
+            Err(_) => return false,
+        };
+        let request = format!("GET {} HTTP/1.0\r\n\r\n", path);
+        let mut response = String::new();
+        stream.write_all(request.as_bytes()).is_ok()
+            && stream.read_to_string(&mut response).is_ok()
+            && response.starts_with("HTTP/1.0 200")
+    }
+
+    /// Send a request with an optional JSON body, and return the response
+    /// status, headers, and body.
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        json: Option<&str>,
+    ) -> (u16, HeaderMap, Bytes) {
+        let req = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.base, path))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(json.map_or_else(Body::empty, |json| json.to_owned().into()))
+            .unwrap();
+        let resp = Client::new().request(req).await.unwrap();
+        let (parts, body) = resp.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap();
+        (parts.status.as_u16(), parts.headers, body)
+    }
+
+    async fn get(&self, path: &str) -> (u16, HeaderMap, Bytes) {
+        self.request(Method::GET, path, None).await
+    }
+}
+
+/// Create a temporary directory holding a file named `name` with `content`.
+fn temp_file(name: &str, content: &[u8]) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join(name), content).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn test_download() {
+    let dir = temp_file("hello.txt", b"hello");
+    let server = Server::spawn(&[&dir.path().join("hello.txt")]);
+    let digest = hex::encode(Sha512::digest(b"hello"));
+
+    let (status, headers, body) =
+        server.get(&format!("/sha512/?h={}", digest)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body, "hello");
+    assert_eq!(
+        headers[header::CONTENT_DISPOSITION],
+        r#"inline; filename="hello.txt""#
+    );
+    assert_eq!(headers[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+}
+
+#[tokio::test]
+async fn test_force_download() {
+    let dir = temp_file("hello.txt", b"hello");
+    let server = Server::spawn_with(
+        &["--force-download=true"],
+        &[&dir.path().join("hello.txt")],
+    );
+    let digest = hex::encode(Sha512::digest(b"hello"));
+
+    let (status, headers, _) =
+        server.get(&format!("/sha512/?h={}", digest)).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        headers[header::CONTENT_DISPOSITION],
+        r#"attachment; filename="hello.txt""#
+    );
+    assert_eq!(headers[header::CONTENT_TYPE], "application/octet-stream");
+}
+
+#[tokio::test]
+async fn test_disposition() {
+    let dir = temp_file("hello.txt", b"hello");
+    let server = Server::spawn_with(
+        &["--force-download=true"],
+        &[&dir.path().join("hello.txt")],
+    );
+    let digest = hex::encode(Sha512::digest(b"hello"));
+
+    for (disposition, expected, content_type) in [
+        ("inline", "inline", "text/plain; charset=utf-8"),
+        ("attachment", "attachment", "application/octet-stream"),
+    ] {
+        let path = format!("/sha512/?h={}&disposition={}", digest, disposition);
+        let (status, headers, _) = server.get(&path).await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            headers[header::CONTENT_DISPOSITION],
+            format!(r#"{}; filename="hello.txt""#, expected)
+        );
+        assert_eq!(headers[header::CONTENT_TYPE], content_type);
+    }
+
+    let path = format!("/sha512/?h={}&disposition=other", digest);
+    let (status, ..) = server.get(&path).await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn test_unknown_digest() {
+    let dir = temp_file("hello.txt", b"hello");
+    let server = Server::spawn(&[&dir.path().join("hello.txt")]);
+
+    let (status, ..) = server.get("/sha512/?h=0123456789").await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn test_list_files() {
+    let dir = temp_file("listed.txt", b"listed");
+    let server = Server::spawn(&[&dir.path().join("listed.txt")]);
+
+    let (status, headers, body) = server.get("/list.html").await;
+    assert_eq!(status, 200);
+    assert!(headers[header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("listed.txt"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_show_qr() {
+    let dir = temp_file("hello.txt", b"hello");
+    let server = Server::spawn(&[&dir.path().join("hello.txt")]);
+    let digest = hex::encode(Sha512::digest(b"hello"));
+
+    let (status, headers, body) =
+        server.get(&format!("/qr/sha512/?h={}", digest)).await;
+    assert_eq!(status, 200);
+    assert_eq!(headers[header::CONTENT_TYPE], "image/svg+xml");
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.starts_with("<?xml"), "{}", body);
+    assert!(body.trim_end().ends_with("</svg>"), "{}", body);
+}
+
+/// Loopback clients may enqueue files, even without the "insecure" feature.
+#[tokio::test]
+async fn test_serve_loopback() {
+    let dir = temp_file("hello.txt", b"hello");
+    std::fs::write(dir.path().join("later.txt"), b"later").unwrap();
+    let server = Server::spawn(&[&dir.path().join("hello.txt")]);
+
+    let json = serde_json::json!({ "path": dir.path().join("later.txt") });
+    let json = json.to_string();
+    let (status, ..) =
+        server.request(Method::POST, "/serve", Some(&json)).await;
+    assert_eq!(status, 200);
+
+    let (_, _, body) = server.get("/list.html").await;
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("later.txt"), "{}", body);
+}
+
+#[tokio::test]
+async fn test_base_path() {
+    let dir = temp_file("hello.txt", b"hello");
+    let server = Server::spawn_with(
+        &["--base-path=/share/"],
+        &[&dir.path().join("hello.txt")],
+    );
+    let digest = hex::encode(Sha512::digest(b"hello"));
+
+    let (status, _, body) = server.get("/share/list.html").await;
+    assert_eq!(status, 200);
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    let url = format!("{}/share/sha512/?h={}", server.base, digest);
+    assert!(body.contains(&url), "{}", body);
+
+    let (status, ..) =
+        server.get(&format!("/share/sha512/?h={}", digest)).await;
+    assert_eq!(status, 200);
+
+    // routes outside the base path redirect to the listing
+    let (status, headers, _) = server.get("/list.html").await;
+    assert_eq!(status, 308);
+    assert_eq!(headers[header::LOCATION], "/share/list.html");
+}
+
+/// The health checks report a ready server, without authentication.
+#[tokio::test]
+async fn test_health() {
+    let dir = temp_file("hello.txt", b"hello");
+    let server = Server::spawn_with(
+        &["--auth-user=user", "--auth-pass=pass", "--auth-downloads=true"],
+        &[&dir.path().join("hello.txt")],
+    );
+
+    for path in ["/health", "/health/ready"] {
+        let (status, _, body) = server.get(path).await;
+        assert_eq!(status, 200, "{}", path);
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["ready"], true, "{}", path);
+        assert_eq!(json["files"], 1, "{}", path);
+    }
+}
+
+/// The server answers health checks while the queued files are hashed, and
+/// reports readiness once they are.
+#[cfg(debug_assertions)]
+#[tokio::test]
+async fn test_health_hashing() {
+    let dir = temp_file("hello.txt", b"hello");
+    let server = Server::spawn_unready(
+        &["--debug-hash-delay=2000"],
+        &[&dir.path().join("hello.txt")],
+    );
+
+    let (status, _, body) = server.get("/health/ready").await;
+    assert_eq!(status, 503);
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["ready"], false);
+    let (status, _, _) = server.get("/health").await;
+    assert_eq!(status, 200);
+
+    for _ in 0..50 {
+        let (status, _, body) = server.get("/health/ready").await;
+        if status == 200 {
+            let json: serde_json::Value =
+                serde_json::from_slice(&body).unwrap();
+            assert_eq!(json["files"], 1);
+            return;
+        }
+        assert_eq!(status, 503);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("server did not become ready");
+}
+
+/// The configuration is only served with authentication configured, and then
+/// only with credentials, even to loopback peers.
+#[tokio::test]
+async fn test_config() {
+    let dir = temp_file("hello.txt", b"hello");
+    let file = dir.path().join("hello.txt");
+    let server = Server::spawn(&[&file]);
+    // unknown routes redirect to the listing
+    assert_eq!(server.get("/config").await.0, 308);
+
+    let server =
+        Server::spawn_with(&["--auth-user=user", "--auth-pass=pass"], &[&file]);
+    assert_eq!(server.get("/config").await.0, 401);
+    let req = Request::get(format!("{}/config", server.base))
+        .header(
+            header::AUTHORIZATION,
+            format!("Basic {}", base64::encode("user:pass")),
+        )
+        .body(Body::empty())
+        .unwrap();
+    let resp = Client::new().request(req).await.unwrap();
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_ne!(json["auth_pass"], "pass");
+}
+
+/// With authentication required for downloads, every route revealing the
+/// served files or their metadata requires credentials.
+#[tokio::test]
+async fn test_auth_downloads() {
+    let dir = temp_file("hello.txt", b"hello");
+    let server = Server::spawn_with(
+        &["--auth-user=user", "--auth-pass=pass", "--auth-downloads=true"],
+        &[&dir.path().join("hello.txt")],
+    );
+    let digest = hex::encode(Sha512::digest(b"hello"));
+
+    for path in [
+        format!("/sha512/?h={}", digest),
+        format!("/qr/sha512/?h={}", digest),
+        format!("/thumb/{}", digest),
+        format!("/browse/{}/", digest),
+        format!("/browse/{}/entry", digest),
+        format!("/download/tar?h={}", digest),
+        "/download/all.zip".to_owned(),
+        "/list.html".to_owned(),
+        "/list.json".to_owned(),
+        "/feed.xml".to_owned(),
+    ] {
+        assert_eq!(server.get(&path).await.0, 401, "{}", path);
+
+        let req = Request::get(format!("{}{}", server.base, path))
+            .header(
+                header::AUTHORIZATION,
+                format!("Basic {}", base64::encode("user:pass")),
+            )
+            .body(Body::empty())
+            .unwrap();
+        let resp = Client::new().request(req).await.unwrap();
+        assert_ne!(resp.status().as_u16(), 401, "{}", path);
+    }
+}
+
+/// The printed configuration redacts secrets.
+#[test]
+fn test_print_config() {
+    let output = Command::new(env!("CARGO_BIN_EXE_qrshare"))
+        .args(["--print-config", "--auth-user=user", "--auth-pass=pass"])
+        .args(["--secret=signing-key"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let config = String::from_utf8(output.stdout).unwrap();
+    assert!(config.contains(r#"auth_user = "user""#), "{}", config);
+    assert!(!config.contains(r#""pass""#), "{}", config);
+    assert!(!config.contains("signing-key"), "{}", config);
+}